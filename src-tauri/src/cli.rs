@@ -0,0 +1,41 @@
+//! Command-line modes of the app binary. With no subcommand it starts the app as usual.
+
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(name = "better-dns-jumper", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum CliCommand {
+    /// Restore DNS if the given app process ends without a clean exit. Started by the
+    /// app itself; see `watchdog.rs`.
+    #[command(hide = true)]
+    Watchdog {
+        #[arg(long)]
+        parent_pid: u32,
+    },
+}
+
+impl Cli {
+    /// The subcommand, if the arguments name one. Anything unrecognized starts the app:
+    /// the autostart and single-instance plugins pass arguments of their own.
+    pub fn command() -> Option<CliCommand> {
+        Cli::try_parse().ok().and_then(|cli| cli.command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_watchdog_subcommand() {
+        let cli = Cli::try_parse_from(["app", "watchdog", "--parent-pid", "4242"]).unwrap();
+        assert_eq!(cli.command, Some(CliCommand::Watchdog { parent_pid: 4242 }));
+        assert_eq!(Cli::try_parse_from(["app"]).unwrap().command, None);
+    }
+}
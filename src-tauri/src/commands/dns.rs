@@ -1,18 +1,33 @@
+use crate::dns::dns_drift::{self, DriftAction, DRIFT_ACTION_SETTING};
+use crate::dns::dns_events::{
+    DefaultRouteFollowed, DnsDrift, DEFAULT_ROUTE_FOLLOWED_EVENT, DNS_DRIFT_DETECTED_EVENT,
+};
 use crate::dns::dns_log_store::DnsLogStore;
+use crate::dns::dns_profiles::{ActiveProfile, ActiveProfileInfo};
 use crate::dns::dns_rules::DnsRules;
-use crate::dns::dns_types::{DnsQueryLog, DnsRule};
-use crate::dns::{dns_server, dns_utils};
+use crate::dns::dns_types::{DnsQueryLog, DnsRule, ProxyPolicy, ProxyStatus};
+use crate::dns::dns_upstream::{ProfileCanary, UpstreamOptions};
+use crate::dns::{dns_server, dns_tls, dns_utils};
 use crate::error::{AppError, AppResult, LogErr};
+use crate::platform::{
+    self, ChangeSource, DnsConfigurator, DnsOptions, Family, NetworkChange, NetworkChanges,
+};
 use crate::types::ServerTestResult;
-use crate::win;
-use crate::win::dns_settings::Family;
 use crate::AppState;
+use hickory_proto::rr::RecordType;
 use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use tauri::{Emitter, Manager};
 use tauri_plugin_store::StoreExt;
 use tokio::sync::{Mutex, RwLock};
-use tokio::time::{self, Duration, Instant};
+use tokio::time::{self, Instant};
+
+/// How long the server test waits for an entry without resolver tuning of its own.
+const DEFAULT_TEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn test_server(
@@ -20,38 +35,26 @@ pub async fn test_server(
     domain: String,
     bootstrap_ip: Option<String>,
     bootstrap_resolver: Option<dns_server::BootstrapResolverInfo>,
+    upstream_options: Option<UpstreamOptions>,
 ) -> AppResult<ServerTestResult> {
-    use hickory_proto::xfer::Protocol;
-    use std::net::SocketAddr;
+    let options = upstream_options.unwrap_or_else(|| UpstreamOptions {
+        timeout_ms: DEFAULT_TEST_TIMEOUT.as_millis() as u64,
+        ..UpstreamOptions::default()
+    });
+    options.check().map_err(AppError::invalid)?;
 
     // Try to detect if this is a plain IP address (plain DNS / UDP)
     let is_plain_ip = server.parse::<std::net::IpAddr>().is_ok();
 
-    let resolver = if is_plain_ip {
+    let upstream = if is_plain_ip {
         // Plain DNS over UDP
         let ip: std::net::IpAddr = server
             .parse()
             .map_err(|_| AppError::invalid(format!("\"{}\" is not a valid IP address.", server)))?;
-        let socket_addr = SocketAddr::new(ip, 53);
-
-        let mut config = hickory_resolver::config::ResolverConfig::new();
-        config.add_name_server(hickory_resolver::config::NameServerConfig {
-            socket_addr,
-            protocol: Protocol::Udp,
-            tls_dns_name: None,
-            http_endpoint: None,
-            bind_addr: None,
-            trust_negative_responses: true,
-        });
-
-        let opts = hickory_resolver::config::ResolverOpts::default();
-        let connector = hickory_resolver::name_server::GenericConnector::<
-            hickory_proto::runtime::TokioRuntimeProvider,
-        >::default();
-
-        hickory_resolver::Resolver::builder_with_config(config, connector)
-            .with_options(opts)
-            .build()
+        dns_server::Upstream::Hickory(Box::new(dns_server::DnsServer::create_plain_resolver(
+            &[ip],
+            options.resolver_opts(),
+        )))
     } else {
         // URL-based protocol (https://, tls://, quic://, h3://)
         let (resolver_domain, port, proto, http_endpoint) =
@@ -62,29 +65,36 @@ pub async fn test_server(
             bootstrap_ip
         } else if let Some(ref resolver_info) = bootstrap_resolver {
             Some(
-                dns_server::DnsServer::resolve_via_bootstrap(resolver_info, &resolver_domain)
-                    .await?,
+                dns_server::DnsServer::resolve_via_bootstrap(
+                    resolver_info,
+                    &resolver_domain,
+                    &options,
+                )
+                .await?,
             )
         } else {
             None
         };
 
-        dns_server::DnsServer::create_dns_resolver(
+        dns_server::DnsServer::create_upstream(
             resolver_domain,
             port,
             proto,
             http_endpoint,
             effective_ip,
+            &options,
         )
         .map_err(|e| {
             AppError::Resolver(format!("could not build a resolver for {}: {}", server, e))
         })?
     };
 
-    let timeout = Duration::from_secs(3);
+    // The server gets exactly the time its profile allows a real query: every attempt
+    // the resolver makes — the first try and each retry — with its own timeout.
+    let timeout = options.timeout() * (options.attempts as u32 + 1);
 
     let start = Instant::now();
-    let result = time::timeout(timeout, resolver.lookup_ip(domain.to_string())).await;
+    let result = time::timeout(timeout, upstream.lookup(&domain, RecordType::A)).await;
     let elapsed = start.elapsed();
 
     match result {
@@ -93,13 +103,25 @@ pub async fn test_server(
                 "DNS lookup succeeded for {} via {} in {:?}",
                 domain, server, elapsed
             );
-            lookup.iter().for_each(|item| {
-                debug!("Resolved: {:?}", item);
+            lookup.iter().for_each(|record| {
+                debug!("Resolved: {}", record.data());
             });
+            // The server answered, so the test passed either way; a profile that is not
+            // in effect is reported next to that rather than as a failure.
+            let (profile_confirmed, error) = match options.profile_canary {
+                Some(ref canary) => {
+                    match confirm_profile(&upstream, canary, &server, timeout).await? {
+                        None => (Some(true), None),
+                        Some(reason) => (Some(false), Some(reason)),
+                    }
+                }
+                None => (None, None),
+            };
             Ok(ServerTestResult {
                 success: true,
                 latency: elapsed.as_millis() as usize,
-                error: None,
+                error,
+                profile_confirmed,
             })
         }
         Ok(Err(e)) => {
@@ -107,6 +129,19 @@ pub async fn test_server(
                 "DNS lookup failed for {} via {} after {:?}: {}",
                 domain, server, elapsed, e
             );
+            if let Some(mismatch) = options.pin_report.take() {
+                return Err(AppError::PinMismatch {
+                    server,
+                    presented: mismatch.presented,
+                });
+            }
+            if let Some(alert) = dns_tls::client_auth_alert(&e.to_string()) {
+                return Err(AppError::Resolver(dns_tls::client_auth_message(
+                    &server,
+                    alert,
+                    options.client_certificate.is_some(),
+                )));
+            }
             Err(AppError::Resolver(format!(
                 "{} could not resolve {}: {}",
                 server, domain, e
@@ -125,12 +160,210 @@ pub async fn test_server(
     }
 }
 
+/// Looks up the entry's canary and checks the answer shows the profile in effect. A
+/// server that answers but ignores the client ID would otherwise pass the test while
+/// filtering nothing. Returns why the profile could not be confirmed, or `None` when
+/// it was; only a canary that cannot be asked at all is an error.
+async fn confirm_profile(
+    upstream: &dns_server::Upstream,
+    canary: &ProfileCanary,
+    server: &str,
+    timeout: std::time::Duration,
+) -> AppResult<Option<String>> {
+    let record_type = RecordType::from_str(canary.record_type.trim()).map_err(|_| {
+        AppError::invalid(format!(
+            "'{}' is not a DNS record type the profile check can use.",
+            canary.record_type
+        ))
+    })?;
+
+    let records = match time::timeout(timeout, upstream.lookup(&canary.domain, record_type)).await {
+        Ok(Ok(records)) => records,
+        Ok(Err(e)) => {
+            return Ok(Some(format!(
+                "{} answered, but the profile check for {} failed: {}",
+                server, canary.domain, e
+            )))
+        }
+        Err(_) => {
+            return Ok(Some(format!(
+                "{} did not answer the profile check for {} within {:?}.",
+                server, canary.domain, timeout
+            )))
+        }
+    };
+
+    let answers: Vec<String> = records.iter().map(|r| r.data().to_string()).collect();
+    let expect = canary.expect.trim().to_lowercase();
+    if expect.is_empty() || answers.iter().any(|a| a.to_lowercase().contains(&expect)) {
+        info!("Profile confirmed for {} via {}", server, canary.domain);
+        return Ok(None);
+    }
+
+    warn!(
+        "Profile check for {} via {} returned {:?}, expected '{}'",
+        server, canary.domain, answers, canary.expect
+    );
+    Ok(Some(format!(
+        "{} answered, but not with the profile: {} returned {:?} where '{}' was expected. Check the client ID.",
+        server, canary.domain, answers, canary.expect
+    )))
+}
+
 #[tauri::command(rename_all = "snake_case")]
 pub fn get_interface_dns_info(interface_idx: u32) -> AppResult<dns_utils::InterfaceDnsInfo> {
-    let interface_idx = win::adapters::resolve_interface_index(interface_idx)?;
+    let interface_idx = platform::system().resolve_interface_index(interface_idx)?;
     dns_utils::get_interface_dns_info(interface_idx).log_err("get_interface_dns_info")
 }
 
+/// The interface's DNS suffix, search list, registration and LLMNR settings.
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_dns_options(interface_index: u32) -> AppResult<DnsOptions> {
+    let net = platform::system();
+    let interface_index = net.resolve_interface_index(interface_index)?;
+    net.dns_options(interface_index).log_err("get_dns_options")
+}
+
+/// A change to an interface's [`DnsOptions`]. Fields left out keep their current value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DnsOptionsUpdate {
+    pub suffix: Option<String>,
+    pub search_list: Option<Vec<String>>,
+    pub register_addresses: Option<bool>,
+    pub register_with_suffix: Option<bool>,
+    pub llmnr: Option<bool>,
+}
+
+impl DnsOptionsUpdate {
+    fn apply_to(self, options: &mut DnsOptions) -> AppResult<()> {
+        if let Some(suffix) = self.suffix {
+            options.suffix = match suffix.trim() {
+                "" => String::new(),
+                suffix => domain_name(suffix)?,
+            };
+        }
+        if let Some(search_list) = self.search_list {
+            options.search_list = search_list
+                .iter()
+                .map(|domain| domain.trim())
+                .filter(|domain| !domain.is_empty())
+                .map(domain_name)
+                .collect::<AppResult<_>>()?;
+        }
+        if let Some(register_addresses) = self.register_addresses {
+            options.register_addresses = register_addresses;
+        }
+        if let Some(register_with_suffix) = self.register_with_suffix {
+            options.register_with_suffix = register_with_suffix;
+        }
+        if let Some(llmnr) = self.llmnr {
+            options.llmnr = llmnr;
+        }
+        Ok(())
+    }
+}
+
+/// Lower-cased and without the trailing dot. Both Windows and resolv.conf separate the
+/// list with commas or spaces, so anything but a plain host name is refused here.
+fn domain_name(domain: &str) -> AppResult<String> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let valid = domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    if valid {
+        Ok(domain)
+    } else {
+        Err(AppError::invalid(format!(
+            "'{}' is not a valid domain name.",
+            domain
+        )))
+    }
+}
+
+/// Changes the interface's suffix, search list, registration or LLMNR settings, and
+/// returns them as they now are. On an activated interface what they were is saved
+/// first: `clear_dns` puts them back along with the name servers. Otherwise the change
+/// simply stands, like one made in the adapter's own settings.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn set_dns_options(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    interface_index: u32,
+    options: DnsOptionsUpdate,
+) -> AppResult<DnsOptions> {
+    let net = platform::system();
+    let interface_index = net
+        .resolve_interface_index(interface_index)
+        .log_err("set_dns_options")?;
+    // Held so an activation or a restore cannot start between the check and the change.
+    let app_state = app_state.lock().await;
+    let active = app_state.profiles.is_active(interface_index);
+    tauri::async_runtime::spawn_blocking(move || {
+        set_dns_options_inner(net, interface_index, options, active)
+    })
+    .await
+    .map_err(|e| AppError::Task(e.to_string()))?
+    .log_err("set_dns_options")
+}
+
+pub(crate) fn set_dns_options_inner(
+    net: &dyn DnsConfigurator,
+    interface_index: u32,
+    update: DnsOptionsUpdate,
+    active: bool,
+) -> AppResult<DnsOptions> {
+    let interface_index = net.resolve_interface_index(interface_index)?;
+    let mut options = net.dns_options(interface_index)?;
+    update.apply_to(&mut options)?;
+
+    // Without an activation there is nothing to restore, and a snapshot taken now would
+    // outlive its servers: the next activation would keep it, and clearing that would
+    // put back whatever the interface had today.
+    if active {
+        net.snapshot_dns(interface_index)?;
+    }
+    net.set_dns_options(interface_index, &options)?;
+    info!(
+        "Set the DNS options of interface {}: {:?}",
+        interface_index, options
+    );
+    net.dns_options(interface_index)
+}
+
+/// Everything `set_dns` applies to an interface: which servers, how to reach them, and
+/// how the proxy should behave. Network profiles store one as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsActivation {
+    pub dns_servers: Vec<String>,
+    pub dns_type: String,
+    pub bootstrap_ip: Option<String>,
+    pub bootstrap_resolver: Option<dns_server::BootstrapResolverInfo>,
+    #[serde(default)]
+    pub proxy_policy: ProxyPolicy,
+    #[serde(default)]
+    pub upstream_options: UpstreamOptions,
+}
+
+/// An activation made on interface `0`, "Auto". It follows the default route: when that
+/// moves to another adapter — Ethernet unplugged, Wi-Fi joined — the activation is
+/// applied there and the adapter it left gets its own DNS back.
+#[derive(Debug, Clone)]
+pub struct FollowedRoute {
+    pub activation: DnsActivation,
+    /// The adapter the activation is on right now.
+    pub interface_index: u32,
+}
+
+// The flat argument list is the IPC contract — the frontend invokes with named keys —
+// so it is collected into a `DnsActivation` here rather than reshaped on the wire.
+#[allow(clippy::too_many_arguments)]
 #[tauri::command(rename_all = "snake_case")]
 pub async fn set_dns(
     app_state: tauri::State<'_, Mutex<AppState>>,
@@ -139,28 +372,72 @@ pub async fn set_dns(
     dns_type: String,
     bootstrap_ip: Option<String>,
     bootstrap_resolver: Option<dns_server::BootstrapResolverInfo>,
+    proxy_policy: Option<ProxyPolicy>,
+    upstream_options: Option<UpstreamOptions>,
 ) -> AppResult<()> {
-    set_dns_inner(
-        app_state,
-        interface_index,
+    let activation = DnsActivation {
         dns_servers,
         dns_type,
         bootstrap_ip,
         bootstrap_resolver,
-    )
-    .await
-    .log_err("set_dns")
+        proxy_policy: proxy_policy.unwrap_or_default(),
+        upstream_options: upstream_options.unwrap_or_default(),
+    };
+    set_dns_inner(&app_state, platform::system(), interface_index, activation)
+        .await
+        .log_err("set_dns")
 }
 
-async fn set_dns_inner(
-    app_state: tauri::State<'_, Mutex<AppState>>,
+/// Undoes a switch that failed after the interface's old proxy was stopped. The
+/// adapter still points at that proxy's address, where nothing answers any more, so
+/// it goes back to what it had before the first activation and the profile is
+/// forgotten rather than left claiming servers that are not in effect.
+async fn abandon_switch(
+    app_state: &mut AppState,
+    net: &'static dyn DnsConfigurator,
     interface_index: u32,
-    dns_servers: Vec<String>,
-    dns_type: String,
-    bootstrap_ip: Option<String>,
-    bootstrap_resolver: Option<dns_server::BootstrapResolverInfo>,
+) {
+    let restored = tauri::async_runtime::spawn_blocking(move || net.restore_dns(interface_index))
+        .await
+        .unwrap_or(false);
+    if !restored && net.interface_uses_proxy_dns(interface_index) {
+        error!(
+            "Could not restore interface {} after a failed switch; it still points at a stopped proxy",
+            interface_index
+        );
+    }
+    let _ = app_state.profiles.remove(interface_index).await;
+    if app_state
+        .followed_route
+        .as_ref()
+        .is_some_and(|followed| followed.interface_index == interface_index)
+    {
+        app_state.followed_route = None;
+    }
+    warn!(
+        "Switching DNS on interface {} failed; restored its original settings",
+        interface_index
+    );
+}
+
+pub(crate) async fn set_dns_inner(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+    interface_index: u32,
+    activation: DnsActivation,
 ) -> AppResult<()> {
-    let interface_index = win::adapters::resolve_interface_index(interface_index)?;
+    // "Auto" keeps following the default route; see `follow_default_route`.
+    let follow = (interface_index == 0).then(|| activation.clone());
+    let requested = activation.clone();
+    let DnsActivation {
+        dns_servers,
+        dns_type,
+        bootstrap_ip,
+        bootstrap_resolver,
+        mut proxy_policy,
+        upstream_options,
+    } = activation;
+    let interface_index = net.resolve_interface_index(interface_index)?;
 
     debug!(
         "set_dns: interface={}, type={}, servers={:?}",
@@ -174,68 +451,134 @@ async fn set_dns_inner(
     }
 
     if dns_type == "doh" || dns_type == "dot" || dns_type == "doq" || dns_type == "doh3" {
+        // Held for the whole activation, so two interfaces being activated at once
+        // can't be handed the same proxy address.
+        let mut app_state = app_state.lock().await;
+
+        // Before anything changes, so deactivation can put back static servers rather
+        // than assuming DHCP. Kept from the first activation when switching servers.
+        net.snapshot_dns(interface_index)?;
+
+        // Switching upstreams on an interface that already has a proxy: stop the old
+        // one first, so its loopback address — and `[::1]:53`, if it had it — are free
+        // for the new one.
+        let mut had_ipv6_redirect = false;
+        let switching = match app_state.profiles.take_proxy(interface_index) {
+            Some(mut old_proxy) => {
+                had_ipv6_redirect = old_proxy.status().ipv6_redirect;
+                if let Err(e) = old_proxy.shutdown().await {
+                    abandon_switch(&mut app_state, net, interface_index).await;
+                    return Err(AppError::Proxy(e));
+                }
+                true
+            }
+            None => false,
+        };
+
         // Read the interface's IPv6 DNS state *before* changing anything, so the
-        // decision below is based on what the user actually had configured. On Windows
-        // versions without SetInterfaceDnsSettings there is no way to write IPv6 name
-        // servers at all, so don't even look.
-        let needs_ipv6_redirect =
-            win::dns_settings::supports_ipv6_dns() && win::has_real_ipv6_dns(interface_index);
-
-        let ipv6_ready = {
-            let mut app_state = app_state.lock().await;
-            app_state
-                .dns_server
-                .run(dns_servers[0].to_string(), bootstrap_ip, bootstrap_resolver)
-                .await?
+        // decision below is based on what the user actually had configured.
+        let needs_ipv6_redirect = needs_ipv6_redirect(net, interface_index, had_ipv6_redirect);
+
+        // Same reasoning: once 127.0.0.2 is applied, the resolvers the local network
+        // handed out are gone from the adapter. The proxy still needs them for
+        // special-use names like `home.arpa` that only the LAN can answer.
+        proxy_policy.original_resolvers =
+            net.real_dns_servers(interface_index).unwrap_or_else(|e| {
+                warn!(
+                    "Could not read the original DNS servers on interface {}: {}",
+                    interface_index, e
+                );
+                Vec::new()
+            });
+
+        let started = async {
+            let mut proxy = app_state.profiles.new_proxy(interface_index)?;
+            let ipv6_ready = proxy
+                .run(
+                    dns_servers[0].to_string(),
+                    bootstrap_ip,
+                    bootstrap_resolver,
+                    proxy_policy,
+                    upstream_options,
+                )
+                .await?;
+            if let Err(e) = net.set_dns(interface_index, Family::V4, &[IpAddr::V4(proxy.listen_v4)])
+            {
+                // Nothing points at it, and nothing would stop it later.
+                let _ = proxy.shutdown().await;
+                return Err(e);
+            }
+            Ok((proxy, ipv6_ready))
+        }
+        .await;
+        let (mut proxy, ipv6_ready) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                if switching {
+                    abandon_switch(&mut app_state, net, interface_index).await;
+                }
+                return Err(e);
+            }
         };
 
-        win::dns_settings::set_interface_dns(
-            interface_index,
-            Family::V4,
-            &[IpAddr::V4(win::PROXY_V4)],
-        )?;
+        let mut applied = vec![IpAddr::V4(proxy.listen_v4)];
 
         // Close the IPv6 leak: the old WMI path (SetDNSServerSearchOrder) is IPv4-only,
         // so a dual-stack machine kept sending queries to its ISP's IPv6 resolver even
         // while "protected". Only redirect when the interface really has IPv6 DNS, and
         // only when the proxy actually managed to bind [::1]:53 — pointing IPv6 DNS at
         // a port nothing is listening on would break resolution outright.
+        // Left as it is, IPv6 bypasses the proxy, and the profile's status says so.
+        let mut ipv6_unprotected = false;
         if needs_ipv6_redirect {
             if ipv6_ready {
-                if let Err(e) = win::dns_settings::set_interface_dns(
-                    interface_index,
-                    Family::V6,
-                    &[IpAddr::V6(win::PROXY_V6)],
-                ) {
-                    error!(
-                        "Failed to set IPv6 DNS on interface {}: {}",
-                        interface_index, e
-                    );
+                let proxy_v6 = IpAddr::V6(platform::PROXY_V6);
+                match net.set_dns(interface_index, Family::V6, &[proxy_v6]) {
+                    Ok(()) => applied.push(proxy_v6),
+                    Err(e) => {
+                        error!(
+                            "Failed to set IPv6 DNS on interface {}: {}",
+                            interface_index, e
+                        );
+                        ipv6_unprotected = true;
+                    }
                 }
             } else {
                 warn!(
-                    "Interface {} has IPv6 DNS configured but the proxy could not bind [::1]:53 — IPv6 queries will bypass it",
+                    "Interface {} has IPv6 DNS configured but the proxy could not serve [::1]:53 — IPv6 queries will bypass it",
                     interface_index
                 );
+                ipv6_unprotected = true;
             }
-        } else if !win::dns_settings::supports_ipv6_dns() && win::has_real_ipv6_dns(interface_index)
-        {
+        } else if !net.supports_ipv6_dns() && net.has_real_ipv6_dns(interface_index) {
             warn!(
                 "Interface {} has IPv6 DNS configured, but this Windows version cannot change it — IPv6 queries will bypass the proxy",
                 interface_index
             );
+            ipv6_unprotected = true;
         }
 
+        let ipv6_redirected = applied.contains(&IpAddr::V6(platform::PROXY_V6));
+        proxy.mark_applied(ipv6_redirected, ipv6_unprotected);
+
         info!(
-            "Applied {} DNS on interface {} via the local proxy (IPv6 redirect: {})",
+            "Applied {} DNS on interface {} via the local proxy on {} (IPv6 redirect: {})",
             dns_type.to_uppercase(),
             interface_index,
-            if needs_ipv6_redirect && ipv6_ready {
-                "on"
-            } else {
-                "off"
-            }
+            proxy.listen_v4,
+            if ipv6_redirected { "on" } else { "off" }
         );
+        app_state.profiles.insert(
+            interface_index,
+            ActiveProfile {
+                dns_type,
+                servers: dns_servers,
+                proxy: Some(proxy),
+                applied,
+                activation: requested,
+            },
+        );
+        track_default_route(&mut app_state, follow, interface_index);
         Ok(())
     } else if dns_type == "dns" {
         let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = dns_servers
@@ -250,6 +593,15 @@ async fn set_dns_inner(
             )));
         }
 
+        // Held from the first write, so the drift check never mistakes a change of
+        // servers in progress for someone else's.
+        let mut app_state = app_state.lock().await;
+        net.snapshot_dns(interface_index)?;
+        let had_ipv6_redirect = app_state
+            .profiles
+            .status(Some(interface_index))
+            .ipv6_redirect;
+
         // Only touch a family we actually have servers for. Passing an empty list to
         // `set_interface_dns` reverts that family to DHCP, which would silently discard
         // the user's selection rather than apply it.
@@ -259,18 +611,39 @@ async fn set_dns_inner(
                 interface_index
             );
         } else {
-            win::dns_settings::set_interface_dns(interface_index, Family::V4, &v4)?;
+            net.set_dns(interface_index, Family::V4, &v4)?;
         }
 
-        if v6.is_empty() {
+        if v6.is_empty() && had_ipv6_redirect {
+            // The old proxy has IPv6 pointed at `[::1]`, where nothing answers once it
+            // stops. Hand that family back to DHCP; clearing the interface still puts
+            // back whatever static servers the snapshot has.
+            net.set_dns(interface_index, Family::V6, &[])?;
+        } else if v6.is_empty() {
             debug!(
                 "No IPv6 servers supplied for interface {} — IPv6 DNS left as-is (plain DNS mode can't fully close the leak without one)",
                 interface_index
             );
         } else {
-            win::dns_settings::set_interface_dns(interface_index, Family::V6, &v6)?;
+            net.set_dns(interface_index, Family::V6, &v6)?;
         }
 
+        // The interface no longer points at its proxy, if it had one.
+        if let Some(mut old_proxy) = app_state.profiles.take_proxy(interface_index) {
+            old_proxy.shutdown().await.map_err(AppError::Proxy)?;
+        }
+        app_state.profiles.insert(
+            interface_index,
+            ActiveProfile {
+                dns_type,
+                servers: dns_servers,
+                proxy: None,
+                applied: v4.iter().chain(&v6).copied().collect(),
+                activation: requested,
+            },
+        );
+        track_default_route(&mut app_state, follow, interface_index);
+
         info!(
             "Applied plain DNS on interface {} ({} IPv4, {} IPv6 server(s))",
             interface_index,
@@ -286,38 +659,306 @@ async fn set_dns_inner(
     }
 }
 
+/// Whether an activation should point the interface's IPv6 DNS at the proxy too: when
+/// it has real IPv6 servers that would otherwise bypass it, or when the proxy it
+/// replaces already had them redirected — by then the real ones are no longer visible.
+/// On Windows versions without SetInterfaceDnsSettings there is no way to write IPv6
+/// name servers at all, so don't even look.
+fn needs_ipv6_redirect(
+    net: &dyn DnsConfigurator,
+    interface_index: u32,
+    had_ipv6_redirect: bool,
+) -> bool {
+    net.supports_ipv6_dns() && (had_ipv6_redirect || net.has_real_ipv6_dns(interface_index))
+}
+
+/// Starts or stops following the default route after an activation on
+/// `interface_index`. An explicit activation on the followed adapter takes it over.
+fn track_default_route(
+    app_state: &mut AppState,
+    follow: Option<DnsActivation>,
+    interface_index: u32,
+) {
+    match follow {
+        Some(activation) => {
+            app_state.followed_route = Some(FollowedRoute {
+                activation,
+                interface_index,
+            })
+        }
+        None if app_state
+            .followed_route
+            .as_ref()
+            .is_some_and(|followed| followed.interface_index == interface_index) =>
+        {
+            info!(
+                "Interface {} was set explicitly; no longer following the default route",
+                interface_index
+            );
+            app_state.followed_route = None;
+        }
+        None => {}
+    }
+}
+
+/// How long the network has to be quiet before it is looked at again. Joining a
+/// network produces a burst of notifications — link, addresses, routes, DHCP — and
+/// the route and the adapters' DNS have only settled once they stop.
+const SETTLE_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How often the drift check runs without a change notification. Group policy keeps
+/// DNS in keys of its own, which nothing watches.
+const DRIFT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Serializes the background work below. A route move takes seconds — a proxy start,
+/// a restore — and a drift check must not run into the middle of one.
+static NETWORK_WORK: Mutex<()> = Mutex::const_new(());
+
+/// How long change notifications are gathered before the frontend hears about them:
+/// enough for one burst, too little to notice.
+const COALESCE_WINDOW: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// What has been reported since the current window opened.
+static PENDING_CHANGES: std::sync::Mutex<NetworkChanges> = std::sync::Mutex::new(NetworkChanges {
+    changes: Vec::new(),
+});
+
+/// Called for every network change notification, from whatever thread the OS reports
+/// it on. Passes the changes on to the frontend in batches. Once the notifications
+/// stop, applies the network profile matching the network the default route is on or,
+/// when none matches, moves an "Auto" activation there — unless only DNS changed,
+/// which does neither — then checks every active interface for drift.
+pub fn network_changed(app: &tauri::AppHandle, change: NetworkChange) {
+    static LATEST: AtomicU64 = AtomicU64::new(0);
+    static TOPOLOGY_CHANGED: AtomicBool = AtomicBool::new(false);
+
+    let Ok(mut pending) = PENDING_CHANGES.lock() else {
+        return;
+    };
+    if pending.push(change) {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            time::sleep(COALESCE_WINDOW).await;
+            let Ok(changes) = PENDING_CHANGES.lock().map(|mut p| std::mem::take(&mut *p)) else {
+                return;
+            };
+            if let Err(e) = app.emit(platform::NETWORK_CHANGED_EVENT, &changes) {
+                warn!("Could not emit {}: {}", platform::NETWORK_CHANGED_EVENT, e);
+            }
+        });
+    }
+    drop(pending);
+
+    if change.source != ChangeSource::Dns {
+        TOPOLOGY_CHANGED.store(true, Ordering::Relaxed);
+    }
+    let this = LATEST.fetch_add(1, Ordering::Relaxed) + 1;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        time::sleep(SETTLE_DEBOUNCE).await;
+        if LATEST.load(Ordering::Relaxed) != this {
+            // A later notification restarted the wait.
+            return;
+        }
+        let _working = NETWORK_WORK.lock().await;
+        if TOPOLOGY_CHANGED.swap(false, Ordering::Relaxed) {
+            let app_state = app.state::<Mutex<AppState>>();
+            let net = platform::system();
+            // A profile for the new network overrides "Auto", so "Auto" is not moved
+            // there only to be replaced a moment later.
+            if !super::network_profiles::profile_matches(&app_state, net).await {
+                match follow_default_route(&app_state, net).await {
+                    Ok(Some(moved)) => {
+                        if let Err(e) = app.emit(DEFAULT_ROUTE_FOLLOWED_EVENT, &moved) {
+                            warn!("Could not emit {}: {}", DEFAULT_ROUTE_FOLLOWED_EVENT, e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!(
+                        "Could not move the activation to the new default route: {}",
+                        e
+                    ),
+                }
+            }
+            super::network_profiles::apply_and_report(&app, &app_state).await;
+        }
+        run_drift_check(&app).await;
+    });
+}
+
+/// Checks for drift every [`DRIFT_CHECK_INTERVAL`] for as long as the app runs.
+pub fn spawn_drift_watchdog(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = time::interval(DRIFT_CHECK_INTERVAL);
+        // The first tick is immediate, and at startup nothing is applied yet.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let _working = NETWORK_WORK.lock().await;
+            run_drift_check(&app).await;
+        }
+    });
+}
+
+async fn run_drift_check(app: &tauri::AppHandle) {
+    let action = drift_action(app);
+    let app_state = app.state::<Mutex<AppState>>();
+    for drift in check_drift(&app_state, platform::system(), action).await {
+        if let Err(e) = app.emit(DNS_DRIFT_DETECTED_EVENT, &drift) {
+            warn!("Could not emit {}: {}", DNS_DRIFT_DETECTED_EVENT, e);
+        }
+    }
+}
+
+/// The user's choice from `setting.json`, which the frontend owns. Read on every check
+/// so a change applies straight away.
+fn drift_action(app: &tauri::AppHandle) -> DriftAction {
+    // `store`, not `store_builder`: the frontend has it open already, and this gets
+    // that same instance rather than a second copy of the file.
+    let stored = match app.store("setting.json") {
+        Ok(store) => store.get(DRIFT_ACTION_SETTING),
+        Err(e) => {
+            warn!("Could not open setting.json: {}", e);
+            None
+        }
+    };
+    stored
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+/// Compares every active interface with what was applied to it and, if `action` says
+/// so, writes the applied servers back. Returns what had drifted.
+async fn check_drift(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+    action: DriftAction,
+) -> Vec<DnsDrift> {
+    // Held throughout: activations and restores take it too, so their own writes are
+    // never mistaken for someone else's. The OS calls themselves run off the runtime.
+    let app_state = app_state.lock().await;
+    let applied: Vec<(u32, Vec<IpAddr>)> = app_state
+        .profiles
+        .applied()
+        .map(|(interface_index, applied)| (interface_index, applied.to_vec()))
+        .collect();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut drifted = dns_drift::find_drift(&applied, net);
+        for drift in &mut drifted {
+            warn!(
+                "{:?} DNS on interface {} was changed to {:?} ({:?}); {:?} was applied",
+                drift.family, drift.interface_index, drift.found, drift.source, drift.expected
+            );
+            if action == DriftAction::Reapply {
+                match dns_drift::reapply(net, drift) {
+                    Ok(()) => info!(
+                        "Re-applied {:?} DNS on interface {}",
+                        drift.family, drift.interface_index
+                    ),
+                    Err(e) => error!(
+                        "Could not re-apply {:?} DNS on interface {}: {}",
+                        drift.family, drift.interface_index, e
+                    ),
+                }
+            }
+        }
+        drifted
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("DNS drift check did not complete: {}", e);
+        Vec::new()
+    })
+}
+
+/// Moves an "Auto" activation to the adapter now carrying the default route, and
+/// restores the adapter it left. An adapter with an activation of its own is never
+/// overwritten. Returns the move, if there was one.
+async fn follow_default_route(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+) -> AppResult<Option<DefaultRouteFollowed>> {
+    let Some(followed) = app_state.lock().await.followed_route.clone() else {
+        return Ok(None);
+    };
+    let best = match net.best_interface_index() {
+        Ok(best) => best,
+        Err(e) => {
+            // Between networks. The activation stays where it is until one is up.
+            debug!("No default route to follow yet: {}", e);
+            return Ok(None);
+        }
+    };
+    let from = followed.interface_index;
+    // With global DNS the activation already covers the new adapter.
+    if best == from || net.dns_is_global() {
+        return Ok(None);
+    }
+    if app_state.lock().await.profiles.is_active(best) {
+        // Set on that adapter by hand, or by a network profile. "Auto" only ever fills
+        // in for an adapter nobody chose DNS for, so it stays where it is.
+        info!(
+            "Default route moved to interface {}, which has its own activation; not following",
+            best
+        );
+        return Ok(None);
+    }
+
+    info!(
+        "Default route moved from interface {} to {}; moving the activation with it",
+        from, best
+    );
+    // The new adapter first: until it points at the proxy, its queries go to whatever
+    // DNS the network handed out.
+    set_dns_inner(app_state, net, best, followed.activation).await?;
+    if let Some(ref mut followed) = app_state.lock().await.followed_route {
+        followed.interface_index = best;
+    }
+
+    // Usually the old adapter is gone or disconnected, which is why the route moved;
+    // the restore then finds nothing to do.
+    if let Err(e) = clear_dns_inner(app_state, net, from).await {
+        warn!(
+            "Moved the activation to interface {}, but could not restore interface {}: {}",
+            best, from, e
+        );
+    }
+    Ok(Some(DefaultRouteFollowed { from, to: best }))
+}
+
 #[tauri::command(rename_all = "snake_case")]
 pub async fn clear_dns(
     app_state: tauri::State<'_, Mutex<AppState>>,
     interface_index: u32,
 ) -> AppResult<()> {
-    clear_dns_inner(app_state, interface_index)
+    clear_dns_inner(&app_state, platform::system(), interface_index)
         .await
         .log_err("clear_dns")
 }
 
-async fn clear_dns_inner(
-    app_state: tauri::State<'_, Mutex<AppState>>,
+pub(crate) async fn clear_dns_inner(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
     interface_index: u32,
 ) -> AppResult<()> {
-    let interface_index = win::adapters::resolve_interface_index(interface_index)?;
+    let interface_index = net.resolve_interface_index(interface_index)?;
+    // Held across the restore, so the drift check doesn't see it and put the proxy back.
+    let mut app_state = app_state.lock().await;
 
     // Restore DNS *before* shutting the proxy down. While the interface still points at
     // 127.0.0.2 / ::1, the proxy is the only resolver it can reach — killing it first
     // and then failing the restore leaves the machine with no working DNS at all.
-    // Same implementation the exit handler uses — see `win::restore_dhcp_dns_blocking`.
+    // Same implementation the exit handler uses — see `DnsConfigurator::restore_dns`.
     // These two paths were separate once and only this one got fixed, which is how
     // 127.0.0.2 survived app close. It blocks, so it runs on a worker thread.
-    let restored = tauri::async_runtime::spawn_blocking(move || {
-        win::restore_dhcp_dns_blocking(interface_index)
-    })
-    .await
-    .unwrap_or_else(|e| {
-        error!("DNS restore task did not complete: {}", e);
-        false
-    });
+    let restored = tauri::async_runtime::spawn_blocking(move || net.restore_dns(interface_index))
+        .await
+        .unwrap_or_else(|e| {
+            error!("DNS restore task did not complete: {}", e);
+            false
+        });
 
-    if !restored && win::interface_uses_proxy_dns(interface_index) {
+    if !restored && net.interface_uses_proxy_dns(interface_index) {
         // Deliberately leave the proxy running: it is the only thing still answering
         // queries for this interface. Shutting it down here is what turns a failed
         // restore into a total DNS outage.
@@ -341,24 +982,51 @@ async fn clear_dns_inner(
         );
     }
 
-    debug!("Restoring DNS succeeded; shutting the local proxy down");
-    let mut app_state = app_state.lock().await;
+    debug!("Restoring DNS succeeded; shutting the interface's proxy down");
     app_state
-        .dns_server
-        .shutdown()
+        .profiles
+        .remove(interface_index)
         .await
         .map_err(AppError::Proxy)?;
+    if app_state
+        .followed_route
+        .as_ref()
+        .is_some_and(|followed| followed.interface_index == interface_index)
+    {
+        app_state.followed_route = None;
+    }
 
     info!(
-        "Cleared DNS on interface {} and stopped the proxy",
+        "Cleared DNS on interface {} and stopped its proxy",
         interface_index
     );
     Ok(())
 }
 
+/// Whether the interface's proxy is running, what it forwards to, and how much it has
+/// done. Without an interface, reports the first proxy that is running.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn get_proxy_status(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    interface_index: Option<u32>,
+) -> AppResult<ProxyStatus> {
+    let interface_index = interface_index
+        .map(|idx| platform::system().resolve_interface_index(idx))
+        .transpose()?;
+    Ok(app_state.lock().await.profiles.status(interface_index))
+}
+
+/// Every interface this app has set DNS on, and the proxy serving each.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn get_active_profiles(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+) -> AppResult<Vec<ActiveProfileInfo>> {
+    Ok(app_state.lock().await.profiles.list())
+}
+
 #[tauri::command(rename_all = "snake_case")]
 pub fn clear_dns_cache() -> AppResult<()> {
-    dns_utils::clear_dns_cache().log_err("clear_dns_cache")
+    platform::system().flush_cache().log_err("clear_dns_cache")
 }
 
 // --- DNS Log commands ---
@@ -459,3 +1127,409 @@ async fn persist_rules(
     debug!("Persisted {} DNS rules", rules_vec.len());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_fixtures::{app_state, ips, plain};
+    use crate::dns::dns_events::DriftSource;
+    use crate::platform::mock::MockNetwork;
+
+    #[tokio::test]
+    async fn plain_dns_is_cleared_back_to_dhcp() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1", "2606:4700:4700::1111"]))
+            .await
+            .unwrap();
+        assert_eq!(net.servers(7), ips(&["1.1.1.1", "2606:4700:4700::1111"]));
+        assert_eq!(state.lock().await.profiles.list().len(), 1);
+
+        clear_dns_inner(&state, net, 7).await.unwrap();
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert!(state.lock().await.profiles.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn clearing_puts_static_servers_back() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["10.0.0.53"])
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        clear_dns_inner(&state, net, 7).await.unwrap();
+
+        assert_eq!(net.servers(7), ips(&["10.0.0.53"]));
+    }
+
+    #[tokio::test]
+    async fn auto_means_the_best_interface() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["192.168.1.1"])
+            .best(7)
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 0, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+
+        assert_eq!(net.servers(3), ips(&["192.168.1.1"]));
+        assert_eq!(net.servers(7), ips(&["1.1.1.1"]));
+        assert_eq!(state.lock().await.profiles.list()[0].interface_index, 7);
+    }
+
+    #[tokio::test]
+    async fn auto_follows_the_default_route() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["10.0.0.1"])
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 0, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        assert!(follow_default_route(&state, net).await.unwrap().is_none());
+
+        net.move_default_route(7);
+        let moved = follow_default_route(&state, net).await.unwrap().unwrap();
+        assert_eq!((moved.from, moved.to), (3, 7));
+        assert_eq!(net.servers(3), ips(&["192.168.1.1"]));
+        assert_eq!(net.servers(7), ips(&["1.1.1.1"]));
+
+        let state = state.lock().await;
+        let profiles = state.profiles.list();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].interface_index, 7);
+        assert_eq!(state.followed_route.as_ref().unwrap().interface_index, 7);
+    }
+
+    #[tokio::test]
+    async fn explicit_activations_stay_put() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["10.0.0.1"])
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 3, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        net.move_default_route(7);
+
+        assert!(follow_default_route(&state, net).await.unwrap().is_none());
+        assert_eq!(net.servers(3), ips(&["1.1.1.1"]));
+        assert_eq!(net.servers(7), ips(&["10.0.0.1"]));
+    }
+
+    #[tokio::test]
+    async fn an_explicit_activation_is_not_followed_onto() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["10.0.0.1"])
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 0, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        set_dns_inner(&state, net, 7, plain(&["9.9.9.9"]))
+            .await
+            .unwrap();
+        net.move_default_route(7);
+
+        assert!(follow_default_route(&state, net).await.unwrap().is_none());
+        assert_eq!(net.servers(3), ips(&["1.1.1.1"]));
+        assert_eq!(net.servers(7), ips(&["9.9.9.9"]));
+    }
+
+    #[tokio::test]
+    async fn clearing_stops_following() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["10.0.0.1"])
+            .leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 0, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        clear_dns_inner(&state, net, 3).await.unwrap();
+        net.move_default_route(7);
+
+        assert!(follow_default_route(&state, net).await.unwrap().is_none());
+        assert_eq!(net.servers(7), ips(&["10.0.0.1"]));
+    }
+
+    #[tokio::test]
+    async fn an_overwrite_is_reported() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        assert!(check_drift(&state, net, DriftAction::Notify)
+            .await
+            .is_empty());
+
+        // A VPN client, say.
+        net.set_dns(7, Family::V4, &ips(&["10.8.0.1"])).unwrap();
+        let drift = check_drift(&state, net, DriftAction::Notify).await;
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].family, Family::V4);
+        assert_eq!(drift[0].expected, ips(&["1.1.1.1"]));
+        assert_eq!(drift[0].found, ips(&["10.8.0.1"]));
+        assert_eq!(drift[0].source, Some(DriftSource::Static));
+        assert!(!drift[0].reapplied);
+        assert_eq!(net.servers(7), ips(&["10.8.0.1"]));
+    }
+
+    #[tokio::test]
+    async fn an_overwrite_can_be_undone() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        net.set_dns(7, Family::V4, &[]).unwrap();
+
+        // Back to the lease, as a DHCP renewal would leave it.
+        let drift = check_drift(&state, net, DriftAction::Reapply).await;
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].found, ips(&["192.168.1.1"]));
+        assert_eq!(drift[0].source, Some(DriftSource::Dhcp));
+        assert!(drift[0].reapplied);
+        assert_eq!(net.servers(7), ips(&["1.1.1.1"]));
+        assert!(check_drift(&state, net, DriftAction::Notify)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_family_never_written_is_not_drift() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        // Router advertisements arriving after activation.
+        net.set_dns(7, Family::V6, &ips(&["fd00::1"])).unwrap();
+
+        assert!(check_drift(&state, net, DriftAction::Notify)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn dns_options_are_restored_with_the_servers() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        let update = DnsOptionsUpdate {
+            suffix: Some("Corp.Example.com.".to_string()),
+            search_list: Some(vec!["example.com".to_string(), " ".to_string()]),
+            llmnr: Some(true),
+            ..DnsOptionsUpdate::default()
+        };
+        let options = set_dns_options_inner(net, 7, update, true).unwrap();
+        assert_eq!(options.suffix, "corp.example.com");
+        assert_eq!(options.search_list, vec!["example.com"]);
+        assert!(options.llmnr && !options.register_addresses);
+
+        // A later change leaves what was set before alone.
+        let update = DnsOptionsUpdate {
+            register_addresses: Some(true),
+            ..DnsOptionsUpdate::default()
+        };
+        let options = set_dns_options_inner(net, 7, update, true).unwrap();
+        assert_eq!(options.suffix, "corp.example.com");
+        assert!(options.register_addresses);
+
+        clear_dns_inner(&state, net, 7).await.unwrap();
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert_eq!(net.dns_options(7).unwrap(), DnsOptions::default());
+    }
+
+    #[tokio::test]
+    async fn options_on_an_inactive_interface_leave_no_snapshot() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["10.0.0.53"])
+            .leak();
+        let state = app_state();
+
+        let update = DnsOptionsUpdate {
+            suffix: Some("corp.example.com".to_string()),
+            ..DnsOptionsUpdate::default()
+        };
+        set_dns_options_inner(net, 7, update, false).unwrap();
+        // The administrator moves the adapter to another server afterwards.
+        net.set_dns(7, Family::V4, &ips(&["10.0.0.54"])).unwrap();
+
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        clear_dns_inner(&state, net, 7).await.unwrap();
+        assert_eq!(net.servers(7), ips(&["10.0.0.54"]));
+        assert_eq!(net.dns_options(7).unwrap().suffix, "corp.example.com");
+    }
+
+    #[test]
+    fn malformed_domains_are_refused() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        for domain in [
+            "corp example.com",
+            "a,b",
+            "-corp.example.com",
+            "corp..example",
+        ] {
+            let update = DnsOptionsUpdate {
+                search_list: Some(vec![domain.to_string()]),
+                ..DnsOptionsUpdate::default()
+            };
+            assert!(
+                set_dns_options_inner(net, 7, update, false).is_err(),
+                "{}",
+                domain
+            );
+        }
+        assert!(net.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_restore_that_never_takes_is_an_error() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .ignore_null_clear()
+            .ignore_empty_string_clear()
+            .ignore_legacy_clear()
+            .leak();
+
+        assert!(clear_dns_inner(&app_state(), net, 7).await.is_err());
+        assert_eq!(net.servers(7), ips(&["127.0.0.2"]));
+    }
+
+    #[tokio::test]
+    async fn a_failed_switch_restores_the_interface() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+
+        // What an encrypted activation leaves behind, short of a proxy bound to :53.
+        {
+            let mut state = state.lock().await;
+            let proxy = state.profiles.new_proxy(7).unwrap();
+            let proxy_v4 = IpAddr::V4(proxy.listen_v4);
+            net.snapshot_dns(7).unwrap();
+            net.set_dns(7, Family::V4, &[proxy_v4]).unwrap();
+            let activation = DnsActivation {
+                dns_type: "doh".to_string(),
+                ..plain(&["https://dns.example/dns-query"])
+            };
+            state.profiles.insert(
+                7,
+                ActiveProfile {
+                    dns_type: activation.dns_type.clone(),
+                    servers: activation.dns_servers.clone(),
+                    proxy: Some(proxy),
+                    applied: vec![proxy_v4],
+                    activation,
+                },
+            );
+        }
+
+        let broken = DnsActivation {
+            dns_type: "doh".to_string(),
+            ..plain(&["ftp://dns.example"])
+        };
+        assert!(set_dns_inner(&state, net, 7, broken).await.is_err());
+
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert!(state.lock().await.profiles.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_plain_switch_takes_ipv6_back_from_the_proxy() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1", "fd00::1"])
+            .leak();
+        let state = app_state();
+        let encrypted = DnsActivation {
+            dns_type: "doh".to_string(),
+            bootstrap_ip: Some("127.0.0.1".to_string()),
+            ..plain(&["https://dns.example/dns-query"])
+        };
+
+        set_dns_inner(&state, net, 7, encrypted).await.unwrap();
+        let proxy = vec![
+            IpAddr::V4(platform::proxy_v4(0)),
+            IpAddr::V6(platform::PROXY_V6),
+        ];
+        assert_eq!(net.servers(7), proxy);
+
+        // IPv4 only, so nothing new is written over `[::1]`.
+        set_dns_inner(&state, net, 7, plain(&["1.1.1.1"]))
+            .await
+            .unwrap();
+        assert_eq!(net.servers(7), ips(&["1.1.1.1", "fd00::1"]));
+    }
+
+    #[tokio::test]
+    async fn only_one_interface_can_have_ipv6_redirected() {
+        let net = MockNetwork::new()
+            .interface(3, &["192.168.1.1", "fd00::1"])
+            .interface(7, &["10.0.0.1", "fd00::2"])
+            .leak();
+        let state = app_state();
+        let encrypted = DnsActivation {
+            dns_type: "doh".to_string(),
+            bootstrap_ip: Some("127.0.0.1".to_string()),
+            ..plain(&["https://dns.example/dns-query"])
+        };
+
+        set_dns_inner(&state, net, 3, encrypted.clone())
+            .await
+            .unwrap();
+        set_dns_inner(&state, net, 7, encrypted).await.unwrap();
+
+        let v6 = |if_index| net.servers(if_index).into_iter().find(IpAddr::is_ipv6);
+        assert_eq!(v6(3), Some(IpAddr::V6(platform::PROXY_V6)));
+        assert_eq!(v6(7), Some("fd00::2".parse().unwrap()));
+
+        let state = state.lock().await;
+        let first = state.profiles.status(Some(3));
+        assert!(first.ipv6_redirect && !first.ipv6_unprotected);
+        let second = state.profiles.status(Some(7));
+        assert!(!second.ipv6_redirect && second.ipv6_unprotected);
+    }
+
+    #[test]
+    fn ipv6_is_redirected_only_when_it_would_leak() {
+        let real = MockNetwork::new().interface(7, &["192.168.1.1", "fd00::1"]);
+        assert!(needs_ipv6_redirect(&real, 7, false));
+
+        let anycast_only = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_anycast_defaults(7);
+        assert!(!needs_ipv6_redirect(&anycast_only, 7, false));
+        assert!(needs_ipv6_redirect(&anycast_only, 7, true));
+
+        let unsupported = MockNetwork::new()
+            .interface(7, &["192.168.1.1", "fd00::1"])
+            .without_ipv6_support();
+        assert!(!needs_ipv6_redirect(&unsupported, 7, true));
+    }
+}
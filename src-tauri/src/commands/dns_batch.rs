@@ -0,0 +1,398 @@
+//! `set_dns` and `clear_dns` over several interfaces at once, all or nothing.
+//!
+//! A machine on Ethernet and Wi-Fi at the same time sends queries over both, so
+//! protecting one of them protects nothing. The batch commands take the interfaces as
+//! a list or as an [`InterfaceSelector`], change them one after another, and if any of
+//! them fails, put every interface already changed back the way it was before the
+//! batch: its previous activation if it had one, its own DNS otherwise. The report says
+//! what happened to each.
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::dns::{clear_dns_inner, set_dns_inner, DnsActivation};
+use crate::dns::dns_server;
+use crate::dns::dns_types::ProxyPolicy;
+use crate::dns::dns_upstream::UpstreamOptions;
+use crate::error::{AppError, AppResult, LogErr};
+use crate::platform::{self, AdapterKind, DnsConfigurator, NetworkInterface};
+use crate::AppState;
+
+/// Which interfaces a batch applies to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceTargets {
+    /// These, in this order. `0` is the adapter with the default route right now; unlike
+    /// `set_dns`, a batch does not follow it when it moves.
+    Interfaces(Vec<u32>),
+    Select(InterfaceSelector),
+}
+
+/// Every interface that fits. The default — all fields `false` — is every adapter that
+/// is up, except virtual ones, VPNs and tunnels. Loopback is never selected.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InterfaceSelector {
+    pub include_down: bool,
+    pub include_virtual: bool,
+    pub include_vpns: bool,
+    pub include_tunnels: bool,
+}
+
+impl InterfaceSelector {
+    fn selects(&self, iface: &NetworkInterface) -> bool {
+        let kind_wanted = match iface.kind {
+            AdapterKind::Physical | AdapterKind::Wireless => true,
+            AdapterKind::Virtual => self.include_virtual,
+            AdapterKind::Vpn => self.include_vpns,
+            AdapterKind::Tunnel => self.include_tunnels,
+            AdapterKind::Loopback => false,
+        };
+        kind_wanted && (self.include_down || iface.is_up)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    /// Changed, and left that way.
+    Applied,
+    /// This is the interface that failed.
+    Failed,
+    /// Changed, then put back because another interface failed.
+    RolledBack,
+    /// Changed, and could not be put back. Still has the batch's change.
+    RollbackFailed,
+    /// Not attempted: an earlier interface failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub interface_index: u32,
+    pub status: BatchStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    /// True when every interface was changed and nothing was rolled back.
+    pub success: bool,
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Clone, Copy)]
+enum BatchOperation {
+    Set,
+    Clear,
+}
+
+/// `set_dns` on every target. Takes the same arguments, with `targets` in place of
+/// `interface_index`.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command(rename_all = "snake_case")]
+pub async fn set_dns_batch(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    targets: InterfaceTargets,
+    dns_servers: Vec<String>,
+    dns_type: String,
+    bootstrap_ip: Option<String>,
+    bootstrap_resolver: Option<dns_server::BootstrapResolverInfo>,
+    proxy_policy: Option<ProxyPolicy>,
+    upstream_options: Option<UpstreamOptions>,
+) -> AppResult<BatchReport> {
+    let activation = DnsActivation {
+        dns_servers,
+        dns_type,
+        bootstrap_ip,
+        bootstrap_resolver,
+        proxy_policy: proxy_policy.unwrap_or_default(),
+        upstream_options: upstream_options.unwrap_or_default(),
+    };
+    run_batch(&app_state, platform::system(), targets, Some(activation))
+        .await
+        .log_err("set_dns_batch")
+}
+
+/// `clear_dns` on every target.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn clear_dns_batch(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    targets: InterfaceTargets,
+) -> AppResult<BatchReport> {
+    run_batch(&app_state, platform::system(), targets, None)
+        .await
+        .log_err("clear_dns_batch")
+}
+
+/// The targets as interface indexes, without duplicates.
+pub(crate) fn resolve_targets(
+    net: &dyn DnsConfigurator,
+    targets: &InterfaceTargets,
+) -> AppResult<Vec<u32>> {
+    let mut indexes: Vec<u32> = Vec::new();
+    match targets {
+        InterfaceTargets::Interfaces(list) => {
+            for &index in list {
+                let index = net.resolve_interface_index(index)?;
+                if !indexes.contains(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        InterfaceTargets::Select(selector) => {
+            indexes.extend(
+                net.list_interfaces()?
+                    .iter()
+                    .filter(|iface| selector.selects(iface))
+                    .map(|iface| iface.interface_index),
+            );
+        }
+    }
+    if indexes.is_empty() {
+        return Err(AppError::invalid(
+            "No network interface matches the selection.",
+        ));
+    }
+    Ok(indexes)
+}
+
+/// Applies `activation` to every target, or clears them all when it is `None`.
+pub(crate) async fn run_batch(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+    targets: InterfaceTargets,
+    activation: Option<DnsActivation>,
+) -> AppResult<BatchReport> {
+    let indexes = resolve_targets(net, &targets)?;
+    let operation = match activation {
+        Some(_) => BatchOperation::Set,
+        None => BatchOperation::Clear,
+    };
+    // An activation on the followed adapter stops it following; a rollback has to
+    // undo that as well.
+    let followed_route = app_state.lock().await.followed_route.clone();
+
+    let mut results: Vec<BatchResult> = Vec::with_capacity(indexes.len());
+    // Interfaces changed so far, with the activation each had before.
+    let mut changed: Vec<(u32, Option<DnsActivation>)> = Vec::new();
+    let mut failed = false;
+
+    for &interface_index in &indexes {
+        if failed {
+            results.push(BatchResult {
+                interface_index,
+                status: BatchStatus::Skipped,
+                error: None,
+            });
+            continue;
+        }
+        let previous = app_state.lock().await.profiles.activation(interface_index);
+        let outcome = match &activation {
+            Some(activation) => {
+                set_dns_inner(app_state, net, interface_index, activation.clone()).await
+            }
+            None => clear_dns_inner(app_state, net, interface_index).await,
+        };
+        match outcome {
+            Ok(()) => {
+                changed.push((interface_index, previous));
+                results.push(BatchResult {
+                    interface_index,
+                    status: BatchStatus::Applied,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                warn!(
+                    "Batch change failed on interface {}; rolling back the {} already changed: {}",
+                    interface_index,
+                    changed.len(),
+                    e
+                );
+                failed = true;
+                results.push(BatchResult {
+                    interface_index,
+                    status: BatchStatus::Failed,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+
+    if failed {
+        // Newest first, so each interface goes back through the states it went through.
+        for (interface_index, previous) in changed.into_iter().rev() {
+            let (status, error) =
+                match undo(app_state, net, operation, interface_index, previous).await {
+                    Ok(()) => (BatchStatus::RolledBack, None),
+                    Err(e) => {
+                        error!(
+                            "Could not roll back interface {} after a failed batch: {}",
+                            interface_index, e
+                        );
+                        (BatchStatus::RollbackFailed, Some(e.to_string()))
+                    }
+                };
+            if let Some(result) = results
+                .iter_mut()
+                .find(|result| result.interface_index == interface_index)
+            {
+                result.status = status;
+                result.error = error;
+            }
+        }
+        app_state.lock().await.followed_route = followed_route;
+    } else {
+        info!(
+            "Batch {} applied to interfaces {:?}",
+            match operation {
+                BatchOperation::Set => "activation",
+                BatchOperation::Clear => "clear",
+            },
+            indexes
+        );
+    }
+
+    Ok(BatchReport {
+        success: !failed,
+        results,
+    })
+}
+
+/// Puts one interface back the way it was before the batch changed it.
+async fn undo(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+    operation: BatchOperation,
+    interface_index: u32,
+    previous: Option<DnsActivation>,
+) -> AppResult<()> {
+    match (operation, previous) {
+        (_, Some(previous)) => set_dns_inner(app_state, net, interface_index, previous).await,
+        (BatchOperation::Set, None) => clear_dns_inner(app_state, net, interface_index).await,
+        // Cleared an interface that had nothing of ours: it has its own DNS, as before.
+        (BatchOperation::Clear, None) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_fixtures::{app_state, ips, plain};
+    use crate::platform::mock::MockNetwork;
+
+    fn statuses(report: &BatchReport) -> Vec<(u32, BatchStatus)> {
+        report
+            .results
+            .iter()
+            .map(|result| (result.interface_index, result.status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn the_default_selector_takes_every_adapter_that_is_up() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .interface(9, &["192.168.2.1"])
+            .interface(11, &["192.168.3.1"])
+            .interface(13, &["172.17.0.1"])
+            .kind(13, AdapterKind::Virtual)
+            .leak();
+        net.set_adapter_enabled(11, false).unwrap();
+        let state = app_state();
+
+        let targets = InterfaceTargets::Select(InterfaceSelector::default());
+        let report = run_batch(&state, net, targets, Some(plain(&["1.1.1.1"])))
+            .await
+            .unwrap();
+        assert!(report.success);
+        assert_eq!(
+            statuses(&report),
+            vec![(7, BatchStatus::Applied), (9, BatchStatus::Applied)]
+        );
+        assert_eq!(net.servers(9), ips(&["1.1.1.1"]));
+        assert_eq!(net.servers(11), ips(&["192.168.3.1"]));
+        assert_eq!(net.servers(13), ips(&["172.17.0.1"]));
+
+        let targets = InterfaceTargets::Interfaces(vec![7, 9]);
+        assert!(run_batch(&state, net, targets, None).await.unwrap().success);
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert!(state.lock().await.profiles.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_failure_rolls_the_others_back() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .interface(9, &["192.168.2.1"])
+            .leak();
+        let state = app_state();
+        set_dns_inner(&state, net, 9, plain(&["9.9.9.9"]))
+            .await
+            .unwrap();
+
+        // Interface 42 does not exist.
+        let targets = InterfaceTargets::Interfaces(vec![7, 9, 42, 11]);
+        let report = run_batch(&state, net, targets, Some(plain(&["1.1.1.1"])))
+            .await
+            .unwrap();
+        assert!(!report.success);
+        assert_eq!(
+            statuses(&report),
+            vec![
+                (7, BatchStatus::RolledBack),
+                (9, BatchStatus::RolledBack),
+                (42, BatchStatus::Failed),
+                (11, BatchStatus::Skipped),
+            ]
+        );
+        assert!(report.results[2].error.is_some());
+
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert_eq!(net.servers(9), ips(&["9.9.9.9"]));
+        let profiles = state.lock().await.profiles.list();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].servers, vec!["9.9.9.9"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_encrypted_batch_stops_the_proxies_it_started() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        let state = app_state();
+        let encrypted = DnsActivation {
+            dns_type: "doh".to_string(),
+            bootstrap_ip: Some("127.0.0.1".to_string()),
+            ..plain(&["https://dns.example/dns-query"])
+        };
+
+        // Interface 42 does not exist.
+        let targets = InterfaceTargets::Interfaces(vec![7, 42]);
+        let report = run_batch(&state, net, targets, Some(encrypted))
+            .await
+            .unwrap();
+        assert!(!report.success);
+        assert_eq!(
+            statuses(&report),
+            vec![(7, BatchStatus::RolledBack), (42, BatchStatus::Failed)]
+        );
+
+        // 7 did point at a proxy before it was put back.
+        let proxy_v4 = format!("set V4 [{}]", platform::proxy_v4(0));
+        assert!(net.calls().contains(&proxy_v4), "{:?}", net.calls());
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert!(state.lock().await.profiles.list().is_empty());
+    }
+
+    #[test]
+    fn an_empty_selection_is_refused() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        net.set_adapter_enabled(7, false).unwrap();
+        let targets = InterfaceTargets::Select(InterfaceSelector::default());
+        assert!(resolve_targets(net, &targets).is_err());
+
+        let targets = InterfaceTargets::Interfaces(vec![7, 0, 7]);
+        assert_eq!(resolve_targets(net, &targets).unwrap(), vec![7]);
+    }
+}
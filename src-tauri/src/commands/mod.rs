@@ -1,3 +1,7 @@
 pub mod app_logs;
 pub mod dns;
+pub mod dns_batch;
 pub mod net_interfaces;
+pub mod network_profiles;
+#[cfg(test)]
+pub mod test_fixtures;
@@ -1,15 +1,14 @@
 use crate::error::{AppError, AppResult, LogErr};
-use crate::net_interfaces::general;
-use crate::win;
+use crate::platform::{self, NetworkInterface};
 use log::info;
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn change_interface_state(interface_idx: u32, enable: bool) -> AppResult<()> {
-    // Runs on a dedicated blocking thread: SetupAPI's class installer synchronously
-    // tears the adapter down and brings it back up, which takes long enough to stall
-    // the async runtime if it ran there.
+    // Runs on a dedicated blocking thread: on Windows, SetupAPI's class installer
+    // synchronously tears the adapter down and brings it back up, which takes long
+    // enough to stall the async runtime if it ran there.
     let result = tauri::async_runtime::spawn_blocking(move || {
-        general::set_interface_enabled(interface_idx, enable)
+        platform::system().set_adapter_enabled(interface_idx, enable)
     })
     .await
     .map_err(|e| AppError::Task(e.to_string()))?
@@ -26,9 +25,10 @@ pub async fn change_interface_state(interface_idx: u32, enable: bool) -> AppResu
 }
 
 #[tauri::command(rename_all = "snake_case")]
-pub fn get_best_interface() -> AppResult<win::adapters::NetworkInterface> {
-    let idx = win::adapters::best_interface_index()?;
-    win::adapters::list_interfaces()?
+pub fn get_best_interface() -> AppResult<NetworkInterface> {
+    let net = platform::system();
+    let idx = net.best_interface_index()?;
+    net.list_interfaces()?
         .into_iter()
         .find(|i| i.interface_index == idx)
         .ok_or(AppError::InterfaceNotFound(idx))
@@ -41,8 +41,9 @@ pub fn get_best_interface() -> AppResult<win::adapters::NetworkInterface> {
 /// failure would be worse than showing nothing, so the error is logged and swallowed
 /// here deliberately.
 #[tauri::command(rename_all = "snake_case")]
-pub fn get_interfaces() -> Vec<win::adapters::NetworkInterface> {
-    win::adapters::list_interfaces()
+pub fn get_interfaces() -> Vec<NetworkInterface> {
+    platform::system()
+        .list_interfaces()
         .log_err("get_interfaces")
         .unwrap_or_default()
 }
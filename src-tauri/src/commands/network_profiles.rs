@@ -0,0 +1,578 @@
+//! Network-aware profiles: "home Wi-Fi gets the filtering DoH server, the office gets
+//! its own DNS, anything else gets Quad9 over DoT", applied without the user doing
+//! anything.
+//!
+//! Each profile names the network it is for by a [`NetworkFingerprint`] — gateway IP
+//! and MAC, DNS suffix, SSID — where only the parts that are filled in have to match.
+//! A profile with none filled in matches every network, which makes it the fallback.
+//! When several match, the one that names the most parts wins. Profiles are checked on
+//! every settled network change, against the interface carrying the default route.
+//!
+//! A profile's client certificate and HTTP headers are not written to
+//! `network_profiles.json`, nor returned by `get_network_profiles`: they go to the OS
+//! credential store, under the profile's ID, and are read back on start-up.
+
+use std::collections::BTreeMap;
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{Emitter, Manager};
+use tauri_plugin_store::StoreExt;
+use tokio::sync::Mutex;
+
+use super::dns::{clear_dns_inner, set_dns_inner, DnsActivation};
+use crate::dns::dns_events::{NetworkProfileApplied, NETWORK_PROFILE_APPLIED_EVENT};
+use crate::dns::dns_tls::ClientCertificate;
+use crate::error::{AppError, AppResult, LogErr};
+use crate::platform::{self, DnsConfigurator, NetworkFingerprint};
+use crate::AppState;
+
+const STORE: &str = "network_profiles.json";
+
+/// The credential-store service profile secrets are filed under, one entry per profile.
+const CREDENTIAL_SERVICE: &str = "ir.betterdnsjumper.app.network-profiles";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// The networks it is for. Parts left empty match anything.
+    #[serde(default)]
+    pub network: NetworkFingerprint,
+    /// What to apply, with the same fields `set_dns` takes. `None` gives the network
+    /// its own DNS back.
+    pub dns: Option<DnsActivation>,
+}
+
+impl NetworkProfile {
+    /// How many of the profile's parts the network matches, or `None` if it differs in
+    /// any of them.
+    fn specificity(&self, network: &NetworkFingerprint) -> Option<usize> {
+        let wanted = &self.network;
+        let parts = [
+            same(&wanted.gateway_ip, &network.gateway_ip, |a, b| a == b),
+            same(&wanted.gateway_mac, &network.gateway_mac, |a, b| {
+                a.replace('-', ":")
+                    .eq_ignore_ascii_case(&b.replace('-', ":"))
+            }),
+            same(&wanted.dns_suffix, &network.dns_suffix, |a, b| {
+                a.trim_end_matches('.')
+                    .eq_ignore_ascii_case(b.trim_end_matches('.'))
+            }),
+            // SSIDs are case-sensitive; "Home" and "home" are two networks.
+            same(&wanted.ssid, &network.ssid, |a, b| a == b),
+        ];
+        parts.into_iter().try_fold(0, |count, part| match part {
+            Some(true) => Some(count + 1),
+            Some(false) => None,
+            None => Some(count),
+        })
+    }
+}
+
+/// The parts of a profile's activation that `UpstreamOptions` never serializes: the
+/// client certificate, with its PKCS#12 password or inline key, and the HTTP headers,
+/// which carry `Authorization` tokens. Not `Debug`, so they stay out of the logs too.
+#[derive(Default, Serialize, Deserialize)]
+struct ProfileSecrets {
+    #[serde(default)]
+    client_certificate: Option<ClientCertificate>,
+    #[serde(default)]
+    http_headers: BTreeMap<String, String>,
+}
+
+impl ProfileSecrets {
+    /// The profile's secrets, or `None` if it has none.
+    fn of(profile: &NetworkProfile) -> Option<Self> {
+        let options = &profile.dns.as_ref()?.upstream_options;
+        if options.client_certificate.is_none() && options.http_headers.is_empty() {
+            return None;
+        }
+        Some(Self {
+            client_certificate: options.client_certificate.clone(),
+            http_headers: options.http_headers.clone(),
+        })
+    }
+
+    fn fill_in(self, profile: &mut NetworkProfile) {
+        if let Some(activation) = profile.dns.as_mut() {
+            activation.upstream_options.client_certificate = self.client_certificate;
+            activation.upstream_options.http_headers = self.http_headers;
+        }
+    }
+}
+
+/// Writes the profile's secrets to the credential store, or removes its entry when it
+/// has none. Blocking: a D-Bus round trip on Linux.
+fn store_secrets(profile_id: &str, secrets: Option<&ProfileSecrets>) -> AppResult<()> {
+    let entry = credential(profile_id)?;
+    match secrets {
+        Some(secrets) => entry
+            .set_password(&serde_json::to_string(secrets)?)
+            .map_err(credential_error),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(credential_error(e)),
+        },
+    }
+}
+
+/// Reads the profile's secrets back. Blocking, like [`store_secrets`].
+fn load_secrets(profile_id: &str) -> AppResult<Option<ProfileSecrets>> {
+    match credential(profile_id)?.get_password() {
+        Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(credential_error(e)),
+    }
+}
+
+fn credential(profile_id: &str) -> AppResult<keyring::Entry> {
+    keyring::Entry::new(CREDENTIAL_SERVICE, profile_id).map_err(credential_error)
+}
+
+fn credential_error(e: keyring::Error) -> AppError {
+    AppError::Store(format!("credential store: {}", e))
+}
+
+/// `None` if the profile does not care, otherwise whether the network matches.
+fn same<T>(wanted: &Option<T>, actual: &Option<T>, eq: impl Fn(&T, &T) -> bool) -> Option<bool> {
+    let wanted = wanted.as_ref()?;
+    Some(actual.as_ref().is_some_and(|actual| eq(wanted, actual)))
+}
+
+/// The profiles, and which one was applied last, so a notification for the network
+/// the machine is already on changes nothing.
+#[derive(Debug, Default)]
+pub struct NetworkProfiles {
+    pub profiles: Vec<NetworkProfile>,
+    applied: Option<(u32, String, NetworkFingerprint)>,
+}
+
+impl NetworkProfiles {
+    /// The enabled profile that matches `network` most specifically. On a tie, the one
+    /// listed first.
+    fn best_match(&self, network: &NetworkFingerprint) -> Option<&NetworkProfile> {
+        self.profiles
+            .iter()
+            .filter(|profile| profile.enabled)
+            .filter_map(|profile| Some((profile.specificity(network)?, profile)))
+            .rev()
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, profile)| profile)
+    }
+}
+
+/// Whether an enabled profile matches the network on the default-route interface. When
+/// one does, the default-route follower stands aside: the profile decides what that
+/// network gets, and moving "Auto" there first would only be a second activation on
+/// the same adapter.
+pub(crate) async fn profile_matches(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+) -> bool {
+    let Ok(interface_index) = net.best_interface_index() else {
+        return false;
+    };
+    let Ok(network) = net.network_fingerprint(interface_index) else {
+        return false;
+    };
+    app_state
+        .lock()
+        .await
+        .network_profiles
+        .best_match(&network)
+        .is_some()
+}
+
+/// Applies the profile matching the network on the default-route interface, unless it
+/// is already applied there. Without a matching profile nothing changes.
+pub(crate) async fn apply_network_profile(
+    app_state: &Mutex<AppState>,
+    net: &'static dyn DnsConfigurator,
+) -> AppResult<Option<NetworkProfileApplied>> {
+    let interface_index = match net.best_interface_index() {
+        Ok(index) => index,
+        Err(e) => {
+            debug!("No network to match profiles against: {}", e);
+            return Ok(None);
+        }
+    };
+    let network = net.network_fingerprint(interface_index)?;
+
+    let (profile, previous) = {
+        let state = app_state.lock().await;
+        let Some(profile) = state.network_profiles.best_match(&network).cloned() else {
+            return Ok(None);
+        };
+        let current = (interface_index, profile.id.clone(), network.clone());
+        if state.network_profiles.applied.as_ref() == Some(&current) {
+            return Ok(None);
+        }
+        (profile, state.network_profiles.applied.clone())
+    };
+
+    info!(
+        "Network on interface {} matches profile \"{}\": {:?}",
+        interface_index, profile.name, network
+    );
+    let active = app_state.lock().await.profiles.is_active(interface_index);
+    match profile.dns.clone() {
+        Some(activation) => set_dns_inner(app_state, net, interface_index, activation).await?,
+        // Nothing of ours on the interface means it already has the network's own DNS;
+        // restoring would only throw away servers the user set by hand.
+        None if active => clear_dns_inner(app_state, net, interface_index).await?,
+        None => {}
+    }
+    app_state.lock().await.network_profiles.applied =
+        Some((interface_index, profile.id.clone(), network.clone()));
+
+    // The default route moved to another adapter. The one it left keeps what the last
+    // profile gave it otherwise — usually it is disconnected, and this is a no-op.
+    if let Some((previous_index, _, _)) = previous {
+        if previous_index != interface_index
+            && app_state.lock().await.profiles.is_active(previous_index)
+        {
+            if let Err(e) = clear_dns_inner(app_state, net, previous_index).await {
+                warn!(
+                    "Could not restore interface {} after the network moved to interface {}: {}",
+                    previous_index, interface_index, e
+                );
+            }
+        }
+    }
+
+    Ok(Some(NetworkProfileApplied {
+        interface_index,
+        profile_id: profile.id,
+        profile_name: profile.name,
+        network,
+    }))
+}
+
+/// Applies the matching profile and tells the frontend. Errors are logged: this runs
+/// from the network-change handler and after the profiles are edited, where nobody is
+/// waiting on the result.
+pub(crate) async fn apply_and_report(app: &tauri::AppHandle, app_state: &Mutex<AppState>) {
+    match apply_network_profile(app_state, platform::system()).await {
+        Ok(Some(applied)) => {
+            if let Err(e) = app.emit(NETWORK_PROFILE_APPLIED_EVENT, &applied) {
+                warn!("Could not emit {}: {}", NETWORK_PROFILE_APPLIED_EVENT, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Could not apply the network profile: {}", e),
+    }
+}
+
+/// Loads the saved profiles into `AppState` and applies the one matching the network
+/// the machine starts on. Called once from `setup`.
+pub fn load(app: &tauri::AppHandle) {
+    let profiles: Vec<NetworkProfile> = match app.store_builder(STORE).build() {
+        Ok(store) => match store.get("profiles") {
+            Some(value) => match serde_json::from_value(value) {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    warn!("Ignoring unreadable network profiles: {}", e);
+                    return;
+                }
+            },
+            None => return,
+        },
+        Err(e) => {
+            warn!("Could not open {}: {}", STORE, e);
+            return;
+        }
+    };
+    info!("Loading {} network profiles from store", profiles.len());
+    // `setup` runs inside the async runtime, where blocking on the lock would panic.
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let profiles = tauri::async_runtime::spawn_blocking(move || {
+            let mut profiles = profiles;
+            for profile in profiles.iter_mut().filter(|profile| profile.dns.is_some()) {
+                match load_secrets(&profile.id) {
+                    Ok(Some(secrets)) => secrets.fill_in(profile),
+                    Ok(None) => {}
+                    // The profile still applies; a server that wants the certificate or
+                    // headers will refuse it, and says so.
+                    Err(e) => warn!(
+                        "Could not read the secrets of network profile \"{}\": {}",
+                        profile.name, e
+                    ),
+                }
+            }
+            profiles
+        })
+        .await;
+        let profiles = match profiles {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                error!("Loading network profiles did not complete: {}", e);
+                return;
+            }
+        };
+        let app_state = app.state::<Mutex<AppState>>();
+        app_state.lock().await.network_profiles.profiles = profiles;
+        apply_and_report(&app, &app_state).await;
+    });
+}
+
+/// The fingerprint of the network an interface is on, for saving the current network
+/// as a profile. `0` is the default-route interface, as everywhere else.
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_network_fingerprint(interface_index: u32) -> AppResult<NetworkFingerprint> {
+    let net = platform::system();
+    let interface_index = net.resolve_interface_index(interface_index)?;
+    net.network_fingerprint(interface_index)
+        .log_err("get_network_fingerprint")
+}
+
+/// The profiles, without their client certificates and HTTP headers.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn get_network_profiles(
+    app_state: tauri::State<'_, Mutex<AppState>>,
+) -> AppResult<Vec<NetworkProfile>> {
+    Ok(app_state.lock().await.network_profiles.profiles.clone())
+}
+
+/// Adds the profile, or replaces the one with the same `id`, and applies whatever now
+/// matches the current network. The client certificate and HTTP headers have to be
+/// sent with every save: `get_network_profiles` leaves them out, and a save without
+/// them removes them.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn save_network_profile(
+    app_handle: tauri::AppHandle,
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    profile: NetworkProfile,
+) -> AppResult<()> {
+    let id = profile.id.clone();
+    let secrets = ProfileSecrets::of(&profile);
+    tauri::async_runtime::spawn_blocking(move || store_secrets(&id, secrets.as_ref()))
+        .await
+        .map_err(|e| AppError::Task(e.to_string()))?
+        .log_err("save_network_profile")?;
+    {
+        let mut state = app_state.lock().await;
+        let profiles = &mut state.network_profiles;
+        match profiles.profiles.iter_mut().find(|p| p.id == profile.id) {
+            Some(existing) => *existing = profile,
+            None => profiles.profiles.push(profile),
+        }
+        // Edited: applying it again is the point.
+        profiles.applied = None;
+    }
+    persist(&app_handle, &app_state)
+        .await
+        .log_err("save_network_profile")?;
+    apply_and_report(&app_handle, &app_state).await;
+    Ok(())
+}
+
+/// Removes the profile. Whatever it applied stays until the next network change.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn delete_network_profile(
+    app_handle: tauri::AppHandle,
+    app_state: tauri::State<'_, Mutex<AppState>>,
+    id: String,
+) -> AppResult<()> {
+    app_state
+        .lock()
+        .await
+        .network_profiles
+        .profiles
+        .retain(|profile| profile.id != id);
+    persist(&app_handle, &app_state)
+        .await
+        .log_err("delete_network_profile")?;
+    tauri::async_runtime::spawn_blocking(move || store_secrets(&id, None))
+        .await
+        .map_err(|e| AppError::Task(e.to_string()))?
+        .log_err("delete_network_profile")
+}
+
+/// Writes the profiles to the store. Their secrets are not serialized; see
+/// [`ProfileSecrets`].
+async fn persist(app_handle: &tauri::AppHandle, app_state: &Mutex<AppState>) -> AppResult<()> {
+    let profiles = app_state.lock().await.network_profiles.profiles.clone();
+    let store = app_handle
+        .store_builder(STORE)
+        .build()
+        .map_err(|e| AppError::Store(format!("could not open {}: {}", STORE, e)))?;
+    store.set("profiles", serde_json::to_value(&profiles)?);
+    store
+        .save()
+        .map_err(|e| AppError::Store(format!("could not write {}: {}", STORE, e)))?;
+    debug!("Persisted {} network profiles", profiles.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_fixtures::{self, ips, plain};
+    use crate::platform::mock::MockNetwork;
+
+    fn with_profiles(profiles: Vec<NetworkProfile>) -> Mutex<AppState> {
+        let mut state = test_fixtures::app_state();
+        state.get_mut().network_profiles.profiles = profiles;
+        state
+    }
+
+    fn home() -> NetworkFingerprint {
+        NetworkFingerprint {
+            gateway_ip: Some("192.168.1.1".parse().unwrap()),
+            gateway_mac: Some("A4:2B:B0:12:34:56".to_string()),
+            dns_suffix: None,
+            ssid: Some("Home".to_string()),
+        }
+    }
+
+    fn office() -> NetworkFingerprint {
+        NetworkFingerprint {
+            gateway_ip: Some("10.0.0.1".parse().unwrap()),
+            gateway_mac: Some("00:11:22:33:44:55".to_string()),
+            dns_suffix: Some("corp.example.".to_string()),
+            ssid: None,
+        }
+    }
+
+    fn profile(id: &str, network: NetworkFingerprint, servers: Option<&[&str]>) -> NetworkProfile {
+        NetworkProfile {
+            id: id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            network,
+            dns: servers.map(plain),
+        }
+    }
+
+    #[test]
+    fn the_most_specific_match_wins() {
+        let profiles = NetworkProfiles {
+            profiles: vec![
+                profile("anywhere", NetworkFingerprint::default(), None),
+                profile(
+                    "home-ssid",
+                    NetworkFingerprint {
+                        ssid: Some("Home".to_string()),
+                        ..NetworkFingerprint::default()
+                    },
+                    None,
+                ),
+                profile(
+                    "home-router",
+                    NetworkFingerprint {
+                        gateway_mac: Some("a4-2b-b0-12-34-56".to_string()),
+                        ssid: Some("Home".to_string()),
+                        ..NetworkFingerprint::default()
+                    },
+                    None,
+                ),
+                profile(
+                    "office",
+                    NetworkFingerprint {
+                        dns_suffix: Some("CORP.example".to_string()),
+                        ..NetworkFingerprint::default()
+                    },
+                    None,
+                ),
+            ],
+            applied: None,
+        };
+
+        assert_eq!(profiles.best_match(&home()).unwrap().id, "home-router");
+        assert_eq!(profiles.best_match(&office()).unwrap().id, "office");
+        let cafe = NetworkFingerprint {
+            ssid: Some("home".to_string()),
+            ..NetworkFingerprint::default()
+        };
+        assert_eq!(profiles.best_match(&cafe).unwrap().id, "anywhere");
+    }
+
+    #[test]
+    fn disabled_profiles_never_match() {
+        let mut home_profile = profile("home", home(), None);
+        home_profile.enabled = false;
+        let profiles = NetworkProfiles {
+            profiles: vec![home_profile],
+            applied: None,
+        };
+        assert!(profiles.best_match(&home()).is_none());
+    }
+
+    #[test]
+    fn secrets_stay_out_of_the_store() {
+        let mut saved = profile("office", office(), Some(&["10.0.0.53"]));
+        let options = &mut saved.dns.as_mut().unwrap().upstream_options;
+        options.client_certificate = Some(ClientCertificate::Pkcs12 {
+            path: "C:\\certs\\me.p12".to_string(),
+            password: "hunter2".to_string(),
+        });
+        options.http_headers =
+            BTreeMap::from([("Authorization".to_string(), "Bearer token".to_string())]);
+
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(!json.contains("hunter2"), "{}", json);
+        assert!(!json.contains("Bearer"), "{}", json);
+
+        let secrets = ProfileSecrets::of(&saved).unwrap();
+        let mut loaded: NetworkProfile = serde_json::from_str(&json).unwrap();
+        assert!(ProfileSecrets::of(&loaded).is_none());
+        secrets.fill_in(&mut loaded);
+        let options = &loaded.dns.unwrap().upstream_options;
+        assert!(matches!(
+            options.client_certificate,
+            Some(ClientCertificate::Pkcs12 { ref password, .. }) if password == "hunter2"
+        ));
+        assert_eq!(options.http_headers["Authorization"], "Bearer token");
+    }
+
+    #[tokio::test]
+    async fn each_network_gets_its_profile() {
+        let net = MockNetwork::new().interface(7, &["192.168.1.1"]).leak();
+        net.join_network(7, home());
+        let state = with_profiles(vec![
+            profile("home", home(), Some(&["9.9.9.9"])),
+            profile("office", office(), None),
+            profile(
+                "anywhere",
+                NetworkFingerprint::default(),
+                Some(&["1.1.1.1"]),
+            ),
+        ]);
+
+        assert!(profile_matches(&state, net).await);
+        let applied = apply_network_profile(&state, net).await.unwrap().unwrap();
+        assert_eq!(applied.profile_id, "home");
+        assert_eq!(net.servers(7), ips(&["9.9.9.9"]));
+        // Same network again: nothing to do.
+        assert!(apply_network_profile(&state, net).await.unwrap().is_none());
+
+        net.join_network(7, office());
+        let applied = apply_network_profile(&state, net).await.unwrap().unwrap();
+        assert_eq!(applied.profile_id, "office");
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+
+        net.join_network(7, NetworkFingerprint::default());
+        let applied = apply_network_profile(&state, net).await.unwrap().unwrap();
+        assert_eq!(applied.profile_id, "anywhere");
+        assert_eq!(net.servers(7), ips(&["1.1.1.1"]));
+    }
+
+    #[tokio::test]
+    async fn without_a_match_nothing_changes() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["10.0.0.53"])
+            .leak();
+        let state = with_profiles(vec![
+            profile("home", home(), Some(&["9.9.9.9"])),
+            profile("office", office(), None),
+        ]);
+
+        assert!(!profile_matches(&state, net).await);
+        assert!(apply_network_profile(&state, net).await.unwrap().is_none());
+        assert_eq!(net.servers(7), ips(&["10.0.0.53"]));
+    }
+}
@@ -0,0 +1,49 @@
+//! The state and activations the command tests share.
+//!
+//! [`app_state`] is an `AppState` with nothing active, whose proxies listen on an
+//! ephemeral port rather than 53, so an encrypted activation can really start one.
+//! Query logs and proxy events go nowhere.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, Mutex, RwLock};
+
+use super::dns::DnsActivation;
+use crate::dns::dns_profiles::InterfaceProfiles;
+use crate::dns::dns_rules::DnsRules;
+use crate::dns::dns_types::ProxyPolicy;
+use crate::dns::dns_upstream::UpstreamOptions;
+use crate::AppState;
+
+pub fn app_state() -> Mutex<AppState> {
+    let (log_sender, _) = mpsc::unbounded_channel();
+    let (event_sender, _) = mpsc::unbounded_channel();
+    let mut profiles = InterfaceProfiles::new(
+        log_sender,
+        event_sender,
+        Arc::new(RwLock::new(DnsRules::new())),
+    );
+    profiles.listen_port = 0;
+    Mutex::new(AppState {
+        profiles,
+        followed_route: None,
+        network_profiles: Default::default(),
+    })
+}
+
+/// Plain DNS with these servers.
+pub fn plain(servers: &[&str]) -> DnsActivation {
+    DnsActivation {
+        dns_servers: servers.iter().map(|s| s.to_string()).collect(),
+        dns_type: "dns".to_string(),
+        bootstrap_ip: None,
+        bootstrap_resolver: None,
+        proxy_policy: ProxyPolicy::default(),
+        upstream_options: UpstreamOptions::default(),
+    }
+}
+
+pub fn ips(list: &[&str]) -> Vec<IpAddr> {
+    list.iter().map(|s| s.parse().unwrap()).collect()
+}
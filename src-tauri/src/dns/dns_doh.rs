@@ -0,0 +1,365 @@
+//! A DNS-over-HTTPS client for entries hickory's built-in DoH cannot serve: extra
+//! request headers, or GET instead of POST.
+//!
+//! hickory only sends RFC 8484 POSTs with a fixed set of headers. Some providers
+//! identify the profile by a header, some filtering proxies only let `GET ?dns=`
+//! through, so entries that set either go through this client instead. Everything
+//! else — plain `https://`, `tls://`, `quic://`, `h3://` — stays on hickory.
+//!
+//! The TLS side is the entry's own `dns_tls` config, so pins, extra CAs and client
+//! certificates apply here exactly as they do for hickory, and so do the timeout,
+//! retries and answer cache. HTTP/3 and DNSSEC validation are not available on this
+//! path; `UpstreamOptions::check_custom_doh` refuses entries that ask for them rather
+//! than quietly dropping them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query};
+use hickory_proto::rr::{Name, Record, RecordType};
+use hickory_proto::xfer::DnsResponse;
+use hickory_proto::ProtoError;
+use hickory_resolver::ResolveError;
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+
+use super::dns_tls;
+use super::dns_upstream::UpstreamOptions;
+
+/// RFC 8484 media type, for both the request body and the `Accept` header.
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// How queries are put on the wire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DohMethod {
+    /// The query as the request body. What hickory does.
+    #[default]
+    Post,
+    /// The query base64url-encoded in a `dns` query parameter.
+    Get,
+}
+
+/// Validates the entry's headers and turns them into a `HeaderMap`.
+pub fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("'{}' is not a valid HTTP header name.", name))?;
+        let header_value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("The value of HTTP header '{}' is not valid.", name))?;
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// A cached answer and when it expires.
+type CachedAnswer = (Instant, Vec<Record>);
+
+/// Answers kept until their TTL runs out, so repeated names skip the round trip the
+/// way they do with hickory's cache.
+struct AnswerCache {
+    capacity: usize,
+    entries: Mutex<HashMap<(Name, RecordType), CachedAnswer>>,
+}
+
+impl AnswerCache {
+    fn get(&self, key: &(Name, RecordType)) -> Option<Vec<Record>> {
+        let entries = self.entries.lock().ok()?;
+        let (expires, records) = entries.get(key)?;
+        (*expires > Instant::now()).then(|| records.clone())
+    }
+
+    fn insert(&self, key: (Name, RecordType), records: &[Record]) {
+        let Some(ttl) = records.iter().map(Record::ttl).min() else {
+            return;
+        };
+        if self.capacity == 0 || ttl == 0 {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if entries.len() >= self.capacity {
+            entries.retain(|_, (expires, _)| *expires > now);
+        }
+        if entries.len() >= self.capacity {
+            // Still full of live answers: make room by dropping the one closest to
+            // expiring.
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(
+            key,
+            (now + Duration::from_secs(ttl.into()), records.to_vec()),
+        );
+    }
+}
+
+/// There is a single address per client, so `concurrent_requests`, which spreads
+/// queries over several name servers, has nothing to apply to — as with hickory given
+/// one name server.
+pub struct DohClient {
+    url: url::Url,
+    addr: Option<SocketAddr>,
+    method: DohMethod,
+    edns0: bool,
+    /// Retries after a request that got no DNS answer at all.
+    attempts: usize,
+    cache: AnswerCache,
+    client: reqwest::Client,
+}
+
+impl DohClient {
+    /// `url` is the full endpoint. `addr`, when given, is where the host is connected
+    /// to instead of being looked up — the proxy must never resolve its own upstream
+    /// through the system resolver, which by then is the proxy itself.
+    pub fn new(
+        url: url::Url,
+        addr: Option<SocketAddr>,
+        options: &UpstreamOptions,
+    ) -> Result<Self, String> {
+        let host = url
+            .host_str()
+            .ok_or(format!("'{}' has no host.", url))?
+            .to_string();
+
+        let mut tls = dns_tls::client_config(&host, options)?
+            .unwrap_or_else(hickory_proto::rustls::client_config);
+        tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let mut builder = reqwest::Client::builder()
+            .use_preconfigured_tls(tls)
+            .default_headers(header_map(&options.http_headers)?)
+            .timeout(options.timeout())
+            .no_proxy();
+        if let Some(addr) = addr {
+            builder = builder.resolve(&host, addr);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| format!("Failed to build the DoH client for {}: {}", url, e))?;
+
+        Ok(Self {
+            url,
+            addr,
+            method: options.doh_method,
+            edns0: options.edns0,
+            attempts: options.attempts,
+            cache: AnswerCache {
+                capacity: options.cache_size,
+                entries: Mutex::new(HashMap::new()),
+            },
+            client,
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Looks `name` up and returns the answer section, with NXDOMAIN and empty answers
+    /// reported as errors the same way hickory reports them.
+    pub async fn lookup(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, ResolveError> {
+        let name = Name::from_ascii(name)?;
+        let key = (name, record_type);
+        if let Some(records) = self.cache.get(&key) {
+            return Ok(records);
+        }
+
+        let mut tries_left = self.attempts;
+        let response = loop {
+            match self.query(key.0.clone(), record_type).await {
+                Ok(response) => break response,
+                Err(e) if tries_left > 0 => {
+                    tries_left -= 1;
+                    debug!("{}; retrying", e);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let response = ProtoError::from_response(response, true)?;
+        let records = response.answers().to_vec();
+        self.cache.insert(key, &records);
+        Ok(records)
+    }
+
+    async fn query(&self, name: Name, record_type: RecordType) -> Result<DnsResponse, String> {
+        let mut message = Message::new();
+        // ID 0, as RFC 8484 recommends, so identical GETs are cacheable.
+        message
+            .set_id(0)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(name, record_type));
+        if self.edns0 {
+            let mut edns = Edns::new();
+            edns.set_max_payload(1232);
+            message.set_edns(edns);
+        }
+        let wire = message
+            .to_vec()
+            .map_err(|e| format!("Failed to encode DNS query: {}", e))?;
+
+        let request = match self.method {
+            DohMethod::Post => self
+                .client
+                .post(self.url.clone())
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .body(wire),
+            DohMethod::Get => {
+                let mut url = self.url.clone();
+                url.query_pairs_mut()
+                    .append_pair("dns", &URL_SAFE_NO_PAD.encode(wire));
+                self.client.get(url)
+            }
+        };
+
+        let response = request
+            .header(ACCEPT, DNS_MESSAGE)
+            .send()
+            .await
+            .map_err(|e| format!("DoH request to {} failed: {}", self.url, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("{} answered HTTP {}", self.url, status));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read the DoH response from {}: {}", self.url, e))?;
+
+        DnsResponse::from_buffer(body.to_vec())
+            .map_err(|e| format!("{} sent an invalid DNS message: {}", self.url, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::RData;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A one-request plain-HTTP DoH stand-in. Answers every query with 192.0.2.1 and
+    /// hands back the raw request so the test can check what was sent.
+    async fn stand_in() -> (url::Url, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 8192];
+            let n = stream.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+
+            let dns = request
+                .split_once("dns=")
+                .map(|(_, rest)| rest.split([' ', '&']).next().unwrap().to_string())
+                .expect("GET with a dns parameter");
+            let query = Message::from_vec(&URL_SAFE_NO_PAD.decode(dns).unwrap()).unwrap();
+
+            let mut answer = Message::new();
+            answer
+                .set_id(query.id())
+                .set_message_type(MessageType::Response)
+                .set_response_code(ResponseCode::NoError)
+                .add_query(query.queries()[0].clone())
+                .add_answer(Record::from_rdata(
+                    query.queries()[0].name().clone(),
+                    60,
+                    RData::A(A::new(192, 0, 2, 1)),
+                ));
+            let body = answer.to_vec().unwrap();
+
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                DNS_MESSAGE,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+            request
+        });
+
+        (url.parse().unwrap(), handle)
+    }
+
+    #[tokio::test]
+    async fn get_mode_sends_headers_and_dns_parameter() {
+        let (url, server) = stand_in().await;
+        let options = UpstreamOptions {
+            doh_method: DohMethod::Get,
+            http_headers: BTreeMap::from([
+                ("User-Agent".to_string(), "dns-test/1".to_string()),
+                ("Authorization".to_string(), "Bearer profile".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let client = DohClient::new(url, None, &options).unwrap();
+        let records = client.lookup("example.com.", RecordType::A).await.unwrap();
+        assert_eq!(records.len(), 1);
+
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("get /dns-query?dns="));
+        assert!(request.contains("user-agent: dns-test/1"));
+        assert!(request.contains("authorization: bearer profile"));
+    }
+
+    #[tokio::test]
+    async fn a_repeated_name_is_answered_from_the_cache() {
+        // The stand-in serves a single request, so the second lookup can only succeed
+        // from the cache.
+        let (url, server) = stand_in().await;
+        let options = UpstreamOptions {
+            doh_method: DohMethod::Get,
+            ..Default::default()
+        };
+
+        let client = DohClient::new(url, None, &options).unwrap();
+        let first = client.lookup("example.com.", RecordType::A).await.unwrap();
+        server.await.unwrap();
+        let second = client.lookup("example.com.", RecordType::A).await.unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn refuses_settings_it_cannot_honour() {
+        let options = UpstreamOptions {
+            doh_method: DohMethod::Get,
+            ..Default::default()
+        };
+        assert!(options.check_custom_doh(false).is_ok());
+        assert!(options.check_custom_doh(true).is_err());
+        let validating = UpstreamOptions {
+            validate: true,
+            ..options
+        };
+        assert!(validating.check_custom_doh(false).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let headers = BTreeMap::from([("Bad Header".to_string(), "x".to_string())]);
+        assert!(header_map(&headers).is_err());
+    }
+}
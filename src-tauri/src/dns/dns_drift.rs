@@ -0,0 +1,118 @@
+//! Noticing when something else rewrites an interface's DNS while it is ours.
+//!
+//! DHCP renewals, VPN clients and group policy all write adapter DNS, and none of them
+//! know the proxy is there. Afterwards the UI still says "protected" while queries go
+//! wherever the other party pointed them. [`find_drift`] compares what each active
+//! interface has now with what was applied to it; the caller decides, per the user's
+//! [`DriftAction`], whether to write it back or only report it.
+
+use std::net::IpAddr;
+
+use log::debug;
+use serde::Deserialize;
+
+use super::dns_events::{DnsDrift, DriftSource};
+use crate::error::AppResult;
+use crate::platform::{self, DnsConfigurator, Family};
+
+/// The `setting.json` key the frontend stores the user's choice under.
+pub const DRIFT_ACTION_SETTING: &str = "dns_drift_action";
+
+/// What to do about an interface whose DNS was changed behind our back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftAction {
+    /// Report it and leave the adapter alone. Someone may have meant it.
+    #[default]
+    Notify,
+    /// Write the applied servers back, then report it.
+    Reapply,
+}
+
+/// Every family, on every active interface, whose servers no longer match what was
+/// applied. Families that were never written are not compared, and an interface that
+/// cannot be read — usually because it has just gone away — is skipped.
+///
+/// `applied` is [`InterfaceProfiles::applied`](super::dns_profiles::InterfaceProfiles::applied),
+/// copied out so the caller can run this off the async runtime.
+pub fn find_drift(applied: &[(u32, Vec<IpAddr>)], net: &dyn DnsConfigurator) -> Vec<DnsDrift> {
+    let mut drift = Vec::new();
+    for (interface_index, applied) in applied {
+        let interface_index = *interface_index;
+        let current = match net.interface_dns_servers(interface_index) {
+            Ok(current) => current,
+            Err(e) => {
+                debug!(
+                    "Skipping the drift check on interface {}: {}",
+                    interface_index, e
+                );
+                continue;
+            }
+        };
+        for family in [Family::V4, Family::V6] {
+            let expected = of_family(applied, family);
+            if expected.is_empty() {
+                continue;
+            }
+            // Windows lists its IPv6 anycast defaults next to whatever is set; they are
+            // not a change anyone made.
+            let found: Vec<IpAddr> = of_family(&current, family)
+                .into_iter()
+                .filter(|ip| !platform::is_default_ipv6_anycast(ip))
+                .collect();
+            if found != expected {
+                drift.push(DnsDrift {
+                    interface_index,
+                    family,
+                    expected,
+                    found,
+                    source: None,
+                    reapplied: false,
+                });
+            }
+        }
+    }
+    if !drift.is_empty() {
+        classify(net, &mut drift);
+    }
+    drift
+}
+
+/// Fills in [`DnsDrift::source`]. Listing interfaces is slow on Windows, so it is only
+/// done once something has drifted.
+fn classify(net: &dyn DnsConfigurator, drift: &mut [DnsDrift]) {
+    let interfaces = match net.list_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            debug!("Could not tell where the drifted DNS came from: {}", e);
+            return;
+        }
+    };
+    for drift in drift.iter_mut().filter(|drift| !drift.found.is_empty()) {
+        drift.source = interfaces
+            .iter()
+            .find(|interface| interface.interface_index == drift.interface_index)
+            .map(|interface| {
+                if interface.dns_is_static {
+                    DriftSource::Static
+                } else {
+                    DriftSource::Dhcp
+                }
+            });
+    }
+}
+
+/// Writes the applied servers back over whatever replaced them.
+pub fn reapply(net: &dyn DnsConfigurator, drift: &mut DnsDrift) -> AppResult<()> {
+    net.set_dns(drift.interface_index, drift.family, &drift.expected)?;
+    drift.reapplied = true;
+    Ok(())
+}
+
+fn of_family(servers: &[IpAddr], family: Family) -> Vec<IpAddr> {
+    servers
+        .iter()
+        .filter(|ip| Family::of(ip) == family)
+        .copied()
+        .collect()
+}
@@ -0,0 +1,153 @@
+//! Events the proxy raises for the frontend.
+//!
+//! The proxy runs inside tokio tasks that have no `AppHandle`, and it is constructed
+//! before the Tauri app exists. Same arrangement as the query log: the proxy holds the
+//! sending half of a channel, and `setup` starts a task that owns the receiver and
+//! emits each event as a Tauri event.
+
+use std::net::IpAddr;
+
+use log::warn;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc;
+
+use super::dns_tls::PinMismatch;
+use super::dns_types::ProxyStatus;
+use crate::platform::{Family, NetworkFingerprint};
+
+/// Emitted when the upstream starts failing and queries begin going to the fallback.
+/// Once per outage, not once per query.
+pub const UPSTREAM_FALLBACK_EVENT: &str = "upstream-fallback";
+
+/// Emitted when the upstream answers again after a fallback.
+pub const UPSTREAM_RECOVERED_EVENT: &str = "upstream-recovered";
+
+/// Emitted once the proxy is serving and the interface points at it. Carries the same
+/// payload as `get_proxy_status`.
+pub const PROXY_STARTED_EVENT: &str = "proxy-started";
+
+/// Emitted when the proxy is shut down on purpose.
+pub const PROXY_STOPPED_EVENT: &str = "proxy-stopped";
+
+/// Emitted when the proxy fails to start, stops serving on its own, or finds its
+/// upstream refusing the connection over a certificate — once per such outage.
+pub const PROXY_ERROR_EVENT: &str = "proxy-error";
+
+/// Emitted when an "Auto" activation has moved to the adapter now carrying the
+/// default route. Emitted by the follower directly, not through the proxy channel.
+pub const DEFAULT_ROUTE_FOLLOWED_EVENT: &str = "default-route-followed";
+
+/// Emitted when an active interface's DNS no longer matches what was applied, once per
+/// interface and family. Emitted by the drift check directly, not through the proxy
+/// channel.
+pub const DNS_DRIFT_DETECTED_EVENT: &str = "dns-drift-detected";
+
+/// Emitted when a network profile has been applied because the machine joined a
+/// network it matches.
+pub const NETWORK_PROFILE_APPLIED_EVENT: &str = "network-profile-applied";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyStopped {
+    pub interface_index: Option<u32>,
+    pub upstream: Option<String>,
+    pub uptime_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyFailed {
+    pub interface_index: Option<u32>,
+    pub upstream: Option<String>,
+    pub error: String,
+    /// Set when the upstream's key matched none of the pins, with the keys it sent.
+    pub pin_mismatch: Option<PinMismatch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamFallback {
+    /// The encrypted upstream that failed, as configured.
+    pub upstream: String,
+    /// Where queries are going instead.
+    pub fallback: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamRecovered {
+    pub upstream: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DefaultRouteFollowed {
+    /// The adapter the activation left, now back on its own DNS.
+    pub from: u32,
+    pub to: u32,
+}
+
+/// Where the servers that replaced ours came from, as far as the OS records it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftSource {
+    /// Handed out by DHCP (or router advertisement): a lease renewal, usually.
+    Dhcp,
+    /// Written as static adapter DNS: a VPN client, group policy or a person.
+    Static,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsDrift {
+    pub interface_index: u32,
+    pub family: Family,
+    /// What was applied.
+    pub expected: Vec<IpAddr>,
+    /// What the interface has instead.
+    pub found: Vec<IpAddr>,
+    /// Who wrote `found`: static servers in the registry on Windows, servers outside
+    /// the DHCP lease on Linux. `None` when the interface could not be listed or
+    /// `found` is empty.
+    pub source: Option<DriftSource>,
+    /// Whether `expected` has been written back, per the user's setting.
+    pub reapplied: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkProfileApplied {
+    pub interface_index: u32,
+    pub profile_id: String,
+    pub profile_name: String,
+    /// The network that matched.
+    pub network: NetworkFingerprint,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProxyEvent {
+    Started(ProxyStatus),
+    Stopped(ProxyStopped),
+    Failed(ProxyFailed),
+    UpstreamFallback(UpstreamFallback),
+    UpstreamRecovered(UpstreamRecovered),
+}
+
+impl ProxyEvent {
+    fn emit(&self, app: &AppHandle) -> tauri::Result<()> {
+        match self {
+            ProxyEvent::Started(payload) => app.emit(PROXY_STARTED_EVENT, payload),
+            ProxyEvent::Stopped(payload) => app.emit(PROXY_STOPPED_EVENT, payload),
+            ProxyEvent::Failed(payload) => app.emit(PROXY_ERROR_EVENT, payload),
+            ProxyEvent::UpstreamFallback(payload) => app.emit(UPSTREAM_FALLBACK_EVENT, payload),
+            ProxyEvent::UpstreamRecovered(payload) => app.emit(UPSTREAM_RECOVERED_EVENT, payload),
+        }
+    }
+}
+
+/// Spawns the task that forwards proxy events to the frontend. Must be called from a
+/// tokio context — `setup` is one.
+pub fn forward_to_frontend(app: AppHandle, mut rx: mpsc::UnboundedReceiver<ProxyEvent>) {
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Err(e) = event.emit(&app) {
+                warn!("Could not emit proxy event {:?}: {}", event, e);
+            }
+        }
+    });
+}
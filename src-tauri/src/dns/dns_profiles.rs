@@ -0,0 +1,183 @@
+//! What each interface is using, and the proxy serving it.
+//!
+//! Every interface activated with an encrypted protocol gets its own `DnsServer` on
+//! its own loopback address (`platform::proxy_v4`), so Wi-Fi can go to one DoH provider
+//! while Ethernet goes to another, and clearing one leaves the rest running. Windows
+//! always sends DNS to port 53, which is why the proxies are told apart by address
+//! rather than by port. Interfaces on plain DNS are tracked too, without a proxy.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::{mpsc, RwLock};
+
+use super::dns_events::ProxyEvent;
+use super::dns_rules::DnsRules;
+use super::dns_server::DnsServer;
+use super::dns_types::{DnsQueryLog, ProxyStatus};
+use crate::commands::dns::DnsActivation;
+use crate::error::{AppError, AppResult};
+use crate::platform;
+
+/// One interface's active DNS.
+pub struct ActiveProfile {
+    pub dns_type: String,
+    pub servers: Vec<String>,
+    /// `None` for plain DNS, which is written straight to the adapter.
+    pub proxy: Option<DnsServer>,
+    /// What was written to the adapter: the proxy's addresses, or the plain servers.
+    /// A family with nothing here was left as it was.
+    pub applied: Vec<IpAddr>,
+    /// The activation as it was requested, so it can be put back as it was.
+    pub activation: DnsActivation,
+}
+
+/// What `get_active_profiles` returns for each interface.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveProfileInfo {
+    pub interface_index: u32,
+    pub dns_type: String,
+    pub servers: Vec<String>,
+    pub proxy: Option<ProxyStatus>,
+}
+
+pub struct InterfaceProfiles {
+    log_sender: mpsc::UnboundedSender<DnsQueryLog>,
+    event_sender: mpsc::UnboundedSender<ProxyEvent>,
+    rules: Arc<RwLock<DnsRules>>,
+    /// Shared by every proxy, so query log IDs stay unique across interfaces.
+    log_id_counter: Arc<AtomicU64>,
+    profiles: HashMap<u32, ActiveProfile>,
+    /// Where every proxy listens: 53, since the OS sends DNS nowhere else. Tests, which
+    /// cannot count on binding it, set `0` for an ephemeral port.
+    pub listen_port: u16,
+}
+
+impl InterfaceProfiles {
+    pub fn new(
+        log_sender: mpsc::UnboundedSender<DnsQueryLog>,
+        event_sender: mpsc::UnboundedSender<ProxyEvent>,
+        rules: Arc<RwLock<DnsRules>>,
+    ) -> Self {
+        Self {
+            log_sender,
+            event_sender,
+            rules,
+            log_id_counter: Arc::new(AtomicU64::new(1)),
+            profiles: HashMap::new(),
+            listen_port: 53,
+        }
+    }
+
+    /// A stopped proxy for `interface_index`, on a loopback address no other
+    /// interface's proxy is using. Call `take_proxy` on the interface first when
+    /// replacing its proxy, so the addresses it had are free again. Only the first
+    /// proxy gets `[::1]`; see `DnsServer::serve_ipv6`.
+    pub fn new_proxy(&self, interface_index: u32) -> AppResult<DnsServer> {
+        let proxies = || {
+            self.profiles
+                .values()
+                .filter_map(|profile| profile.proxy.as_ref())
+        };
+        let in_use: Vec<_> = proxies().map(|proxy| proxy.listen_v4).collect();
+        let listen_v4 = (0..platform::PROXY_SLOTS)
+            .map(platform::proxy_v4)
+            .find(|addr| !in_use.contains(addr))
+            .ok_or_else(|| {
+                AppError::Proxy(format!(
+                    "Encrypted DNS is already active on {} interfaces, which is the most that can run at once. Deactivate one first.",
+                    platform::PROXY_SLOTS
+                ))
+            })?;
+
+        let mut proxy = DnsServer::new(
+            self.log_sender.clone(),
+            self.event_sender.clone(),
+            self.rules.clone(),
+        );
+        proxy.log_id_counter = self.log_id_counter.clone();
+        proxy.listen_v4 = listen_v4;
+        proxy.listen_port = self.listen_port;
+        proxy.interface_index = Some(interface_index);
+        proxy.serve_ipv6 = !proxies().any(|proxy| proxy.status().sockets.udp_v6);
+        Ok(proxy)
+    }
+
+    /// Removes the interface's proxy, if it has one, and returns it still running.
+    pub fn take_proxy(&mut self, interface_index: u32) -> Option<DnsServer> {
+        self.profiles
+            .get_mut(&interface_index)
+            .and_then(|profile| profile.proxy.take())
+    }
+
+    /// Records what the interface is now using. Any proxy it had before must already
+    /// have been taken with `take_proxy`.
+    pub fn insert(&mut self, interface_index: u32, profile: ActiveProfile) {
+        self.profiles.insert(interface_index, profile);
+    }
+
+    /// Forgets the interface and stops its proxy. Other interfaces are not touched.
+    pub async fn remove(&mut self, interface_index: u32) -> Result<(), String> {
+        match self.profiles.remove(&interface_index) {
+            Some(ActiveProfile {
+                proxy: Some(mut proxy),
+                ..
+            }) => proxy.shutdown().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The status of the interface's proxy, or with no interface given, of the first
+    /// running one — what the single-proxy `get_proxy_status` used to return.
+    pub fn status(&self, interface_index: Option<u32>) -> ProxyStatus {
+        let proxy = match interface_index {
+            Some(index) => self
+                .profiles
+                .get(&index)
+                .and_then(|profile| profile.proxy.as_ref()),
+            None => self
+                .profiles
+                .values()
+                .filter_map(|profile| profile.proxy.as_ref())
+                .find(|proxy| proxy.status().running),
+        };
+        proxy.map(DnsServer::status).unwrap_or_default()
+    }
+
+    /// Whether anything of ours — a proxy or plain servers — is applied to the interface.
+    pub fn is_active(&self, interface_index: u32) -> bool {
+        self.profiles.contains_key(&interface_index)
+    }
+
+    /// What the interface was activated with, if anything.
+    pub fn activation(&self, interface_index: u32) -> Option<DnsActivation> {
+        self.profiles
+            .get(&interface_index)
+            .map(|profile| profile.activation.clone())
+    }
+
+    /// Each interface with what was written to it.
+    pub fn applied(&self) -> impl Iterator<Item = (u32, &[IpAddr])> {
+        self.profiles
+            .iter()
+            .map(|(&interface_index, profile)| (interface_index, profile.applied.as_slice()))
+    }
+
+    pub fn list(&self) -> Vec<ActiveProfileInfo> {
+        let mut list: Vec<_> = self
+            .profiles
+            .iter()
+            .map(|(&interface_index, profile)| ActiveProfileInfo {
+                interface_index,
+                dns_type: profile.dns_type.clone(),
+                servers: profile.servers.clone(),
+                proxy: profile.proxy.as_ref().map(DnsServer::status),
+            })
+            .collect();
+        list.sort_by_key(|info| info.interface_index);
+        list
+    }
+}
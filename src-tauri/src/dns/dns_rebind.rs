@@ -0,0 +1,201 @@
+//! DNS rebinding protection for answers forwarded from the upstream resolver.
+//!
+//! A public name that resolves to `192.168.1.1` or `127.0.0.1` is how a web page gets a
+//! browser to talk to the user's router or a local dev server under an origin the page
+//! controls. The proxy sits in exactly the right place to stop that: it sees every
+//! forwarded answer before the client does, so private and loopback addresses can be
+//! removed there.
+//!
+//! Names that legitimately point inward — internal zones, split-horizon corporate
+//! domains — go on the allowlist and are passed through untouched. Rule answers are
+//! never filtered: the user wrote those themselves.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use hickory_proto::rr::{RData, Record};
+use serde::{Deserialize, Serialize};
+
+/// What happens to a forwarded answer that contains a private or loopback address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RebindAction {
+    /// Remove only the offending A/AAAA records and answer with whatever is left.
+    #[default]
+    Drop,
+    /// Refuse the whole query.
+    Block,
+}
+
+/// Rebinding protection settings, as sent by the frontend with `set_dns`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RebindProtection {
+    pub enabled: bool,
+    pub action: RebindAction,
+    /// Domains allowed to resolve to private addresses. Each entry covers the domain
+    /// itself and every subdomain; a leading `*.` is accepted and ignored.
+    pub allowlist: Vec<String>,
+}
+
+impl RebindProtection {
+    /// True if `domain` (lowercase, no trailing dot) is exempt from filtering.
+    pub fn is_allowed(&self, domain: &str) -> bool {
+        self.allowlist.iter().any(|entry| {
+            let entry = entry.trim().trim_end_matches('.').to_lowercase();
+            let suffix = entry.strip_prefix("*.").unwrap_or(&entry);
+            !suffix.is_empty()
+                && (domain == suffix
+                    || domain
+                        .strip_suffix(suffix)
+                        .is_some_and(|rest| rest.ends_with('.')))
+        })
+    }
+
+    /// Splits a forwarded answer into the records that may be returned and the addresses
+    /// that were filtered out. An empty second half means nothing was caught.
+    pub fn filter(&self, domain: &str, records: Vec<Record>) -> (Vec<Record>, Vec<IpAddr>) {
+        if !self.enabled || self.is_allowed(domain) {
+            return (records, Vec::new());
+        }
+
+        let mut kept = Vec::with_capacity(records.len());
+        let mut caught = Vec::new();
+        for record in records {
+            match record_ip(&record) {
+                Some(ip) if is_internal_addr(&ip) => caught.push(ip),
+                _ => kept.push(record),
+            }
+        }
+        (kept, caught)
+    }
+}
+
+fn record_ip(record: &Record) -> Option<IpAddr> {
+    match record.data() {
+        RData::A(a) => Some(IpAddr::V4(a.0)),
+        RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+        _ => None,
+    }
+}
+
+/// True for addresses a public name has no business resolving to: loopback, RFC 1918,
+/// link-local, CGNAT shared space, unspecified, and the IPv6 equivalents (including
+/// IPv4-mapped forms of any of those).
+pub fn is_internal_addr(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_internal_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_internal_v4(&v4),
+            None => {
+                v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local()
+                    || is_site_local_v6(v6)
+            }
+        },
+    }
+}
+
+fn is_internal_v4(v4: &Ipv4Addr) -> bool {
+    let [a, b, ..] = v4.octets();
+    v4.is_loopback()
+        || v4.is_private()
+        || v4.is_link_local()
+        || v4.is_unspecified()
+        // 0.0.0.0/8: "this network", routed to the local host by most stacks.
+        || a == 0
+        // 100.64.0.0/10: carrier-grade NAT, which is where Tailscale and similar put
+        // their peers.
+        || (a == 100 && (64..128).contains(&b))
+}
+
+/// `fec0::/10`, deprecated but still routed locally — Windows uses it for its default
+/// DNS anycast addresses.
+fn is_site_local_v6(v6: &Ipv6Addr) -> bool {
+    (v6.segments()[0] & 0xffc0) == 0xfec0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, AAAA};
+    use hickory_proto::rr::Name;
+
+    fn a(ip: &str) -> Record {
+        Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            60,
+            RData::A(A(ip.parse().unwrap())),
+        )
+    }
+
+    fn aaaa(ip: &str) -> Record {
+        Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            60,
+            RData::AAAA(AAAA(ip.parse().unwrap())),
+        )
+    }
+
+    fn enabled(allowlist: &[&str]) -> RebindProtection {
+        RebindProtection {
+            enabled: true,
+            action: RebindAction::Drop,
+            allowlist: allowlist.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn classifies_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert!(is_internal_addr(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "172.32.0.1", "100.128.0.1", "2606:4700::1111"] {
+            assert!(!is_internal_addr(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn drops_only_internal_records() {
+        let (kept, caught) = enabled(&[]).filter(
+            "example.com",
+            vec![a("93.184.216.34"), a("192.168.0.10"), aaaa("::1")],
+        );
+        assert_eq!(kept.len(), 1);
+        assert_eq!(caught.len(), 2);
+    }
+
+    #[test]
+    fn allowlist_covers_subdomains_but_not_lookalikes() {
+        let protection = enabled(&["corp.example", "*.lab.test"]);
+        assert!(protection.is_allowed("corp.example"));
+        assert!(protection.is_allowed("git.corp.example"));
+        assert!(protection.is_allowed("host.lab.test"));
+        assert!(!protection.is_allowed("evilcorp.example"));
+
+        let (kept, caught) = protection.filter("git.corp.example", vec![a("10.0.0.5")]);
+        assert_eq!(kept.len(), 1);
+        assert!(caught.is_empty());
+    }
+
+    #[test]
+    fn disabled_protection_passes_everything() {
+        let (kept, caught) =
+            RebindProtection::default().filter("example.com", vec![a("127.0.0.1")]);
+        assert_eq!(kept.len(), 1);
+        assert!(caught.is_empty());
+    }
+}
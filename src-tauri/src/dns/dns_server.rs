@@ -5,7 +5,7 @@ use hickory_proto::runtime::TokioRuntimeProvider;
 use hickory_proto::xfer::Protocol;
 use hickory_resolver::config::{NameServerConfig, ResolverConfig, ResolverOpts};
 use hickory_resolver::name_server::GenericConnector;
-use hickory_resolver::{Resolver, TokioResolver};
+use hickory_resolver::{ResolveError, Resolver, TokioResolver};
 use hickory_server::authority::MessageResponseBuilder;
 use hickory_server::server::{
     Request, RequestHandler, ResponseHandler, ResponseInfo, ServerFuture,
@@ -13,8 +13,8 @@ use hickory_server::server::{
 use log::{debug, error, info, warn};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
 use tokio::time::{Duration, Instant};
@@ -23,14 +23,29 @@ use tokio::time::{Duration, Instant};
 /// RFC 7766 suggests a few seconds for a resolver that is not under memory pressure.
 const TCP_CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Debug, serde::Deserialize)]
+/// How long `shutdown` waits for in-flight queries before giving up on a clean stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct BootstrapResolverInfo {
     pub server: String,
     pub bootstrap_ip: Option<String>,
 }
 
+use super::dns_doh::DohClient;
+use super::dns_events::{
+    ProxyEvent, ProxyFailed, ProxyStopped, UpstreamFallback, UpstreamRecovered,
+};
+use super::dns_rebind::RebindAction;
 use super::dns_rules::DnsRules;
-use super::dns_types::{DnsQueryLog, DnsQueryStatus};
+use super::dns_special_use::SpecialUseAnswer;
+use super::dns_tls::{self, PinReport};
+use super::dns_types::{
+    DnsQueryLog, DnsQueryStatus, ProxyCounts, ProxyPolicy, ProxySockets, ProxyStatus,
+    UpstreamFailurePolicy,
+};
+use super::dns_upstream::UpstreamOptions;
+use crate::error::AppError;
 
 pub struct DnsServer {
     pub resolver: Option<TokioResolver>,
@@ -38,13 +53,94 @@ pub struct DnsServer {
     pub socket: Option<UdpSocket>,
     pub shutdown_sender: Option<oneshot::Sender<()>>,
     pub log_sender: Option<mpsc::UnboundedSender<DnsQueryLog>>,
+    pub event_sender: Option<mpsc::UnboundedSender<ProxyEvent>>,
     pub rules: Arc<RwLock<DnsRules>>,
     pub log_id_counter: Arc<AtomicU64>,
+    pub counters: Arc<ProxyCounters>,
+    /// IPv4 loopback address to serve on; one of the `platform::proxy_v4` slots.
+    pub listen_v4: Ipv4Addr,
+    /// Port to serve on, on every socket. Always 53 in the app — the system resolver
+    /// sends nowhere else. Tests use `0`, and `run` replaces it with the port the OS
+    /// picked.
+    pub listen_port: u16,
+    /// The interface this proxy serves, for status and events.
+    pub interface_index: Option<u32>,
+    /// Whether to serve `[::1]` too. There is only the one IPv6 loopback address, so
+    /// `InterfaceProfiles::new_proxy` turns this off while another proxy has it.
+    pub serve_ipv6: bool,
+    /// Present from a successful `run` until `shutdown`.
+    session: Option<ProxySession>,
+    /// The task serving the sockets; awaited on shutdown so they are closed by the
+    /// time it returns.
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// Query totals, shared between the server and its request handler.
+#[derive(Debug, Default)]
+pub struct ProxyCounters {
+    queries: AtomicU64,
+    errors: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl ProxyCounters {
+    fn reset(&self) {
+        self.queries.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+        self.blocked.store(0, Ordering::Relaxed);
+    }
+
+    /// Blocked covers everything the proxy kept from the client: rule hits, answers
+    /// rebinding protection filtered (even when a fallback answered them) and refused
+    /// special-use names.
+    fn count(&self, log: &DnsQueryLog, refused: bool) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        match log.status {
+            DnsQueryStatus::Error => self.errors.fetch_add(1, Ordering::Relaxed),
+            DnsQueryStatus::Blocked | DnsQueryStatus::Rebind => {
+                self.blocked.fetch_add(1, Ordering::Relaxed)
+            }
+            _ if refused || !log.rebind_hits.is_empty() => {
+                self.blocked.fetch_add(1, Ordering::Relaxed)
+            }
+            _ => 0,
+        };
+    }
+
+    pub fn snapshot(&self) -> ProxyCounts {
+        ProxyCounts {
+            queries: self.queries.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What one run of the proxy looks like, for `get_proxy_status`.
+struct ProxySession {
+    upstream: String,
+    upstream_addr: Option<SocketAddr>,
+    sockets: ProxySockets,
+    ipv6_redirect: bool,
+    ipv6_unprotected: bool,
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: Instant,
+    /// When the server task exited, whether asked to or not.
+    stopped: Arc<OnceLock<Instant>>,
+}
+
+impl ProxySession {
+    /// How long the proxy served: up to now while it runs, frozen once it stops.
+    fn uptime_secs(&self) -> u64 {
+        let until = self.stopped.get().copied().unwrap_or_else(Instant::now);
+        until.duration_since(self.started).as_secs()
+    }
 }
 
 impl DnsServer {
     pub fn new(
         log_sender: mpsc::UnboundedSender<DnsQueryLog>,
+        event_sender: mpsc::UnboundedSender<ProxyEvent>,
         rules: Arc<RwLock<DnsRules>>,
     ) -> Self {
         Self {
@@ -53,13 +149,56 @@ impl DnsServer {
             socket: None,
             shutdown_sender: None,
             log_sender: Some(log_sender),
+            event_sender: Some(event_sender),
             rules,
             log_id_counter: Arc::new(AtomicU64::new(1)),
+            counters: Arc::new(ProxyCounters::default()),
+            listen_v4: crate::platform::PROXY_V4,
+            listen_port: 53,
+            interface_index: None,
+            serve_ipv6: true,
+            session: None,
+            task: None,
+        }
+    }
+
+    fn send_event(&self, event: ProxyEvent) {
+        if let Some(ref sender) = self.event_sender {
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn status(&self) -> ProxyStatus {
+        let Some(ref session) = self.session else {
+            return ProxyStatus::default();
+        };
+        ProxyStatus {
+            running: self.server.is_some() && session.stopped.get().is_none(),
+            interface_index: self.interface_index,
+            listen_v4: Some(self.listen_v4.to_string()),
+            upstream: Some(session.upstream.clone()),
+            upstream_addr: session.upstream_addr.map(|addr| addr.to_string()),
+            sockets: session.sockets,
+            ipv6_redirect: session.ipv6_redirect,
+            ipv6_unprotected: session.ipv6_unprotected,
+            started_at: Some(session.started_at.to_rfc3339()),
+            uptime_secs: session.uptime_secs(),
+            counts: self.counters.snapshot(),
+        }
+    }
+
+    /// Called once the interface points at the proxy. Records whether IPv6 was
+    /// redirected too, or left bypassing it, and tells the frontend the proxy is live.
+    pub fn mark_applied(&mut self, ipv6_redirect: bool, ipv6_unprotected: bool) {
+        if let Some(ref mut session) = self.session {
+            session.ipv6_redirect = ipv6_redirect;
+            session.ipv6_unprotected = ipv6_unprotected;
         }
+        self.send_event(ProxyEvent::Started(self.status()));
     }
 
     /// Starts the proxy and returns whether it managed to bind the IPv6 loopback
-    /// socket (`[::1]:53`). The IPv4 socket (`127.0.0.2:53`) is required — a failure
+    /// socket (`[::1]:53`). The IPv4 socket (`listen_v4:53`) is required — a failure
     /// there fails the whole call, matching the previous behavior. IPv6 is
     /// best-effort: callers must not point IPv6 DNS at `::1` unless this returns
     /// `true`, or IPv6 queries would go to a port nothing is listening on.
@@ -68,39 +207,137 @@ impl DnsServer {
         server: String,
         bootstrap_ip: Option<String>,
         bootstrap_resolver: Option<BootstrapResolverInfo>,
+        policy: ProxyPolicy,
+        options: UpstreamOptions,
+    ) -> Result<bool, String> {
+        let upstream = server.clone();
+        let result = self
+            .start(server, bootstrap_ip, bootstrap_resolver, policy, options)
+            .await;
+        if let Err(ref error) = result {
+            self.send_event(ProxyEvent::Failed(ProxyFailed {
+                interface_index: self.interface_index,
+                upstream: Some(upstream),
+                error: error.clone(),
+                pin_mismatch: None,
+            }));
+        }
+        result
+    }
+
+    async fn start(
+        &mut self,
+        server: String,
+        bootstrap_ip: Option<String>,
+        bootstrap_resolver: Option<BootstrapResolverInfo>,
+        policy: ProxyPolicy,
+        options: UpstreamOptions,
     ) -> Result<bool, String> {
+        options.check()?;
+        let label = server.clone();
         let (domain, port, proto, http_endpoint) = Self::parse_server_url(&server)?;
 
         // Priority: bootstrap_ip > bootstrap_resolver > system DNS
         let effective_bootstrap_ip = if bootstrap_ip.is_some() {
             bootstrap_ip
         } else if let Some(ref resolver_info) = bootstrap_resolver {
-            Some(Self::resolve_via_bootstrap(resolver_info, &domain).await?)
+            Some(Self::resolve_via_bootstrap(resolver_info, &domain, &options).await?)
         } else {
             None
         };
 
-        let resolver = DnsServer::create_dns_resolver(
+        let resolver = DnsServer::create_upstream(
             domain,
             port,
             proto,
             http_endpoint,
             effective_bootstrap_ip,
+            &options,
         )
         .map_err(|e| {
             error!("Failed to create DNS resolver: {}", e);
             format!("Failed to create DNS resolver: {}", e)
         })?;
 
+        let upstream_addr = resolver.addr();
         let socket_v4 = self.create_udp_socket().await?;
+        // TCP and IPv6 share the UDP socket's port, which may have just been picked.
+        self.listen_port = socket_v4
+            .local_addr()
+            .map_err(|e| format!("Failed to read the UDP socket's address: {}", e))?
+            .port();
+        let mut sockets = ProxySockets {
+            udp_v4: true,
+            ..Default::default()
+        };
 
         debug!("created socket: {:?}", socket_v4);
 
+        // Special-use names that belong to the local network are answered by whatever
+        // the interface used before activation. Plain UDP on purpose: that is all a home
+        // router speaks, and these names never leave the LAN anyway.
+        let original_resolver = if policy.original_resolvers.is_empty() {
+            None
+        } else {
+            debug!(
+                "Original resolvers for local names: {:?}",
+                policy.original_resolvers
+            );
+            Some(Self::create_plain_resolver(
+                &policy.original_resolvers,
+                options.resolver_opts(),
+            ))
+        };
+
+        let fallback = match policy.upstream_failure {
+            UpstreamFailurePolicy::FailClosed => None,
+            UpstreamFailurePolicy::OriginalResolvers => match original_resolver {
+                Some(ref original) => Some(Fallback {
+                    label: format!("original resolvers {:?}", policy.original_resolvers),
+                    resolver: original.clone(),
+                }),
+                None => {
+                    warn!("Fallback to the original resolvers requested, but none are known — failing closed");
+                    None
+                }
+            },
+            UpstreamFailurePolicy::PlainResolver { ref servers } => {
+                let ips = servers
+                    .iter()
+                    .map(|s| {
+                        s.parse::<IpAddr>()
+                            .map_err(|e| format!("Invalid fallback resolver '{}': {}", s, e))
+                    })
+                    .collect::<Result<Vec<IpAddr>, String>>()?;
+                if ips.is_empty() {
+                    return Err("The fallback resolver policy has no servers.".to_string());
+                }
+                Some(Fallback {
+                    label: format!("plain resolver {:?}", ips),
+                    resolver: Self::create_plain_resolver(&ips, options.resolver_opts()),
+                })
+            }
+        };
+        if let Some(ref fallback) = fallback {
+            info!("Upstream failures will fall back to the {}", fallback.label);
+        }
+
         let dns_resolver = DnsResolver::new(
-            resolver,
+            Upstreams {
+                label: server.clone(),
+                primary: resolver,
+                original: original_resolver,
+                fallback,
+                pin_report: options.pin_report.clone(),
+                client_certificate: options.client_certificate.is_some(),
+            },
+            self.interface_index,
             self.log_sender.clone(),
+            self.event_sender.clone(),
             self.rules.clone(),
             self.log_id_counter.clone(),
+            self.counters.clone(),
+            policy,
         );
 
         let mut server = ServerFuture::new(dns_resolver);
@@ -113,11 +350,14 @@ impl DnsServer {
         // and the client is then required to retry the same query over TCP. With no TCP
         // listener that retry hits a closed port and the lookup fails outright — which
         // is how large TXT/DNSKEY answers and some CDN responses were breaking.
-        match TcpListener::bind((crate::win::PROXY_V4, 53)).await {
-            Ok(listener) => server.register_listener(listener, TCP_CLIENT_TIMEOUT),
+        match TcpListener::bind((self.listen_v4, self.listen_port)).await {
+            Ok(listener) => {
+                server.register_listener(listener, TCP_CLIENT_TIMEOUT);
+                sockets.tcp_v4 = true;
+            }
             Err(e) => warn!(
-                "Could not bind TCP 127.0.0.2:53 — truncated responses will fail to retry: {}",
-                e
+                "Could not bind TCP {}:{} — truncated responses will fail to retry: {}",
+                self.listen_v4, self.listen_port, e
             ),
         }
 
@@ -125,12 +365,27 @@ impl DnsServer {
         // proxy, which is the root of the IPv6 DNS leak (see WMI_MIGRATION_PLAN.md).
         // Both the UDP and TCP sockets must come up, for the same truncation reason as
         // above; if either fails we leave IPv6 DNS pointing at the real resolver.
-        let ipv6_ready = match UdpSocket::bind((crate::win::PROXY_V6, 53)).await {
-            Ok(socket_v6) => {
+        let bound_v6 = if self.serve_ipv6 {
+            UdpSocket::bind((crate::platform::PROXY_V6, self.listen_port))
+                .await
+                .map(Some)
+        } else {
+            Ok(None)
+        };
+        let ipv6_ready = match bound_v6 {
+            Ok(None) => {
+                info!(
+                    "Another interface's proxy serves [::1]:53, so IPv6 DNS will not be redirected"
+                );
+                false
+            }
+            Ok(Some(socket_v6)) => {
                 server.register_socket(socket_v6);
-                match TcpListener::bind((crate::win::PROXY_V6, 53)).await {
+                sockets.udp_v6 = true;
+                match TcpListener::bind((crate::platform::PROXY_V6, self.listen_port)).await {
                     Ok(listener) => {
                         server.register_listener(listener, TCP_CLIENT_TIMEOUT);
+                        sockets.tcp_v6 = true;
                         true
                     }
                     Err(e) => {
@@ -160,8 +415,23 @@ impl DnsServer {
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
         self.shutdown_sender = Some(shutdown_tx);
 
+        self.counters.reset();
+        let stopped = Arc::new(OnceLock::new());
+        self.session = Some(ProxySession {
+            upstream: label.clone(),
+            upstream_addr,
+            sockets,
+            ipv6_redirect: false,
+            ipv6_unprotected: false,
+            started_at: chrono::Utc::now(),
+            started: Instant::now(),
+            stopped: stopped.clone(),
+        });
+
         let server_clone = server.clone();
-        tokio::spawn(async move {
+        let event_sender = self.event_sender.clone();
+        let interface_index = self.interface_index;
+        self.task = Some(tokio::spawn(async move {
             debug!("Dns server blocking until done");
 
             tokio::select! {
@@ -171,7 +441,17 @@ impl DnsServer {
                 } => {
                     match result {
                         Ok(_) => debug!("Dns server stopped (block_until_done completed)"),
-                        Err(err) => error!("Dns server stopped with error: {}", err),
+                        Err(err) => {
+                            error!("Dns server stopped with error: {}", err);
+                            if let Some(sender) = event_sender {
+                                let _ = sender.send(ProxyEvent::Failed(ProxyFailed {
+                                    interface_index,
+                                    upstream: Some(label),
+                                    error: format!("The proxy stopped serving: {}", err),
+                                    pin_mismatch: None,
+                                }));
+                            }
+                        }
                     }
                 }
                 _ = shutdown_rx => {
@@ -189,7 +469,8 @@ impl DnsServer {
                     debug!("Dns server stopped (after graceful shutdown)");
                 }
             }
-        });
+            let _ = stopped.set(Instant::now());
+        }));
 
         debug!("registered socket");
 
@@ -207,8 +488,22 @@ impl DnsServer {
                 debug!("shutdown signal sent successfully");
             }
         }
+        // Wait for the sockets to close, so the same address can be bound again right
+        // away — replacing an interface's proxy reuses its slot.
+        if let Some(task) = self.task.take() {
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, task).await.is_err() {
+                warn!("The dns server did not stop within {:?}", SHUTDOWN_TIMEOUT);
+            }
+        }
         // Clear the server reference after shutdown
         self.server = None;
+        if let Some(session) = self.session.take() {
+            self.send_event(ProxyEvent::Stopped(ProxyStopped {
+                interface_index: self.interface_index,
+                uptime_secs: session.uptime_secs(),
+                upstream: Some(session.upstream),
+            }));
+        }
         debug!("dns server shutdown successfully");
         Ok(())
     }
@@ -227,8 +522,7 @@ impl DnsServer {
         match scheme {
             "https" => {
                 let port = server_url.port().unwrap_or(443);
-                let path = server_url.path().to_string();
-                Ok((domain, port, Protocol::Https, Some(path)))
+                Ok((domain, port, Protocol::Https, Some(http_path(&server_url))))
             }
             "tls" => {
                 let port = server_url.port().unwrap_or(853);
@@ -240,11 +534,10 @@ impl DnsServer {
             }
             "h3" => {
                 let port = server_url.port().unwrap_or(443);
-                let path = server_url.path().to_string();
-                let endpoint = if path.is_empty() || path == "/" {
+                let endpoint = if server_url.path().is_empty() || server_url.path() == "/" {
                     "/dns-query".to_string()
                 } else {
-                    path
+                    http_path(&server_url)
                 };
                 Ok((domain, port, Protocol::H3, Some(endpoint)))
             }
@@ -255,37 +548,94 @@ impl DnsServer {
         }
     }
 
-    pub fn create_dns_resolver(
-        domain: String,
+    /// Where to connect for `domain`: the bootstrap IP if there is one, otherwise a
+    /// system lookup done now, before the proxy takes over system DNS.
+    fn upstream_addr(
+        domain: &str,
         port: u16,
-        protocol: Protocol,
-        http_endpoint: Option<String>,
-        bootstrap_ip: Option<String>,
-    ) -> Result<TokioResolver, String> {
-        let mut config = ResolverConfig::new();
-
-        let socket_addr = if let Some(ref ip_str) = bootstrap_ip {
+        bootstrap_ip: Option<&str>,
+    ) -> Result<SocketAddr, String> {
+        if let Some(ip_str) = bootstrap_ip {
             let ip: IpAddr = ip_str
                 .parse()
                 .map_err(|e| format!("Failed to parse bootstrap IP '{}': {}", ip_str, e))?;
-            SocketAddr::new(ip, port)
+            Ok(SocketAddr::new(ip, port))
         } else {
-            let mut addrs = (domain.clone(), port)
+            let mut addrs = (domain, port)
                 .to_socket_addrs()
                 .map_err(|e| format!("Failed to resolve domain: {}", e))?;
             addrs
                 .next()
-                .ok_or(format!("Failed to resolve domain: {}", &domain))?
-        };
+                .ok_or(format!("Failed to resolve domain: {}", domain))
+        }
+    }
+
+    /// The upstream for an encrypted server: hickory, unless the entry uses DoH features
+    /// hickory lacks (see `dns_doh`).
+    pub fn create_upstream(
+        domain: String,
+        port: u16,
+        protocol: Protocol,
+        http_endpoint: Option<String>,
+        bootstrap_ip: Option<String>,
+        options: &UpstreamOptions,
+    ) -> Result<Upstream, String> {
+        let is_doh = matches!(protocol, Protocol::Https | Protocol::H3);
+        if !(is_doh && options.needs_custom_doh()) {
+            return Self::create_dns_resolver(
+                domain,
+                port,
+                protocol,
+                http_endpoint,
+                bootstrap_ip,
+                options,
+            )
+            .map(|resolver| Upstream::Hickory(Box::new(resolver)));
+        }
+
+        options.check_custom_doh(protocol == Protocol::H3)?;
+        let socket_addr = Self::upstream_addr(&domain, port, bootstrap_ip.as_deref())?;
+        let url = format!(
+            "https://{}:{}{}",
+            options.server_name(&domain),
+            port,
+            http_endpoint.as_deref().unwrap_or("/dns-query")
+        );
+        let url = url::Url::parse(&url).map_err(|e| format!("Invalid DoH URL {}: {}", url, e))?;
+        info!(
+            "DNS Server Resolved: {:?} (custom DoH, {:?})",
+            socket_addr, options.doh_method
+        );
+        DohClient::new(url, Some(socket_addr), options).map(Upstream::Doh)
+    }
+
+    pub fn create_dns_resolver(
+        domain: String,
+        port: u16,
+        protocol: Protocol,
+        http_endpoint: Option<String>,
+        bootstrap_ip: Option<String>,
+        options: &UpstreamOptions,
+    ) -> Result<TokioResolver, String> {
+        options.check_client_id(protocol == Protocol::Tls)?;
+        let mut config = ResolverConfig::new();
+
+        let socket_addr = Self::upstream_addr(&domain, port, bootstrap_ip.as_deref())?;
 
         info!(
             "DNS Server Resolved: {:?} (protocol: {:?})",
             socket_addr, protocol
         );
 
+        let mut opts = options.resolver_opts();
         let tls_dns_name = match protocol {
             Protocol::Udp | Protocol::Tcp => None,
-            _ => Some(domain),
+            _ => {
+                if let Some(tls_config) = dns_tls::client_config(&domain, options)? {
+                    opts.tls_config = tls_config;
+                }
+                Some(options.server_name(&domain))
+            }
         };
 
         config.add_name_server(NameServerConfig {
@@ -297,8 +647,6 @@ impl DnsServer {
             trust_negative_responses: true,
         });
 
-        let opts = ResolverOpts::default();
-
         let connector = GenericConnector::<TokioRuntimeProvider>::default();
 
         let resolver = Resolver::builder_with_config(config, connector)
@@ -308,11 +656,38 @@ impl DnsServer {
         Ok(resolver)
     }
 
+    /// A plain DNS (UDP, port 53) resolver over the given servers, tried in order.
+    pub fn create_plain_resolver(servers: &[IpAddr], opts: ResolverOpts) -> TokioResolver {
+        let mut config = ResolverConfig::new();
+        for ip in servers {
+            config.add_name_server(NameServerConfig {
+                socket_addr: SocketAddr::new(*ip, 53),
+                protocol: Protocol::Udp,
+                tls_dns_name: None,
+                http_endpoint: None,
+                bind_addr: None,
+                trust_negative_responses: true,
+            });
+        }
+
+        let connector = GenericConnector::<TokioRuntimeProvider>::default();
+
+        Resolver::builder_with_config(config, connector)
+            .with_options(opts)
+            .build()
+    }
+
     /// Resolve a domain using a bootstrap resolver (either plain DNS IP or DoH URL with its own bootstrap IP).
+    ///
+    /// `options` are those of the server being bootstrapped: a link slow enough to need
+    /// a longer timeout needs it for the bootstrap lookup too. Its TLS settings are
+    /// dropped: pins for the server say nothing about the bootstrap.
     pub async fn resolve_via_bootstrap(
         bootstrap: &BootstrapResolverInfo,
         domain: &str,
+        options: &UpstreamOptions,
     ) -> Result<String, String> {
+        let options = &options.for_bootstrap();
         let is_plain_ip = bootstrap.server.parse::<IpAddr>().is_ok();
 
         let resolver = if is_plain_ip {
@@ -320,24 +695,7 @@ impl DnsServer {
                 .server
                 .parse()
                 .map_err(|e| format!("Failed to parse bootstrap IP: {}", e))?;
-            let socket_addr = SocketAddr::new(ip, 53);
-
-            let mut config = ResolverConfig::new();
-            config.add_name_server(NameServerConfig {
-                socket_addr,
-                protocol: Protocol::Udp,
-                tls_dns_name: None,
-                http_endpoint: None,
-                bind_addr: None,
-                trust_negative_responses: true,
-            });
-
-            let opts = ResolverOpts::default();
-            let connector = GenericConnector::<TokioRuntimeProvider>::default();
-
-            Resolver::builder_with_config(config, connector)
-                .with_options(opts)
-                .build()
+            Self::create_plain_resolver(&[ip], options.resolver_opts())
         } else {
             let (resolver_domain, port, proto, http_endpoint) =
                 Self::parse_server_url(&bootstrap.server)?;
@@ -347,6 +705,7 @@ impl DnsServer {
                 proto,
                 http_endpoint,
                 bootstrap.bootstrap_ip.clone(),
+                options,
             )?
         };
 
@@ -365,7 +724,7 @@ impl DnsServer {
     }
 
     pub async fn create_udp_socket(&self) -> Result<UdpSocket, String> {
-        let socket = UdpSocket::bind((crate::win::PROXY_V4, 53))
+        let socket = UdpSocket::bind((self.listen_v4, self.listen_port))
             .await
             .map_err(|e| format!("Failed to create UDP socket: {}", e))?;
 
@@ -373,29 +732,116 @@ impl DnsServer {
     }
 
     pub async fn is_running(&self) -> bool {
-        self.server.is_some()
+        self.status().running
+    }
+}
+
+/// The path and query of a DoH URL, which together are the HTTP endpoint. Profile IDs
+/// live in either, depending on the provider.
+fn http_path(url: &url::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
     }
 }
 
+/// Where forwarded queries go.
+pub enum Upstream {
+    Hickory(Box<TokioResolver>),
+    Doh(DohClient),
+}
+
+impl Upstream {
+    /// The address queries go to, if known.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        match self {
+            Upstream::Hickory(resolver) => resolver
+                .config()
+                .name_servers()
+                .first()
+                .map(|ns| ns.socket_addr),
+            Upstream::Doh(client) => client.addr(),
+        }
+    }
+
+    pub async fn lookup(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, ResolveError> {
+        match self {
+            Upstream::Hickory(resolver) => DnsResolver::forward(resolver, name, record_type).await,
+            Upstream::Doh(client) => client.lookup(name, record_type).await,
+        }
+    }
+}
+
+/// How often a failing upstream is retried while the fallback answers in its place.
+const UPSTREAM_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A resolver queries are sent to when the upstream fails, with a label for logs.
+pub struct Fallback {
+    pub label: String,
+    pub resolver: TokioResolver,
+}
+
+/// Every resolver a query can end up at.
+pub struct Upstreams {
+    /// The upstream as configured, for logs and events.
+    pub label: String,
+    pub primary: Upstream,
+    /// The interface's pre-activation resolvers, for special-use names.
+    pub original: Option<TokioResolver>,
+    pub fallback: Option<Fallback>,
+    /// Where the primary's certificate verifier records a pin mismatch.
+    pub pin_report: PinReport,
+    /// Whether the primary presents a client certificate.
+    pub client_certificate: bool,
+}
+
 pub struct DnsResolver {
-    resolver: TokioResolver,
+    upstreams: Upstreams,
+    interface_index: Option<u32>,
     log_sender: Option<mpsc::UnboundedSender<DnsQueryLog>>,
+    event_sender: Option<mpsc::UnboundedSender<ProxyEvent>>,
     rules: Arc<RwLock<DnsRules>>,
     log_id_counter: Arc<AtomicU64>,
+    counters: Arc<ProxyCounters>,
+    policy: ProxyPolicy,
+    /// Set while queries are being answered by the fallback, so the warning and the
+    /// event fire once per outage instead of once per query.
+    falling_back: AtomicBool,
+    /// While falling back, when a query may next try the upstream again.
+    next_upstream_try: std::sync::Mutex<Instant>,
+    /// Set once the upstream has been refused over its certificate, so the proxy error
+    /// is raised once per outage rather than once per query.
+    upstream_rejected: AtomicBool,
 }
 
 impl DnsResolver {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        resolver: TokioResolver,
+        upstreams: Upstreams,
+        interface_index: Option<u32>,
         log_sender: Option<mpsc::UnboundedSender<DnsQueryLog>>,
+        event_sender: Option<mpsc::UnboundedSender<ProxyEvent>>,
         rules: Arc<RwLock<DnsRules>>,
         log_id_counter: Arc<AtomicU64>,
+        counters: Arc<ProxyCounters>,
+        policy: ProxyPolicy,
     ) -> Self {
         Self {
-            resolver,
+            upstreams,
+            interface_index,
             log_sender,
+            event_sender,
             rules,
             log_id_counter,
+            counters,
+            policy,
+            falling_back: AtomicBool::new(false),
+            next_upstream_try: std::sync::Mutex::new(Instant::now()),
+            upstream_rejected: AtomicBool::new(false),
         }
     }
 
@@ -403,15 +849,245 @@ impl DnsResolver {
         self.log_id_counter.fetch_add(1, Ordering::Relaxed)
     }
 
-    fn send_log(&self, log: DnsQueryLog) {
+    /// Counts the query and hands it to the log store. Every answered query passes
+    /// through here exactly once, which is what makes it the place to count. `refused`
+    /// is for a special-use name the proxy would not answer, which the log shows as
+    /// local.
+    fn send_log(&self, log: DnsQueryLog, refused: bool) {
+        self.counters.count(&log, refused);
         if let Some(ref sender) = self.log_sender {
             let _ = sender.send(log);
         }
     }
 
+    fn send_event(&self, event: ProxyEvent) {
+        if let Some(ref sender) = self.event_sender {
+            let _ = sender.send(event);
+        }
+    }
+
     fn record_type_str(rt: RecordType) -> String {
         format!("{:?}", rt)
     }
+
+    fn is_forwarded_type(rt: RecordType) -> bool {
+        matches!(
+            rt,
+            RecordType::A | RecordType::AAAA | RecordType::TXT | RecordType::MX
+        )
+    }
+
+    /// Looks `name` up through `resolver` and flattens the answer into records.
+    async fn forward(
+        resolver: &TokioResolver,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, ResolveError> {
+        match record_type {
+            RecordType::A | RecordType::AAAA => resolver
+                .lookup_ip(name)
+                .await
+                .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
+            RecordType::TXT => resolver
+                .txt_lookup(name)
+                .await
+                .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
+            RecordType::MX => resolver
+                .mx_lookup(name)
+                .await
+                .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
+            _ => resolver
+                .lookup(name, record_type)
+                .await
+                .map(|lookup| lookup.records().to_vec()),
+        }
+    }
+
+    /// True when the error means the upstream could not be reached or did not answer,
+    /// as opposed to answering "no such name". Only the former should fall back — a
+    /// second opinion on NXDOMAIN from an unencrypted resolver is exactly the leak the
+    /// user opted out of.
+    fn is_upstream_failure(e: &ResolveError) -> bool {
+        !e.is_nx_domain() && !e.is_no_records_found()
+    }
+
+    /// Whether this query should go to the upstream. Always, unless the fallback is
+    /// answering; then one query per [`UPSTREAM_RETRY_INTERVAL`] does, to notice when it
+    /// is back.
+    fn upstream_is_due(&self) -> bool {
+        if !self.falling_back.load(Ordering::Relaxed) {
+            return true;
+        }
+        let Ok(mut next) = self.next_upstream_try.lock() else {
+            return true;
+        };
+        let now = Instant::now();
+        if now < *next {
+            return false;
+        }
+        *next = now + UPSTREAM_RETRY_INTERVAL;
+        true
+    }
+
+    fn note_fallback(&self, fallback: &Fallback, error: &str) {
+        if let Ok(mut next) = self.next_upstream_try.lock() {
+            *next = Instant::now() + UPSTREAM_RETRY_INTERVAL;
+        }
+        if !self.falling_back.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Upstream {} is failing ({}); answering from the {} — queries are leaving the machine unencrypted",
+                self.upstreams.label, error, fallback.label
+            );
+            self.send_event(ProxyEvent::UpstreamFallback(UpstreamFallback {
+                upstream: self.upstreams.label.clone(),
+                fallback: fallback.label.clone(),
+                error: error.to_string(),
+            }));
+        }
+    }
+
+    /// What a failed upstream query is logged and reported as. A pin mismatch or a
+    /// refused client certificate is spelled out as the server test does, and raised as
+    /// a proxy error once until the upstream answers again; anything else is passed on
+    /// as it is.
+    fn describe_upstream_error(&self, error: &ResolveError) -> String {
+        let label = &self.upstreams.label;
+        let (message, mismatch) = if let Some(mismatch) = self.upstreams.pin_report.take() {
+            let message = AppError::PinMismatch {
+                server: label.clone(),
+                presented: mismatch.presented.clone(),
+            }
+            .to_string();
+            (message, Some(mismatch))
+        } else if let Some(alert) = dns_tls::client_auth_alert(&error.to_string()) {
+            let message =
+                dns_tls::client_auth_message(label, alert, self.upstreams.client_certificate);
+            (message, None)
+        } else {
+            return error.to_string();
+        };
+        if !self.upstream_rejected.swap(true, Ordering::Relaxed) {
+            warn!("{}", message);
+            self.send_event(ProxyEvent::Failed(ProxyFailed {
+                interface_index: self.interface_index,
+                upstream: Some(self.upstreams.label.clone()),
+                error: message.clone(),
+                pin_mismatch: mismatch,
+            }));
+        }
+        message
+    }
+
+    fn note_upstream_ok(&self) {
+        self.upstream_rejected.store(false, Ordering::Relaxed);
+        if self.falling_back.swap(false, Ordering::Relaxed) {
+            info!(
+                "Upstream {} is answering again; fallback ended",
+                self.upstreams.label
+            );
+            self.send_event(ProxyEvent::UpstreamRecovered(UpstreamRecovered {
+                upstream: self.upstreams.label.clone(),
+            }));
+        }
+    }
+
+    /// Answers a special-use name without involving the upstream. See
+    /// `dns_special_use` for which names land here and why.
+    async fn answer_special_use<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        mut response_handle: R,
+        answer: SpecialUseAnswer,
+        record_name: Name,
+        record_type: RecordType,
+        domain_clean: String,
+    ) -> ResponseInfo {
+        let start = Instant::now();
+
+        let (response_code, records, status) = match answer {
+            SpecialUseAnswer::Loopback => {
+                let rdata = match record_type {
+                    RecordType::A => Some(RData::A(A(Ipv4Addr::LOCALHOST))),
+                    RecordType::AAAA => Some(RData::AAAA(AAAA(Ipv6Addr::LOCALHOST))),
+                    _ => None,
+                };
+                let records: Vec<Record> = rdata
+                    .map(|rdata| Record::from_rdata(record_name, 60, rdata))
+                    .into_iter()
+                    .collect();
+                (ResponseCode::NoError, records, DnsQueryStatus::Local)
+            }
+            SpecialUseAnswer::Refused => (ResponseCode::Refused, Vec::new(), DnsQueryStatus::Local),
+            SpecialUseAnswer::Original => match self.upstreams.original {
+                Some(ref original) => match original.lookup(record_name, record_type).await {
+                    Ok(lookup) => (
+                        ResponseCode::NoError,
+                        lookup.records().to_vec(),
+                        DnsQueryStatus::Local,
+                    ),
+                    Err(e) if e.is_nx_domain() => {
+                        (ResponseCode::NXDomain, Vec::new(), DnsQueryStatus::Local)
+                    }
+                    Err(e) if e.is_no_records_found() => {
+                        (ResponseCode::NoError, Vec::new(), DnsQueryStatus::Local)
+                    }
+                    Err(e) => {
+                        debug!("Original resolver failed for {}: {}", domain_clean, e);
+                        (ResponseCode::ServFail, Vec::new(), DnsQueryStatus::Error)
+                    }
+                },
+                // Never fall through to the upstream — keeping these names off the
+                // public resolver is the whole point.
+                None => (ResponseCode::NXDomain, Vec::new(), DnsQueryStatus::Local),
+            },
+            SpecialUseAnswer::NxDomain => {
+                (ResponseCode::NXDomain, Vec::new(), DnsQueryStatus::Local)
+            }
+        };
+
+        debug!(
+            "Answered special-use name {} locally: {:?} ({} record(s))",
+            domain_clean,
+            response_code,
+            records.len()
+        );
+
+        let response = MessageResponseBuilder::from_message_request(request);
+        let mut header = Header::response_from_request(request.header());
+        header.set_response_code(response_code);
+        let result = response_handle
+            .send_response(response.build(header, records.iter(), &[], &[], &[]))
+            .await;
+
+        let response_records = if records.is_empty() {
+            vec![response_code.to_str().to_string()]
+        } else {
+            records.iter().map(|r| r.data().to_string()).collect()
+        };
+        self.send_log(
+            DnsQueryLog {
+                id: self.next_log_id(),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                domain: domain_clean,
+                record_type: Self::record_type_str(record_type),
+                response_records,
+                latency_ms: start.elapsed().as_millis() as u64,
+                status,
+                rebind_hits: Vec::new(),
+            },
+            response_code == ResponseCode::Refused,
+        );
+
+        match result {
+            Err(e) => {
+                error!("Error sending response: {}", e);
+                let mut err_header = Header::response_from_request(request.header());
+                err_header.set_response_code(ResponseCode::ServFail);
+                err_header.into()
+            }
+            Ok(info) => info,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -421,7 +1097,6 @@ impl RequestHandler for DnsResolver {
         request: &Request,
         mut response_handle: R,
     ) -> ResponseInfo {
-        let resolver = &self.resolver;
         if let Some(query) = request.queries().first() {
             let name = query.name().to_ascii();
             let record_type = query.query_type();
@@ -480,18 +1155,22 @@ impl RequestHandler for DnsResolver {
                     };
 
                     // Log blocked entry
-                    self.send_log(DnsQueryLog {
-                        id: self.next_log_id(),
-                        timestamp: chrono::Utc::now().to_rfc3339(),
-                        domain: domain_clean.clone(),
-                        record_type: record_type_string,
-                        response_records: synthetic_record
-                            .as_ref()
-                            .map(|r| vec![r.data().to_string()])
-                            .unwrap_or_default(),
-                        latency_ms: 0,
-                        status: DnsQueryStatus::Blocked,
-                    });
+                    self.send_log(
+                        DnsQueryLog {
+                            id: self.next_log_id(),
+                            timestamp: chrono::Utc::now().to_rfc3339(),
+                            domain: domain_clean.clone(),
+                            record_type: record_type_string,
+                            response_records: synthetic_record
+                                .as_ref()
+                                .map(|r| vec![r.data().to_string()])
+                                .unwrap_or_default(),
+                            latency_ms: 0,
+                            status: DnsQueryStatus::Blocked,
+                            rebind_hits: Vec::new(),
+                        },
+                        false,
+                    );
 
                     return match result {
                         Err(e) => {
@@ -505,79 +1184,157 @@ impl RequestHandler for DnsResolver {
                 }
             }
 
+            // Special-use names never reach the upstream.
+            if let Some(answer) = self.policy.special_use.answer_for(&domain_clean) {
+                let record_name = Name::from_ascii(&name).unwrap_or_default();
+                return self
+                    .answer_special_use(
+                        request,
+                        response_handle,
+                        answer,
+                        record_name,
+                        record_type,
+                        domain_clean,
+                    )
+                    .await;
+            }
+
             // No rule matched — forward to DoH resolver
+            if !Self::is_forwarded_type(record_type) {
+                error!("Unsupported record type: {:?}", record_type);
+                let response = MessageResponseBuilder::from_message_request(request);
+                let mut header = Header::response_from_request(request.header());
+                header.set_response_code(ResponseCode::NotImp);
+                let result = response_handle
+                    .send_response(response.build_no_records(header))
+                    .await;
+                return match result {
+                    Err(e) => {
+                        error!("Error sending response: {}", e);
+                        let mut err_header = Header::response_from_request(request.header());
+                        err_header.set_response_code(ResponseCode::ServFail);
+                        err_header.into()
+                    }
+                    Ok(info) => info,
+                };
+            }
+
             let start = Instant::now();
 
-            // Perform DNS lookup through DoH resolver and convert to Vec<Record>
-            let records_result: Result<Vec<Record>, _> = match record_type {
-                RecordType::A | RecordType::AAAA => resolver
-                    .lookup_ip(name.clone())
-                    .await
-                    .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
-                RecordType::TXT => resolver
-                    .txt_lookup(name.clone())
-                    .await
-                    .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
-                RecordType::MX => resolver
-                    .mx_lookup(name.clone())
-                    .await
-                    .map(|lookup| lookup.as_lookup().record_iter().cloned().collect()),
-                _ => {
-                    error!("Unsupported record type: {:?}", record_type);
-                    let response = MessageResponseBuilder::from_message_request(request);
-                    let mut header = Header::response_from_request(request.header());
-                    header.set_response_code(ResponseCode::NotImp);
-                    let result = response_handle
-                        .send_response(response.build_no_records(header))
-                        .await;
-                    return match result {
-                        Err(e) => {
-                            error!("Error sending response: {}", e);
-                            let mut err_header = Header::response_from_request(request.header());
-                            err_header.set_response_code(ResponseCode::ServFail);
-                            err_header.into()
-                        }
-                        Ok(info) => info,
-                    };
+            let fallback = self.upstreams.fallback.as_ref();
+            let mut fell_back = false;
+            let mut records_result = match fallback {
+                // Mid-outage: straight to the fallback rather than waiting out the
+                // upstream's timeout on every query.
+                Some(fallback) if !self.upstream_is_due() => {
+                    fell_back = true;
+                    Self::forward(&fallback.resolver, &name, record_type).await
                 }
+                _ => self.upstreams.primary.lookup(&name, record_type).await,
             };
 
+            let mut upstream_error = None;
+            if !fell_back {
+                // The fallback only ever sees queries the upstream failed to answer. An
+                // NXDOMAIN is an answer, so it counts as the upstream being healthy.
+                upstream_error = match records_result {
+                    Err(ref e) if Self::is_upstream_failure(e) => {
+                        Some(self.describe_upstream_error(e))
+                    }
+                    _ => {
+                        self.note_upstream_ok();
+                        None
+                    }
+                };
+                if let (Some(error), Some(fallback)) = (&upstream_error, fallback) {
+                    self.note_fallback(fallback, error);
+                    records_result = Self::forward(&fallback.resolver, &name, record_type).await;
+                    fell_back = true;
+                }
+            }
+
             let latency_ms = start.elapsed().as_millis() as u64;
 
+            // Rebinding protection sees the upstream answer before the client does.
+            let rebind = &self.policy.rebind_protection;
+            let (records_result, rebind_hits) = match records_result {
+                Ok(records) => {
+                    let (kept, caught) = rebind.filter(&domain_clean, records);
+                    (Ok(kept), caught)
+                }
+                Err(e) => (Err(e), Vec::new()),
+            };
+            if !rebind_hits.is_empty() {
+                warn!(
+                    "Possible DNS rebinding: {} resolved to internal address(es) {:?} ({:?})",
+                    domain_clean, rebind_hits, rebind.action
+                );
+            }
+            let rebind_records: Vec<String> = rebind_hits.iter().map(|ip| ip.to_string()).collect();
+
             // Build response
             let response = MessageResponseBuilder::from_message_request(request);
             let mut header = Header::response_from_request(request.header());
 
-            let (result, log_status, log_records) = if let Ok(ref records) = records_result {
-                header.set_response_code(ResponseCode::NoError);
-                let response_records: Vec<String> =
-                    records.iter().map(|r| r.data().to_string()).collect();
-                let send_result = response_handle
-                    .send_response(response.build(header, records.iter(), &[], &[], &[]))
-                    .await;
-                (send_result, DnsQueryStatus::Success, response_records)
-            } else {
-                header.set_response_code(ResponseCode::ServFail);
-                let send_result = response_handle
-                    .send_response(response.build_no_records(header))
-                    .await;
-                (
-                    send_result,
-                    DnsQueryStatus::Error,
-                    vec![records_result.unwrap_err().to_string()],
-                )
+            // That the query left the machine unencrypted outranks a rebind hit; the hit
+            // still travels in `rebind_hits`.
+            let (result, log_status, log_records) = match records_result {
+                Ok(_) if !rebind_hits.is_empty() && rebind.action == RebindAction::Block => {
+                    header.set_response_code(ResponseCode::Refused);
+                    let send_result = response_handle
+                        .send_response(response.build_no_records(header))
+                        .await;
+                    if fell_back {
+                        (send_result, DnsQueryStatus::Fallback, Vec::new())
+                    } else {
+                        (send_result, DnsQueryStatus::Rebind, rebind_records.clone())
+                    }
+                }
+                Ok(records) => {
+                    header.set_response_code(ResponseCode::NoError);
+                    let sent = || records.iter().map(|r| r.data().to_string()).collect();
+                    // A hit is logged with the addresses that were removed, not the ones
+                    // that went out — those are what the user needs to judge the hit.
+                    let (log_status, log_records) = if fell_back {
+                        (DnsQueryStatus::Fallback, sent())
+                    } else if !rebind_hits.is_empty() {
+                        (DnsQueryStatus::Rebind, rebind_records.clone())
+                    } else {
+                        (DnsQueryStatus::Success, sent())
+                    };
+                    let send_result = response_handle
+                        .send_response(response.build(header, records.iter(), &[], &[], &[]))
+                        .await;
+                    (send_result, log_status, log_records)
+                }
+                Err(e) => {
+                    header.set_response_code(ResponseCode::ServFail);
+                    let send_result = response_handle
+                        .send_response(response.build_no_records(header))
+                        .await;
+                    // The fallback's own error, if it had a go; otherwise the upstream's.
+                    let message = match upstream_error {
+                        Some(message) if !fell_back => message,
+                        _ => e.to_string(),
+                    };
+                    (send_result, DnsQueryStatus::Error, vec![message])
+                }
             };
 
             // Log the query
-            self.send_log(DnsQueryLog {
-                id: self.next_log_id(),
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                domain: domain_clean,
-                record_type: record_type_string,
-                response_records: log_records,
-                latency_ms,
-                status: log_status,
-            });
+            self.send_log(
+                DnsQueryLog {
+                    id: self.next_log_id(),
+                    timestamp: chrono::Utc::now().to_rfc3339(),
+                    domain: domain_clean,
+                    record_type: record_type_string,
+                    response_records: log_records,
+                    latency_ms,
+                    status: log_status,
+                    rebind_hits: rebind_records,
+                },
+                false,
+            );
 
             match result {
                 Err(e) => {
@@ -609,3 +1366,263 @@ impl RequestHandler for DnsResolver {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::dns_doh::DohMethod;
+    use crate::dns::dns_test_upstream::{self as upstream, StandInUpstream};
+    use crate::dns::dns_types::DnsRule;
+
+    /// A proxy on an ephemeral loopback port, forwarding to `server`.
+    struct Harness {
+        proxy: DnsServer,
+        addr: SocketAddr,
+        logs: mpsc::UnboundedReceiver<DnsQueryLog>,
+        events: mpsc::UnboundedReceiver<ProxyEvent>,
+    }
+
+    async fn start_proxy(server: &str, rules: DnsRules) -> Harness {
+        let (log_sender, logs) = mpsc::unbounded_channel();
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let mut proxy = DnsServer::new(log_sender, event_sender, Arc::new(RwLock::new(rules)));
+        proxy.listen_v4 = Ipv4Addr::LOCALHOST;
+        proxy.listen_port = 0;
+        proxy
+            .run(
+                server.to_string(),
+                Some("127.0.0.1".to_string()),
+                None,
+                ProxyPolicy::default(),
+                upstream::options(),
+            )
+            .await
+            .unwrap();
+        let addr = SocketAddr::from((proxy.listen_v4, proxy.listen_port));
+        Harness {
+            proxy,
+            addr,
+            logs,
+            events,
+        }
+    }
+
+    async fn next_log(logs: &mut mpsc::UnboundedReceiver<DnsQueryLog>) -> DnsQueryLog {
+        tokio::time::timeout(Duration::from_secs(5), logs.recv())
+            .await
+            .expect("no query was logged")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwards_over_dot_doh_and_doq() {
+        let upstream = StandInUpstream::start().await;
+
+        for url in upstream.urls() {
+            let mut harness = start_proxy(&url, DnsRules::new()).await;
+
+            let answer = upstream::query_udp(harness.addr, "example.com.", RecordType::A).await;
+            assert_eq!(answer.response_code(), ResponseCode::NoError, "{}", url);
+            assert_eq!(
+                answer.answers()[0].data(),
+                &RData::A(A(upstream::EXAMPLE_V4)),
+                "{}",
+                url
+            );
+
+            let log = next_log(&mut harness.logs).await;
+            assert_eq!(log.domain, "example.com");
+            assert!(matches!(log.status, DnsQueryStatus::Success), "{}", url);
+            assert_eq!(harness.proxy.status().counts.queries, 1);
+
+            harness.proxy.shutdown().await.unwrap();
+        }
+        assert!(upstream.queries() >= 3);
+    }
+
+    #[tokio::test]
+    async fn the_client_id_is_sent_as_the_server_name() {
+        let upstream = StandInUpstream::start().await;
+        let [dot, doh, doq] = upstream.urls();
+        let with_id = |doh_method| UpstreamOptions {
+            client_id: Some("abc123".to_string()),
+            doh_method,
+            ..upstream::options()
+        };
+        let connect = |url: &str, options: &UpstreamOptions| {
+            let (domain, port, protocol, endpoint) = DnsServer::parse_server_url(url).unwrap();
+            DnsServer::create_upstream(
+                domain,
+                port,
+                protocol,
+                endpoint,
+                Some("127.0.0.1".to_string()),
+                options,
+            )
+        };
+
+        let cases = [
+            (doh.clone(), with_id(DohMethod::Post)),
+            // A custom header takes it through `dns_doh` rather than hickory.
+            (
+                doh,
+                UpstreamOptions {
+                    http_headers: [("X-Test".to_string(), "1".to_string())].into(),
+                    ..with_id(DohMethod::Post)
+                },
+            ),
+            (doq, with_id(DohMethod::Post)),
+            (upstream.h3_url(), with_id(DohMethod::Post)),
+        ];
+        for (url, options) in &cases {
+            let resolver = connect(url, options).unwrap();
+            resolver
+                .lookup("example.com.", RecordType::A)
+                .await
+                .unwrap_or_else(|e| panic!("{}: {}", url, e));
+        }
+        let expected = format!("abc123.{}", upstream::HOST);
+        assert_eq!(upstream.server_names(), vec![expected; cases.len()]);
+
+        // hickory sends DoT without SNI, so an ID there would go nowhere.
+        let resolver = connect(&dot, &upstream::options()).unwrap();
+        resolver
+            .lookup("example.com.", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(upstream.server_names().len(), cases.len());
+        assert!(connect(&dot, &with_id(DohMethod::Post)).is_err());
+    }
+
+    #[tokio::test]
+    async fn rules_are_answered_without_the_upstream() {
+        let upstream = StandInUpstream::start().await;
+        let mut rules = DnsRules::new();
+        rules.add_rule(DnsRule {
+            id: "1".to_string(),
+            domain: "*.ads.example.com".to_string(),
+            response: "0.0.0.0".to_string(),
+            enabled: true,
+            record_type: "A".to_string(),
+        });
+        let mut harness = start_proxy(&upstream.urls()[0], rules).await;
+
+        let answer =
+            upstream::query_udp(harness.addr, "tracker.ads.example.com.", RecordType::A).await;
+        assert_eq!(
+            answer.answers()[0].data(),
+            &RData::A(A(Ipv4Addr::UNSPECIFIED))
+        );
+
+        let log = next_log(&mut harness.logs).await;
+        assert!(matches!(log.status, DnsQueryStatus::Blocked));
+        assert_eq!(harness.proxy.status().counts.blocked, 1);
+        assert_eq!(upstream.queries(), 0);
+
+        harness.proxy.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_answers_can_be_retried_over_tcp() {
+        let upstream = StandInUpstream::start().await;
+        let mut harness = start_proxy(&upstream.urls()[0], DnsRules::new()).await;
+
+        let udp = upstream::query_udp(harness.addr, "big.example.com.", RecordType::TXT).await;
+        assert!(udp.truncated());
+
+        let tcp = upstream::query_tcp(harness.addr, "big.example.com.", RecordType::TXT).await;
+        assert!(!tcp.truncated());
+        assert_eq!(tcp.answers().len(), upstream::BIG_TXT_RECORDS);
+
+        next_log(&mut harness.logs).await;
+        next_log(&mut harness.logs).await;
+        harness.proxy.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failing_upstream_is_only_retried_now_and_then() {
+        let plain = || {
+            DnsServer::create_plain_resolver(
+                &[IpAddr::from([192, 0, 2, 1])],
+                ResolverOpts::default(),
+            )
+        };
+        let fallback = Fallback {
+            label: "plain resolver".to_string(),
+            resolver: plain(),
+        };
+        let resolver = DnsResolver::new(
+            Upstreams {
+                label: "tls://upstream.example".to_string(),
+                primary: Upstream::Hickory(Box::new(plain())),
+                original: None,
+                fallback: None,
+                pin_report: PinReport::default(),
+                client_certificate: false,
+            },
+            None,
+            None,
+            None,
+            Arc::new(RwLock::new(DnsRules::new())),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(ProxyCounters::default()),
+            ProxyPolicy::default(),
+        );
+        assert!(resolver.upstream_is_due());
+
+        resolver.note_fallback(&fallback, "timed out");
+        assert!(!resolver.upstream_is_due());
+
+        // Once the interval is up, one query tries the upstream and the next does not.
+        *resolver.next_upstream_try.lock().unwrap() = Instant::now();
+        assert!(resolver.upstream_is_due());
+        assert!(!resolver.upstream_is_due());
+
+        resolver.note_upstream_ok();
+        assert!(resolver.upstream_is_due());
+    }
+
+    #[tokio::test]
+    async fn shutdown_releases_the_sockets() {
+        let upstream = StandInUpstream::start().await;
+        let mut harness = start_proxy(&upstream.urls()[0], DnsRules::new()).await;
+        harness.proxy.mark_applied(false, false);
+        assert!(harness.proxy.is_running().await);
+
+        harness.proxy.shutdown().await.unwrap();
+        assert!(!harness.proxy.is_running().await);
+        UdpSocket::bind(harness.addr).await.unwrap();
+        TcpListener::bind(harness.addr).await.unwrap();
+
+        assert!(matches!(
+            harness.events.recv().await,
+            Some(ProxyEvent::Started(_))
+        ));
+        assert!(matches!(
+            harness.events.recv().await,
+            Some(ProxyEvent::Stopped(_))
+        ));
+    }
+
+    #[test]
+    fn filtered_and_refused_answers_count_as_blocked() {
+        let log = |status, rebind_hits: &[&str]| DnsQueryLog {
+            id: 0,
+            timestamp: String::new(),
+            domain: "example.com".to_string(),
+            record_type: "A".to_string(),
+            response_records: Vec::new(),
+            latency_ms: 0,
+            status,
+            rebind_hits: rebind_hits.iter().map(|s| s.to_string()).collect(),
+        };
+        let counters = ProxyCounters::default();
+        counters.count(&log(DnsQueryStatus::Success, &[]), false);
+        counters.count(&log(DnsQueryStatus::Rebind, &["10.0.0.1"]), false);
+        counters.count(&log(DnsQueryStatus::Fallback, &["10.0.0.1"]), false);
+        counters.count(&log(DnsQueryStatus::Local, &[]), true);
+
+        let counts = counters.snapshot();
+        assert_eq!((counts.queries, counts.blocked), (4, 3));
+    }
+}
@@ -0,0 +1,233 @@
+//! Special-use domain names (RFC 6761 and friends), handled inside the proxy.
+//!
+//! These names have no meaning on the public internet. Sending them to a public DoH
+//! provider gains nothing — the answer is NXDOMAIN anyway — and leaks the shape of the
+//! local network: printer names, AD domains, reverse lookups of LAN addresses. The
+//! proxy answers them itself instead, according to this table:
+//!
+//! | Zone                                    | Default              | Reference |
+//! |-----------------------------------------|----------------------|-----------|
+//! | `localhost`, `*.localhost`              | answered locally     | RFC 6761 §6.3 |
+//! | `invalid`                               | NXDOMAIN             | RFC 6761 §6.4 |
+//! | `test`                                  | NXDOMAIN             | RFC 6761 §6.2 |
+//! | `onion`                                 | NXDOMAIN             | RFC 7686 |
+//! | `local`                                 | original resolver    | RFC 6762 |
+//! | `home.arpa`                             | original resolver    | RFC 8375 |
+//! | private and link-local reverse zones    | original resolver    | RFC 6303 |
+//!
+//! `localhost` is fixed: RFC 6761 requires it to resolve to loopback, and there is no
+//! reason to let it be anything else. Every other row can be changed per zone to
+//! NXDOMAIN, REFUSED, the interface's original resolver, or the upstream (the old
+//! behaviour).
+//!
+//! "Original resolver" means the servers the interface had before the proxy was
+//! applied — usually the router, which is the only thing that can answer for
+//! `home.arpa` or a LAN reverse lookup. `local` goes there too rather than to NXDOMAIN
+//! because Active Directory domains named `corp.local` are still common, and their
+//! controllers answer in unicast DNS. When no original resolver is known these fall back
+//! to NXDOMAIN, never to the upstream.
+//!
+//! User rules are checked first, so a rule for `nas.home.arpa` still wins.
+
+use serde::{Deserialize, Serialize};
+
+/// The special-use zone a query name falls into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialUseZone {
+    Localhost,
+    Invalid,
+    Test,
+    Onion,
+    Local,
+    HomeArpa,
+    PrivateReverse,
+}
+
+/// What the proxy does with a query for a special-use zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpecialUseAction {
+    NxDomain,
+    Refused,
+    /// Ask the resolvers the interface had before the proxy was applied.
+    Original,
+    /// Send it to the encrypted upstream like any other name.
+    Upstream,
+}
+
+/// Per-zone actions. `localhost` is not configurable; see the module docs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpecialUsePolicy {
+    pub enabled: bool,
+    pub invalid: SpecialUseAction,
+    pub test: SpecialUseAction,
+    pub onion: SpecialUseAction,
+    pub local: SpecialUseAction,
+    pub home_arpa: SpecialUseAction,
+    pub private_reverse: SpecialUseAction,
+}
+
+impl Default for SpecialUsePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            invalid: SpecialUseAction::NxDomain,
+            test: SpecialUseAction::NxDomain,
+            onion: SpecialUseAction::NxDomain,
+            local: SpecialUseAction::Original,
+            home_arpa: SpecialUseAction::Original,
+            private_reverse: SpecialUseAction::Original,
+        }
+    }
+}
+
+/// How a special-use query is answered, once the policy has been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialUseAnswer {
+    /// Synthesize `127.0.0.1` / `::1`.
+    Loopback,
+    NxDomain,
+    Refused,
+    Original,
+}
+
+impl SpecialUsePolicy {
+    /// Returns how to handle `domain` (lowercase, no trailing dot), or `None` when the
+    /// name is not special-use, the policy is off, or the zone is set to `Upstream`.
+    pub fn answer_for(&self, domain: &str) -> Option<SpecialUseAnswer> {
+        if !self.enabled {
+            return None;
+        }
+        let action = match classify(domain)? {
+            SpecialUseZone::Localhost => return Some(SpecialUseAnswer::Loopback),
+            SpecialUseZone::Invalid => self.invalid,
+            SpecialUseZone::Test => self.test,
+            SpecialUseZone::Onion => self.onion,
+            SpecialUseZone::Local => self.local,
+            SpecialUseZone::HomeArpa => self.home_arpa,
+            SpecialUseZone::PrivateReverse => self.private_reverse,
+        };
+        match action {
+            SpecialUseAction::NxDomain => Some(SpecialUseAnswer::NxDomain),
+            SpecialUseAction::Refused => Some(SpecialUseAnswer::Refused),
+            SpecialUseAction::Original => Some(SpecialUseAnswer::Original),
+            SpecialUseAction::Upstream => None,
+        }
+    }
+}
+
+/// Classifies a lowercase query name without its trailing dot.
+pub fn classify(domain: &str) -> Option<SpecialUseZone> {
+    let in_zone = |zone: &str| {
+        domain == zone
+            || domain
+                .strip_suffix(zone)
+                .is_some_and(|rest| rest.ends_with('.'))
+    };
+
+    if in_zone("localhost") {
+        Some(SpecialUseZone::Localhost)
+    } else if in_zone("invalid") {
+        Some(SpecialUseZone::Invalid)
+    } else if in_zone("test") {
+        Some(SpecialUseZone::Test)
+    } else if in_zone("onion") {
+        Some(SpecialUseZone::Onion)
+    } else if in_zone("local") {
+        Some(SpecialUseZone::Local)
+    } else if in_zone("home.arpa") {
+        Some(SpecialUseZone::HomeArpa)
+    } else if is_private_reverse(domain) {
+        Some(SpecialUseZone::PrivateReverse)
+    } else {
+        None
+    }
+}
+
+/// Reverse zones for RFC 1918, loopback, link-local and unique-local space.
+fn is_private_reverse(domain: &str) -> bool {
+    if let Some(rest) = domain.strip_suffix(".in-addr.arpa") {
+        // Labels are reversed: "5.1.168.192" for 192.168.1.5.
+        let octets: Vec<&str> = rest.rsplit('.').collect();
+        return match octets.as_slice() {
+            ["10", ..] | ["127", ..] => true,
+            ["192", "168", ..] | ["169", "254", ..] => true,
+            ["172", second, ..] => second.parse::<u8>().is_ok_and(|b| (16..=31).contains(&b)),
+            _ => false,
+        };
+    }
+
+    if let Some(rest) = domain.strip_suffix(".ip6.arpa") {
+        // Nibbles are reversed: the last two labels are the first byte.
+        let nibbles: Vec<&str> = rest.rsplit('.').collect();
+        return match nibbles.as_slice() {
+            // fc00::/7, unique local.
+            ["f", "c" | "d", ..] => true,
+            // fe80::/10, link-local.
+            ["f", "e", "8" | "9" | "a" | "b", ..] => true,
+            _ => false,
+        };
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_special_use_zones() {
+        assert_eq!(classify("localhost"), Some(SpecialUseZone::Localhost));
+        assert_eq!(classify("app.localhost"), Some(SpecialUseZone::Localhost));
+        assert_eq!(classify("foo.invalid"), Some(SpecialUseZone::Invalid));
+        assert_eq!(classify("printer.local"), Some(SpecialUseZone::Local));
+        assert_eq!(classify("nas.home.arpa"), Some(SpecialUseZone::HomeArpa));
+        assert_eq!(classify("example.com"), None);
+        assert_eq!(classify("notlocalhost"), None);
+        assert_eq!(classify("latest"), None);
+    }
+
+    #[test]
+    fn recognises_private_reverse_zones() {
+        for name in [
+            "10.in-addr.arpa",
+            "5.0.0.10.in-addr.arpa",
+            "1.1.168.192.in-addr.arpa",
+            "1.0.16.172.in-addr.arpa",
+            "1.0.31.172.in-addr.arpa",
+            "1.0.0.127.in-addr.arpa",
+            "d.f.ip6.arpa",
+            "1.0.0.0.0.8.e.f.ip6.arpa",
+        ] {
+            assert_eq!(
+                classify(name),
+                Some(SpecialUseZone::PrivateReverse),
+                "{}",
+                name
+            );
+        }
+        for name in [
+            "1.1.1.1.in-addr.arpa",
+            "1.0.32.172.in-addr.arpa",
+            "8.8.8.8.in-addr.arpa",
+            "0.0.0.0.0.6.8.4.1.0.0.2.ip6.arpa",
+        ] {
+            assert_eq!(classify(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn upstream_action_means_no_local_handling() {
+        let policy = SpecialUsePolicy {
+            home_arpa: SpecialUseAction::Upstream,
+            ..Default::default()
+        };
+        assert_eq!(policy.answer_for("nas.home.arpa"), None);
+        assert_eq!(
+            policy.answer_for("localhost"),
+            Some(SpecialUseAnswer::Loopback)
+        );
+    }
+}
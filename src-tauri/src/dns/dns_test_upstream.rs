@@ -0,0 +1,299 @@
+//! Local stand-ins for encrypted upstreams, so the proxy can be tested end to end with
+//! no network access.
+//!
+//! [`StandInUpstream`] is one hickory server answering DoT, DoH, DoQ and DoH3 on
+//! ephemeral loopback ports. It presents the self-signed certificate in `testdata/`, for
+//! `upstream.example`, and the proxy trusts it through `self_signed_sha256` — the same
+//! way a user trusts a home-lab resolver. It notes the server name (SNI) each TLS
+//! handshake asked for. It knows two names:
+//!
+//! - `example.com`: A [`EXAMPLE_V4`], AAAA [`EXAMPLE_V6`].
+//! - `big.example.com`: TXT records too large for a UDP answer without EDNS, to make
+//!   the proxy truncate.
+//!
+//! Everything else is NXDOMAIN. The client side, [`query_udp`] and [`query_tcp`], sends
+//! raw messages so tests see the header bits (TC in particular) a resolver would hide.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hickory_proto::op::{Header, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, TXT};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::rustls::default_provider;
+use hickory_server::authority::MessageResponseBuilder;
+use hickory_server::server::{
+    Request, RequestHandler, ResponseHandler, ResponseInfo, ServerFuture,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use super::dns_upstream::UpstreamOptions;
+
+/// The name on the stand-in's certificate.
+pub const HOST: &str = "upstream.example";
+
+pub const EXAMPLE_V4: Ipv4Addr = Ipv4Addr::new(93, 184, 216, 34);
+pub const EXAMPLE_V6: Ipv6Addr =
+    Ipv6Addr::new(0x2606, 0x2800, 0x220, 1, 0x248, 0x1893, 0x25c8, 0x1946);
+
+/// How many TXT records `big.example.com` has, each a 200-byte string.
+pub const BIG_TXT_RECORDS: usize = 30;
+
+const CERTIFICATE: &[u8] = include_bytes!("testdata/upstream.crt");
+const KEY: &[u8] = include_bytes!("testdata/upstream.key");
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct StandInUpstream {
+    pub dot: SocketAddr,
+    pub doh: SocketAddr,
+    pub doq: SocketAddr,
+    pub doh3: SocketAddr,
+    queries: Arc<AtomicUsize>,
+    server_names: Arc<Mutex<Vec<String>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl StandInUpstream {
+    pub async fn start() -> Self {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let mut server = ServerFuture::new(StandIn {
+            queries: queries.clone(),
+        });
+
+        let certificate = CertificateDer::from_pem_slice(CERTIFICATE).unwrap();
+        let key = PrivateKeyDer::from_pem_slice(KEY).unwrap();
+        let server_names = Arc::new(Mutex::new(Vec::new()));
+        let resolver = Arc::new(RecordingCertResolver {
+            key: Arc::new(
+                CertifiedKey::from_der(vec![certificate], key, &default_provider()).unwrap(),
+            ),
+            server_names: server_names.clone(),
+        });
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let dot = listener.local_addr().unwrap();
+        server
+            .register_tls_listener(listener, TIMEOUT, resolver.clone())
+            .unwrap();
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let doh = listener.local_addr().unwrap();
+        server
+            .register_https_listener(
+                listener,
+                TIMEOUT,
+                resolver.clone(),
+                None,
+                "/dns-query".to_string(),
+            )
+            .unwrap();
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let doq = socket.local_addr().unwrap();
+        server
+            .register_quic_listener(socket, TIMEOUT, resolver.clone(), None)
+            .unwrap();
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let doh3 = socket.local_addr().unwrap();
+        server
+            .register_h3_listener(socket, TIMEOUT, resolver, None)
+            .unwrap();
+
+        let task = tokio::spawn(async move {
+            let _ = server.block_until_done().await;
+        });
+        Self {
+            dot,
+            doh,
+            doq,
+            doh3,
+            queries,
+            server_names,
+            task,
+        }
+    }
+
+    /// The DoT, DoH and DoQ URLs, as a user would enter them.
+    pub fn urls(&self) -> [String; 3] {
+        [
+            format!("tls://{}:{}", HOST, self.dot.port()),
+            format!("https://{}:{}/dns-query", HOST, self.doh.port()),
+            format!("quic://{}:{}", HOST, self.doq.port()),
+        ]
+    }
+
+    /// The DoH3 URL, kept out of [`urls`](Self::urls), which the proxy's forwarding
+    /// tests go through.
+    pub fn h3_url(&self) -> String {
+        format!("h3://{}:{}/dns-query", HOST, self.doh3.port())
+    }
+
+    /// The server name each TLS handshake so far sent, in order. Handshakes without one
+    /// are not listed.
+    pub fn server_names(&self) -> Vec<String> {
+        self.server_names.lock().unwrap().clone()
+    }
+
+    /// Queries the stand-in has received, over every transport.
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for StandInUpstream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Upstream options that trust the stand-in's certificate, with caching off so every
+/// query reaches it.
+pub fn options() -> UpstreamOptions {
+    let certificate = CertificateDer::from_pem_slice(CERTIFICATE).unwrap();
+    let fingerprint: String = Sha256::digest(certificate.as_ref())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    UpstreamOptions {
+        self_signed_sha256: Some(fingerprint),
+        cache_size: 0,
+        timeout_ms: TIMEOUT.as_millis() as u64,
+        ..UpstreamOptions::default()
+    }
+}
+
+/// Presents the stand-in's certificate whatever name was asked for, and notes the name.
+#[derive(Debug)]
+struct RecordingCertResolver {
+    key: Arc<CertifiedKey>,
+    server_names: Arc<Mutex<Vec<String>>>,
+}
+
+impl ResolvesServerCert for RecordingCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        if let Some(name) = client_hello.server_name() {
+            self.server_names.lock().unwrap().push(name.to_string());
+        }
+        Some(self.key.clone())
+    }
+}
+
+struct StandIn {
+    queries: Arc<AtomicUsize>,
+}
+
+impl StandIn {
+    /// The records for a name, or `None` if it does not exist.
+    fn answer(name: &str, record_type: RecordType) -> Option<Vec<Record>> {
+        let owner = Name::from_ascii(name).ok()?;
+        let rdata: Vec<RData> = match (name.trim_end_matches('.'), record_type) {
+            ("example.com", RecordType::A) => vec![RData::A(A(EXAMPLE_V4))],
+            ("example.com", RecordType::AAAA) => vec![RData::AAAA(AAAA(EXAMPLE_V6))],
+            ("example.com", _) => Vec::new(),
+            ("big.example.com", RecordType::TXT) => (0..BIG_TXT_RECORDS)
+                .map(|i| RData::TXT(TXT::new(vec![format!("{:02}", i).repeat(100)])))
+                .collect(),
+            ("big.example.com", _) => Vec::new(),
+            _ => return None,
+        };
+        Some(
+            rdata
+                .into_iter()
+                .map(|rdata| Record::from_rdata(owner.clone(), 60, rdata))
+                .collect(),
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for StandIn {
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        mut response_handle: R,
+    ) -> ResponseInfo {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        let answer = request
+            .queries()
+            .first()
+            .and_then(|query| Self::answer(&query.name().to_ascii(), query.query_type()));
+
+        let mut header = Header::response_from_request(request.header());
+        header.set_response_code(match answer {
+            Some(_) => ResponseCode::NoError,
+            None => ResponseCode::NXDomain,
+        });
+        let records = answer.unwrap_or_default();
+        let response = MessageResponseBuilder::from_message_request(request);
+        match response_handle
+            .send_response(response.build(header, records.iter(), &[], &[], &[]))
+            .await
+        {
+            Ok(info) => info,
+            Err(_) => {
+                let mut header = Header::response_from_request(request.header());
+                header.set_response_code(ResponseCode::ServFail);
+                header.into()
+            }
+        }
+    }
+}
+
+fn query(name: &str, record_type: RecordType) -> Vec<u8> {
+    let mut message = Message::new();
+    message
+        .set_id(next_id())
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+    message.to_vec().unwrap()
+}
+
+fn next_id() -> u16 {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed) as u16
+}
+
+/// One query over UDP, without EDNS. hickory then caps the answer at 4096 bytes rather
+/// than RFC 1035's 512, so only answers over that come back truncated.
+pub async fn query_udp(server: SocketAddr, name: &str, record_type: RecordType) -> Message {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    socket
+        .send_to(&query(name, record_type), server)
+        .await
+        .unwrap();
+    let mut buf = vec![0; 4096];
+    let len = tokio::time::timeout(TIMEOUT, socket.recv(&mut buf))
+        .await
+        .expect("no UDP answer")
+        .unwrap();
+    Message::from_vec(&buf[..len]).unwrap()
+}
+
+/// One query over TCP, length-prefixed as RFC 1035 §4.2.2 has it.
+pub async fn query_tcp(server: SocketAddr, name: &str, record_type: RecordType) -> Message {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let message = query(name, record_type);
+        stream.write_u16(message.len() as u16).await?;
+        stream.write_all(&message).await?;
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).await?;
+        std::io::Result::Ok(buf)
+    };
+    let buf = tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .expect("no TCP answer")
+        .unwrap();
+    Message::from_vec(&buf).unwrap()
+}
@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use std::net::IpAddr;
+
+use super::dns_rebind::RebindProtection;
+use super::dns_special_use::SpecialUsePolicy;
+
 pub const MAX_LOG_ENTRIES: usize = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +13,14 @@ pub enum DnsQueryStatus {
     Success,
     Error,
     Blocked,
+    /// A forwarded answer pointed a public name at a private or loopback address and
+    /// was filtered by rebinding protection.
+    Rebind,
+    /// A special-use name (`localhost`, `home.arpa`, …) answered without the upstream.
+    Local,
+    /// The upstream failed and the answer came from the fallback resolver instead —
+    /// which means the query left the machine unencrypted.
+    Fallback,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +32,10 @@ pub struct DnsQueryLog {
     pub response_records: Vec<String>,
     pub latency_ms: u64,
     pub status: DnsQueryStatus,
+    /// Addresses rebinding protection removed from the answer. Kept apart from the
+    /// status so a fallback answer with a hit still shows as [`DnsQueryStatus::Fallback`].
+    #[serde(default)]
+    pub rebind_hits: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,3 +46,79 @@ pub struct DnsRule {
     pub enabled: bool,
     pub record_type: String,
 }
+
+/// Proxy behaviour chosen by the user, sent alongside `set_dns`. Every field has a
+/// default, so a frontend that sends nothing still gets a working proxy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyPolicy {
+    pub rebind_protection: RebindProtection,
+    pub special_use: SpecialUsePolicy,
+    pub upstream_failure: UpstreamFailurePolicy,
+    /// The interface's DNS servers from before the proxy was applied. Filled in by the
+    /// backend at activation, never sent by the frontend.
+    #[serde(skip)]
+    pub original_resolvers: Vec<IpAddr>,
+}
+
+/// What the proxy does when the encrypted upstream cannot answer.
+///
+/// Both fallbacks are plain, unencrypted DNS. That is the trade the user is making —
+/// availability over privacy — so every answer obtained that way is logged with
+/// [`DnsQueryStatus::Fallback`] and the switch raises an event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum UpstreamFailurePolicy {
+    /// Answer SERVFAIL. Nothing leaves the machine unencrypted.
+    #[default]
+    FailClosed,
+    /// Ask the resolvers the interface had before activation (usually DHCP's).
+    OriginalResolvers,
+    /// Ask a designated plain resolver.
+    PlainResolver { servers: Vec<String> },
+}
+
+/// Which of the proxy's four listeners came up.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProxySockets {
+    pub udp_v4: bool,
+    pub tcp_v4: bool,
+    pub udp_v6: bool,
+    pub tcp_v6: bool,
+}
+
+/// Totals since the proxy was last started.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProxyCounts {
+    pub queries: u64,
+    /// Queries answered SERVFAIL because no resolver could answer.
+    pub errors: u64,
+    /// Queries answered by a blocking rule, filtered by rebinding protection, or
+    /// refused as a special-use name.
+    pub blocked: u64,
+}
+
+/// What `get_proxy_status` returns and `proxy-started` carries.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProxyStatus {
+    pub running: bool,
+    pub interface_index: Option<u32>,
+    /// The IPv4 loopback address this proxy serves on.
+    pub listen_v4: Option<String>,
+    /// The upstream as configured, e.g. `https://dns.example/dns-query`.
+    pub upstream: Option<String>,
+    /// The address the upstream connection goes to, once resolved.
+    pub upstream_addr: Option<String>,
+    pub sockets: ProxySockets,
+    /// Whether the interface's IPv6 DNS was pointed at `[::1]`.
+    pub ipv6_redirect: bool,
+    /// Whether the interface has IPv6 DNS servers of its own that were left in place,
+    /// so its IPv6 queries bypass the proxy: `[::1]` was taken by another interface's
+    /// proxy, or this Windows version cannot change IPv6 name servers.
+    pub ipv6_unprotected: bool,
+    /// RFC 3339.
+    pub started_at: Option<String>,
+    /// Stops counting when the proxy stops serving.
+    pub uptime_secs: u64,
+    pub counts: ProxyCounts,
+}
@@ -0,0 +1,229 @@
+//! Per-server upstream settings: how the resolver talks to one DoH/DoT/DoQ/DoH3 server.
+//!
+//! These live on the server entry in the frontend, next to the URL and bootstrap IPs,
+//! and travel with `set_dns` and `test_server`. Everything defaults to hickory's own
+//! defaults, so an entry that sets nothing behaves exactly as before.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use hickory_resolver::config::ResolverOpts;
+use serde::{Deserialize, Serialize};
+
+use super::dns_doh::{self, DohMethod};
+use super::dns_tls::{self, ClientCertificate, PinReport};
+
+/// A lookup whose answer shows whether the resolver applied the user's profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileCanary {
+    pub domain: String,
+    /// `A`, `AAAA`, `TXT` or `MX`.
+    #[serde(default = "ProfileCanary::default_record_type")]
+    pub record_type: String,
+    /// Text the answer must contain, case-insensitively — typically the profile ID or
+    /// a status string. Empty means any answer will do.
+    #[serde(default)]
+    pub expect: String,
+}
+
+impl ProfileCanary {
+    fn default_record_type() -> String {
+        "TXT".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpstreamOptions {
+    /// Per-query timeout. Satellite and congested mobile links need far more than the
+    /// 5 s default.
+    pub timeout_ms: u64,
+    /// Retries after a failed attempt; `0` means a single try.
+    pub attempts: usize,
+    /// Queries sent in parallel when several name servers are configured.
+    pub concurrent_requests: usize,
+    pub edns0: bool,
+    /// DNSSEC-validate answers.
+    pub validate: bool,
+    /// Answer cache size, in records. `0` disables caching.
+    pub cache_size: usize,
+    /// SPKI SHA-256 pins, base64 or hex. When set, the server must present a pinned key
+    /// somewhere in its chain; see `dns_tls`.
+    pub spki_pins: Vec<String>,
+    /// Extra trust anchors, each a PEM file path or inline PEM. Added to the webpki
+    /// roots, not a replacement for them.
+    pub ca_certificates: Vec<String>,
+    /// SHA-256 fingerprint of a self-signed server certificate. When set, that one
+    /// certificate is trusted and the webpki roots and extra CAs are not consulted.
+    pub self_signed_sha256: Option<String>,
+    /// Presented to servers that ask for client authentication. Accepted but never
+    /// serialized: it holds a PKCS#12 password or an inline key.
+    #[serde(skip_serializing)]
+    pub client_certificate: Option<ClientCertificate>,
+    /// DoH (`https://`) only: extra request headers, e.g. `User-Agent` or
+    /// `Authorization`. Never serialized either, for the `Authorization` ones. Not
+    /// available on `h3://` entries nor together with `validate`; see `doh_method`.
+    #[serde(skip_serializing)]
+    pub http_headers: BTreeMap<String, String>,
+    /// DoH (`https://`) only. Anything other than POST with no extra headers is sent by
+    /// `dns_doh` rather than hickory, which speaks HTTP/1.1 and HTTP/2 and does not
+    /// validate DNSSEC: [`check_custom_doh`](Self::check_custom_doh) refuses such an
+    /// entry on an `h3://` URL, or with `validate` set.
+    pub doh_method: DohMethod,
+    /// Profile ID for resolvers that read it from the TLS server name, such as
+    /// NextDNS and AdGuard DNS. The handshake then names `<client_id>.<host>` while the
+    /// connection still goes to `<host>` (or its bootstrap IP). DoH, DoQ and DoH3 only:
+    /// hickory sends no server name at all over DoT, so
+    /// [`check_client_id`](Self::check_client_id) refuses it on a `tls://` entry.
+    pub client_id: Option<String>,
+    /// A name the provider documents for checking that the profile is in effect. Only
+    /// used by the server test.
+    pub profile_canary: Option<ProfileCanary>,
+    /// Where the verifier records a pin mismatch. Filled in by the backend, never sent
+    /// by the frontend.
+    #[serde(skip)]
+    pub pin_report: PinReport,
+}
+
+impl Default for UpstreamOptions {
+    fn default() -> Self {
+        let defaults = ResolverOpts::default();
+        Self {
+            timeout_ms: defaults.timeout.as_millis() as u64,
+            attempts: defaults.attempts,
+            concurrent_requests: defaults.num_concurrent_reqs,
+            edns0: defaults.edns0,
+            validate: defaults.validate,
+            cache_size: defaults.cache_size,
+            spki_pins: Vec::new(),
+            ca_certificates: Vec::new(),
+            self_signed_sha256: None,
+            client_certificate: None,
+            http_headers: BTreeMap::new(),
+            doh_method: DohMethod::Post,
+            client_id: None,
+            profile_canary: None,
+            pin_report: PinReport::default(),
+        }
+    }
+}
+
+impl UpstreamOptions {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn resolver_opts(&self) -> ResolverOpts {
+        let mut opts = ResolverOpts::default();
+        opts.timeout = self.timeout();
+        opts.attempts = self.attempts;
+        opts.num_concurrent_reqs = self.concurrent_requests;
+        opts.edns0 = self.edns0;
+        opts.validate = self.validate;
+        opts.cache_size = self.cache_size;
+        opts
+    }
+
+    /// The options for looking up this server's own address through a bootstrap
+    /// resolver: same timing, none of the TLS or HTTP settings, which belong to this
+    /// server and not to the bootstrap.
+    pub fn for_bootstrap(&self) -> Self {
+        Self {
+            spki_pins: Vec::new(),
+            ca_certificates: Vec::new(),
+            self_signed_sha256: None,
+            client_certificate: None,
+            http_headers: BTreeMap::new(),
+            doh_method: DohMethod::Post,
+            client_id: None,
+            profile_canary: None,
+            pin_report: PinReport::default(),
+            ..self.clone()
+        }
+    }
+
+    /// The TLS server name for `host`: the host itself, or with the client ID
+    /// prepended.
+    pub fn server_name(&self, host: &str) -> String {
+        match self.client_id.as_deref().map(str::trim) {
+            Some(id) if !id.is_empty() => format!("{}.{}", id, host),
+            _ => host.to_string(),
+        }
+    }
+
+    /// True when the entry needs `dns_doh` instead of hickory's DoH.
+    pub fn needs_custom_doh(&self) -> bool {
+        self.doh_method != DohMethod::Post || !self.http_headers.is_empty()
+    }
+
+    /// Rejects a client ID on a DoT entry (`dot`). hickory turns SNI off for DoT, so the
+    /// ID would only be checked against the certificate, never sent, and the provider
+    /// would answer without the user's profile.
+    pub fn check_client_id(&self, dot: bool) -> Result<(), String> {
+        let has_id = self
+            .client_id
+            .as_deref()
+            .is_some_and(|id| !id.trim().is_empty());
+        if dot && has_id {
+            return Err(
+                "A client ID cannot be sent over DNS-over-TLS. Use the provider's DoH or DoQ address for this profile, or put the ID in the tls:// host name."
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects settings `dns_doh` cannot honour, for an entry that needs it: HTTP/3,
+    /// and DNSSEC validation. Both are out of scope for `dns_doh` for now, and an error
+    /// is better than an entry that quietly goes without. `h3` is whether the entry is a
+    /// DoH3 one.
+    pub fn check_custom_doh(&self, h3: bool) -> Result<(), String> {
+        if h3 {
+            return Err(
+                "Custom HTTP headers and GET are not available over HTTP/3. Use an https:// URL for this server, or remove them."
+                    .to_string(),
+            );
+        }
+        if self.validate {
+            return Err(
+                "DNSSEC validation is not available together with custom HTTP headers or GET."
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects values that would make the resolver unusable rather than merely slow.
+    pub fn check(&self) -> Result<(), String> {
+        if self.timeout_ms == 0 {
+            return Err("The query timeout must be greater than zero.".to_string());
+        }
+        if self.timeout_ms > 60_000 {
+            return Err(format!(
+                "A query timeout of {} ms is too long; the maximum is 60000 ms.",
+                self.timeout_ms
+            ));
+        }
+        for pin in &self.spki_pins {
+            dns_tls::parse_pin(pin)?;
+        }
+        if let Some(ref fingerprint) = self.self_signed_sha256 {
+            dns_tls::parse_fingerprint(fingerprint)?;
+        }
+        dns_doh::header_map(&self.http_headers)?;
+        if let Some(id) = self.client_id.as_deref().map(str::trim) {
+            let valid = id.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+            if !id.is_empty() && !valid {
+                return Err(format!(
+                    "'{}' cannot be used as a client ID: only letters, digits and '-' are allowed in a server name.",
+                    id
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,15 @@
+pub mod dns_doh;
+pub mod dns_drift;
+pub mod dns_events;
 pub mod dns_log_store;
+pub mod dns_profiles;
+pub mod dns_rebind;
 pub mod dns_rules;
 pub mod dns_server;
+pub mod dns_special_use;
+#[cfg(test)]
+pub mod dns_test_upstream;
+pub mod dns_tls;
 pub mod dns_types;
+pub mod dns_upstream;
 pub mod dns_utils;
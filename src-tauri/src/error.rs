@@ -53,9 +53,21 @@ pub enum AppError {
     #[error("Windows Management Instrumentation error: {0}")]
     Wmi(String),
 
+    /// systemd-resolved refused or failed a call, on Linux.
+    #[error("systemd-resolved error: {0}")]
+    DBus(String),
+
     #[error("Could not reach DNS server: {0}")]
     Resolver(String),
 
+    /// An encrypted upstream presented a valid certificate chain whose keys match none
+    /// of the SPKI pins on the server entry.
+    #[error("{server} presented a certificate whose key is not pinned (it sent {}). The connection may be intercepted; if the server changed its key on purpose, update the pins.", .presented.join(", "))]
+    PinMismatch {
+        server: String,
+        presented: Vec<String>,
+    },
+
     #[error("{context}: {source}")]
     Io {
         context: String,
@@ -112,7 +124,9 @@ impl AppError {
             AppError::InvalidInput(_) => "invalid_input",
             AppError::Proxy(_) => "proxy",
             AppError::Wmi(_) => "wmi",
+            AppError::DBus(_) => "dbus",
             AppError::Resolver(_) => "resolver",
+            AppError::PinMismatch { .. } => "pin_mismatch",
             AppError::Io { .. } => "io",
             AppError::Store(_) => "store",
             AppError::Task(_) => "task",
@@ -1,15 +1,20 @@
+mod cli;
 mod commands;
 mod dns;
 mod error;
 mod logging;
-mod net_interfaces;
+mod panic_hook;
+mod platform;
 mod types;
+#[cfg(windows)]
 mod utils;
+mod watchdog;
+#[cfg(windows)]
 mod win;
 
 use dns::dns_log_store::DnsLogStore;
+use dns::dns_profiles::InterfaceProfiles;
 use dns::dns_rules::DnsRules;
-use dns::dns_server::DnsServer;
 use dns::dns_types::DnsRule;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -18,22 +23,35 @@ use tauri_plugin_window_state::StateFlags;
 
 use commands::app_logs::{clear_app_logs, get_app_logs, get_log_file_path, open_log_dir};
 use commands::dns::{
-    clear_dns, clear_dns_cache, clear_dns_logs, delete_dns_rule, get_dns_logs, get_dns_rules,
-    get_interface_dns_info, save_dns_rule, set_dns, test_server, toggle_dns_rule,
+    clear_dns, clear_dns_cache, clear_dns_logs, delete_dns_rule, get_active_profiles, get_dns_logs,
+    get_dns_options, get_dns_rules, get_interface_dns_info, get_proxy_status, save_dns_rule,
+    set_dns, set_dns_options, test_server, toggle_dns_rule,
 };
+use commands::dns_batch::{clear_dns_batch, set_dns_batch};
 use commands::net_interfaces::{change_interface_state, get_best_interface, get_interfaces};
+use commands::network_profiles::{
+    delete_network_profile, get_network_fingerprint, get_network_profiles, save_network_profile,
+};
 use tauri::RunEvent;
 use tauri::{Manager, WindowEvent};
 use tokio::sync::{Mutex, RwLock};
 
-use crate::win::clear_stale_doh_dns;
-
 pub struct AppState {
-    pub dns_server: DnsServer,
+    pub profiles: InterfaceProfiles,
+    /// Set while an "Auto" activation follows the default route.
+    pub followed_route: Option<commands::dns::FollowedRoute>,
+    pub network_profiles: commands::network_profiles::NetworkProfiles,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    if let Some(cli::CliCommand::Watchdog { parent_pid }) = cli::Cli::command() {
+        std::process::exit(watchdog::run(parent_pid));
+    }
+
+    // First, so a panic anywhere below still gets the adapters restored.
+    panic_hook::install(Arc::new(panic_hook::SystemRestore));
+
     let rules = Arc::new(RwLock::new(DnsRules::new()));
 
     // DnsLogStore::new() spawns a tokio task, so we need a runtime.
@@ -45,6 +63,8 @@ pub fn run() {
     // We need the log_sender for DnsServer, but DnsLogStore needs tokio.
     // Solution: create a channel pair manually, create DnsLogStore in setup.
     let (log_sender, log_receiver) = tokio::sync::mpsc::unbounded_channel();
+    // Same arrangement for proxy events, which need an `AppHandle` to reach the UI.
+    let (event_sender, event_receiver) = tokio::sync::mpsc::unbounded_channel();
 
     let rules_clone = rules.clone();
 
@@ -77,8 +97,14 @@ pub fn run() {
             get_interfaces,
             set_dns,
             get_interface_dns_info,
+            get_dns_options,
+            set_dns_options,
             clear_dns,
+            set_dns_batch,
+            clear_dns_batch,
             clear_dns_cache,
+            get_proxy_status,
+            get_active_profiles,
             test_server,
             change_interface_state,
             get_dns_logs,
@@ -87,13 +113,19 @@ pub fn run() {
             save_dns_rule,
             delete_dns_rule,
             toggle_dns_rule,
+            get_network_fingerprint,
+            get_network_profiles,
+            save_network_profile,
+            delete_network_profile,
             get_app_logs,
             clear_app_logs,
             get_log_file_path,
             open_log_dir,
         ])
         .manage(Mutex::new(AppState {
-            dns_server: DnsServer::new(log_sender, rules.clone()),
+            profiles: InterfaceProfiles::new(log_sender, event_sender, rules.clone()),
+            followed_route: None,
+            network_profiles: Default::default(),
         }))
         .manage(rules.clone())
         .setup(move |app| {
@@ -103,19 +135,37 @@ pub fn run() {
                 logging::log_file().display()
             );
 
-            // Clean up stale DoH DNS (127.0.0.2) left over from a previous
-            // run that didn't shut down cleanly (e.g. Windows shutdown/crash).
-            clear_stale_doh_dns();
+            // Undo whatever a previous run changed and never reverted (crash, power
+            // loss), using its journal. Then sweep for stale proxy DNS (127.0.0.2) the
+            // journal does not know about, e.g. from a version that had none.
+            let net = platform::system();
+            net.recover();
+            net.restore_all();
 
-            // Push notification of link/address changes, so the UI reacts to a Wi-Fi
-            // switch immediately instead of on the next poll tick. Failure is logged
+            // Restores DNS if this process is killed outright, which no handler here
+            // can catch.
+            watchdog::spawn();
+
+            // Push notification of link, address, route and DNS changes, so the UI
+            // reacts to a Wi-Fi switch immediately instead of on the next poll tick. Failure is logged
             // and non-fatal: the frontend keeps a slow poll as a safety net.
-            win::notify::register(app.handle().clone());
+            let handle = app.handle().clone();
+            net.watch_changes(Arc::new(move |change| {
+                commands::dns::network_changed(&handle, change);
+            }));
+
+            // Change notifications miss DNS set by group policy, which has keys of its
+            // own.
+            commands::dns::spawn_drift_watchdog(app.handle().clone());
 
             // Create and manage the log store, starting the receiver task
             let log_store = DnsLogStore::from_receiver(log_receiver);
             app.manage(log_store);
 
+            dns::dns_events::forward_to_frontend(app.handle().clone(), event_receiver);
+
+            commands::network_profiles::load(app.handle());
+
             // Load persisted rules from store.
             //
             // This hook is NOT a plain sync context: `main` is `#[tokio::main]` and Tauri
@@ -167,18 +217,21 @@ pub fn run() {
         .run(move |_app_handle, _event| match &_event {
             RunEvent::ExitRequested { .. } => {
                 info!("Exit requested — restoring DNS settings before shutdown");
-                // Stop Windows calling back into a process that is tearing down.
-                win::notify::unregister();
+                // Stop the OS calling back into a process that is tearing down.
+                platform::system().unwatch_changes();
                 // Synchronous cleanup — no tokio dependency, completes before
                 // Windows force-kills the process during shutdown.
-                clear_stale_doh_dns();
+                platform::system().restore_all();
             }
             // Second chance. The sweep is idempotent and returns immediately when no
             // interface points at the proxy, so running it again costs nothing — and
             // leaving 127.0.0.2 applied costs the user their internet.
             RunEvent::Exit => {
                 info!("Exiting — final DNS restore check");
-                clear_stale_doh_dns();
+                platform::system().restore_all();
+                platform::system().settle_on_exit();
+                // Last: the watchdog stands down only once there is nothing left to do.
+                watchdog::signal_clean_exit();
             }
             RunEvent::WindowEvent {
                 event: WindowEvent::CloseRequested { .. },
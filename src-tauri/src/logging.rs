@@ -15,7 +15,7 @@
 //! instead of being split into fragments.
 
 use std::fs;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 use serde::Serialize;
@@ -88,29 +88,80 @@ pub fn plugin<R: tauri::Runtime>() -> tauri::plugin::TauriPlugin<R> {
         // chatty at debug level and would bury the app's own lines. The webview target
         // is explicitly included so `@tauri-apps/plugin-log` calls from the React side
         // land in the same file, in order, instead of only in the devtools console.
-        .filter(|metadata| {
-            let target = metadata.target();
-            target.starts_with("better_dns_jumper_lib") || target.starts_with(WEBVIEW_TARGET)
-        })
+        .filter(|metadata| is_app_target(metadata.target()))
         .format(|out, message, record| {
             out.finish(format_args!(
-                "{} [{:<5}] [{}] {}",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                record.level(),
-                record.target(),
-                message
+                "{}",
+                format_line(record.level(), record.target(), message)
             ))
-        });
+        })
+        // Debug builds record debug-level detail; release stays at info so a
+        // long-running session doesn't rotate away useful history behind a wall of
+        // routine chatter.
+        .level(max_level());
+
+    builder.build()
+}
+
+/// The line format, shared by the plugin and [`StandaloneLogger`]. `parse_line` must
+/// be able to read it back.
+fn format_line(level: log::Level, target: &str, message: &std::fmt::Arguments) -> String {
+    format!(
+        "{} [{:<5}] [{}] {}",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        level,
+        target,
+        message
+    )
+}
 
-    // Debug builds record debug-level detail; release stays at info so a long-running
-    // session doesn't rotate away useful history behind a wall of routine chatter.
-    let builder = if cfg!(debug_assertions) {
-        builder.level(log::LevelFilter::Debug)
+fn is_app_target(target: &str) -> bool {
+    target.starts_with("better_dns_jumper_lib") || target.starts_with(WEBVIEW_TARGET)
+}
+
+fn max_level() -> log::LevelFilter {
+    if cfg!(debug_assertions) {
+        log::LevelFilter::Debug
     } else {
-        builder.level(log::LevelFilter::Info)
-    };
+        log::LevelFilter::Info
+    }
+}
 
-    builder.build()
+/// Logger for processes that run without Tauri, i.e. the watchdog. Appends to the same
+/// file in the same format, so its lines appear in the viewer in order with the app's.
+/// The file is opened per line: these processes log a handful of lines at most, and
+/// the app's logger may rotate the file in between.
+struct StandaloneLogger;
+
+impl log::Log for StandaloneLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        is_app_target(metadata.target()) && metadata.level() <= max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(record.level(), record.target(), record.args());
+        let _ = fs::create_dir_all(log_dir());
+        if let Ok(mut file) = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file())
+        {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs [`StandaloneLogger`]. For processes that never build the Tauri app.
+pub fn init_standalone() {
+    static LOGGER: StandaloneLogger = StandaloneLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(max_level());
+    }
 }
 
 /// Splits a log line into its parts, or returns `None` if it is a continuation line.
@@ -249,6 +300,19 @@ mod tests {
         assert_eq!(entry.message, "Interface [12] is down");
     }
 
+    #[test]
+    fn reads_back_its_own_format() {
+        let line = format_line(
+            log::Level::Warn,
+            "better_dns_jumper_lib::watchdog",
+            &format_args!("Parent {} exited", 42),
+        );
+        let entry = parse_line(&line, 0).expect("should parse");
+        assert_eq!(entry.level, "WARN");
+        assert_eq!(entry.target, "better_dns_jumper_lib::watchdog");
+        assert_eq!(entry.message, "Parent 42 exited");
+    }
+
     #[test]
     fn handles_multibyte_messages() {
         let line =
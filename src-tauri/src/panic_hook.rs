@@ -0,0 +1,158 @@
+//! Restores DNS when the process is going down with a panic.
+//!
+//! The `RunEvent::ExitRequested` / `Exit` handlers in `lib.rs` are not reached when the
+//! Rust side panics — the event loop is what unwinds. The adapter is then left pointing
+//! at a proxy that is about to disappear, and the user has no DNS until the next launch
+//! runs the startup sweep. The hook installed here runs first: it logs the panic with a
+//! backtrace to the app log, runs the same blocking restore the exit handler uses, and
+//! writes a last entry saying so.
+//!
+//! Only panics that end the process restore anything. A panic inside a tokio task is
+//! caught by tokio and the app keeps running with the proxy still serving; pulling the
+//! adapters off it then would silently drop protection over a single failed query. The
+//! main thread runs the event loop, so a panic there is fatal, as is any panic in a
+//! build with `panic = "abort"`. The OS notification callbacks and watch threads are
+//! the exception: a panic unwinding out of an `extern "system"` callback aborts the
+//! process from a thread this hook does not treat as fatal, so those catch their own
+//! panics with `platform::guard_callback` instead.
+
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use log::{error, info};
+
+/// What the hook needs from the platform: put every adapter we changed back.
+pub trait DnsRestore: Send + Sync {
+    fn restore_all(&self);
+}
+
+/// The real adapters, via the sweep the exit handler runs.
+pub struct SystemRestore;
+
+impl DnsRestore for SystemRestore {
+    fn restore_all(&self) {
+        crate::platform::system().restore_all();
+    }
+}
+
+/// Set once the fatal path has run. A second panic — from another thread, or from the
+/// restore itself — must not run it again.
+static RESTORED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the hook in front of whatever hook is already set, which still runs
+/// afterwards.
+pub fn install(restore: Arc<dyn DnsRestore>) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let report = PanicReport::from_info(info);
+        handle(&report, restore.as_ref(), &RESTORED);
+        previous(info);
+    }));
+}
+
+/// The parts of a panic the hook acts on.
+#[derive(Debug)]
+struct PanicReport {
+    message: String,
+    location: String,
+    thread: String,
+    fatal: bool,
+}
+
+impl PanicReport {
+    fn from_info(info: &PanicHookInfo<'_>) -> Self {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "(non-string panic payload)".to_string());
+        let thread = std::thread::current()
+            .name()
+            .unwrap_or("unnamed")
+            .to_string();
+
+        Self {
+            message,
+            location: info
+                .location()
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| "unknown location".to_string()),
+            fatal: cfg!(panic = "abort") || thread == "main",
+            thread,
+        }
+    }
+}
+
+fn handle(report: &PanicReport, restore: &dyn DnsRestore, restored: &AtomicBool) {
+    error!(
+        "Panic on thread '{}' at {}: {}\n{}",
+        report.thread,
+        report.location,
+        report.message,
+        Backtrace::force_capture()
+    );
+
+    if !report.fatal || restored.swap(true, Ordering::SeqCst) {
+        log::logger().flush();
+        return;
+    }
+
+    error!("The panic is fatal — restoring DNS before the process exits");
+    restore.restore_all();
+    info!("DNS restore after panic finished; exiting");
+    log::logger().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct MockRestore {
+        calls: AtomicUsize,
+    }
+
+    impl DnsRestore for MockRestore {
+        fn restore_all(&self) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn report(fatal: bool) -> PanicReport {
+        PanicReport {
+            message: "boom".to_string(),
+            location: "src/lib.rs:1:1".to_string(),
+            thread: if fatal {
+                "main"
+            } else {
+                "tokio-runtime-worker"
+            }
+            .to_string(),
+            fatal,
+        }
+    }
+
+    #[test]
+    fn fatal_panics_restore_once() {
+        let mock = MockRestore::default();
+        let restored = AtomicBool::new(false);
+
+        handle(&report(true), &mock, &restored);
+        handle(&report(true), &mock, &restored);
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn task_panics_leave_dns_alone() {
+        let mock = MockRestore::default();
+        let restored = AtomicBool::new(false);
+
+        handle(&report(false), &mock, &restored);
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 0);
+        assert!(!restored.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,85 @@
+//! What the app has written to DNS and not yet put back, so a crash can be undone on
+//! the next launch.
+//!
+//! The counterpart of the Windows change journal, and smaller: the snapshot file
+//! already holds what to put back, so all this keeps is, per snapshot key — a link name,
+//! or `resolv.conf` — the servers last written there. An entry is recorded *before* the
+//! write, and completed once the snapshot has been restored, or on a clean exit when it
+//! is plain DNS, which is meant to stay. Whatever is still pending at startup was never
+//! put back, and `recover` restores it — unless the servers are no longer the ones
+//! written, in which case something else has changed them since and the entry is
+//! abandoned, as on Windows.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use log::warn;
+
+use super::save_all;
+use crate::error::{AppError, AppResult};
+use crate::platform::state_dir;
+
+const JOURNAL_FILE: &str = "dns_journal.json";
+
+/// Serializes the read-modify-write of the file within this process.
+static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+/// Records that `written` is about to be applied under `key`, replacing whatever was
+/// recorded there before.
+pub fn record(key: &str, written: &[IpAddr]) -> AppResult<()> {
+    let _guard = lock();
+    let path = path();
+    let mut pending = load(&path);
+    pending.insert(key.to_string(), written.to_vec());
+    save_all(&path, &pending)
+}
+
+/// Forgets the entry under `key`: it has been put back, or is meant to stay.
+pub fn complete(key: &str) {
+    let _guard = lock();
+    let path = path();
+    let mut pending = load(&path);
+    if pending.remove(key).is_none() {
+        return;
+    }
+    let saved = if pending.is_empty() {
+        std::fs::remove_file(&path)
+            .map_err(|e| AppError::io(format!("Could not remove {}", path.display()), e))
+    } else {
+        save_all(&path, &pending)
+    };
+    if let Err(e) = saved {
+        warn!("Could not complete the DNS journal entry of {}: {}", key, e);
+    }
+}
+
+/// Every entry not completed yet, with the servers written there.
+pub fn pending() -> BTreeMap<String, Vec<IpAddr>> {
+    let _guard = lock();
+    load(&path())
+}
+
+fn lock() -> MutexGuard<'static, ()> {
+    JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn path() -> PathBuf {
+    state_dir().join(JOURNAL_FILE)
+}
+
+/// A missing or unreadable file has nothing pending.
+fn load(path: &Path) -> BTreeMap<String, Vec<IpAddr>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring an unreadable DNS journal in {}: {}",
+                path.display(),
+                e
+            );
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
@@ -0,0 +1,1038 @@
+//! [`DnsConfigurator`] on Linux.
+//!
+//! DNS goes through systemd-resolved when it is running and through `/etc/resolv.conf`
+//! otherwise; the choice is made once, when the configurator is created. Interfaces
+//! come from sysfs and `getifaddrs`, routes from procfs, and change notifications from
+//! rtnetlink and inotify. The SSID of a wireless link comes from NetworkManager, when it
+//! is running.
+//!
+//! Snapshots are saved the same way as on Windows — a JSON file in the state directory,
+//! keyed by something that survives a reboot — so a restore after a crash still knows
+//! what to put back. A smaller change journal than Windows' records what was written
+//! before each write (see [`journal`]), so a crash in the middle of a plain-DNS
+//! activation or a `resolv.conf` rewrite is undone on the next launch too, not only
+//! what still points at a proxy address.
+//!
+//! Binding the proxy to port 53 and changing DNS both need privileges: run the app as
+//! root, or grant it `CAP_NET_BIND_SERVICE` and `CAP_NET_ADMIN`.
+
+mod journal;
+mod network_manager;
+mod resolv_conf;
+mod resolved;
+mod watch;
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    is_proxy_addr, state_dir, AdapterKind, ChangeCallback, DnsConfigurator, DnsOptions, Family,
+    InterfaceStats, NetworkFingerprint, NetworkInterface,
+};
+use crate::error::{AppError, AppResult};
+use resolved::{Resolved, ROUTE_ALL};
+
+const SNAPSHOT_FILE: &str = "dns_snapshots.json";
+
+/// Key of the `/etc/resolv.conf` snapshot: the file is global, not per interface.
+const RESOLV_CONF_KEY: &str = "resolv.conf";
+
+// IANA ifType values, to match what Windows reports for the same kinds of adapter.
+const IF_TYPE_OTHER: u32 = 1;
+const IF_TYPE_ETHERNET: u32 = 6;
+const IF_TYPE_PPP: u32 = 23;
+const IF_TYPE_SOFTWARE_LOOPBACK: u32 = 24;
+const IF_TYPE_IEEE80211: u32 = 71;
+const IF_TYPE_TUNNEL: u32 = 131;
+
+// ARPHRD values from `/sys/class/net/<name>/type` (linux/if_arp.h).
+const ARPHRD_ETHER: u32 = 1;
+const ARPHRD_PPP: u32 = 512;
+const ARPHRD_LOOPBACK: u32 = 772;
+const ARPHRD_NONE: u32 = 65534;
+
+enum Backend {
+    Resolved(Resolved),
+    ResolvConf,
+}
+
+pub struct LinuxConfigurator {
+    backend: Backend,
+    /// With `resolv.conf`, the interface whose activation the file currently carries.
+    /// The file is global, so a second interface is refused rather than layered on top,
+    /// and only this one's deactivation puts the snapshot back.
+    resolv_conf_owner: Mutex<Option<u32>>,
+}
+
+/// How DNS was configured before the app changed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+enum Snapshot {
+    /// The link's runtime servers and domains, and its LLMNR mode. No servers and no
+    /// domains means nothing was set at runtime, and restoring is a `RevertLink` — which
+    /// puts LLMNR back as well.
+    Resolved {
+        servers: Vec<IpAddr>,
+        domains: Vec<(String, bool)>,
+        #[serde(default)]
+        llmnr: String,
+    },
+    /// The whole file.
+    ResolvConf { text: String },
+}
+
+/// Serializes the read-modify-write of the snapshot file.
+static SNAPSHOT_LOCK: Mutex<()> = Mutex::new(());
+
+impl LinuxConfigurator {
+    pub fn new() -> Self {
+        let backend = match Resolved::connect() {
+            Some(resolved) => {
+                info!("Configuring DNS through systemd-resolved");
+                Backend::Resolved(resolved)
+            }
+            None => {
+                info!(
+                    "systemd-resolved is not running; configuring DNS through {}",
+                    resolv_conf::PATH
+                );
+                Backend::ResolvConf
+            }
+        };
+        Self {
+            backend,
+            resolv_conf_owner: Mutex::new(None),
+        }
+    }
+
+    /// With `resolv.conf`, makes `if_index` the owner of the file, unless another
+    /// interface already is. Always succeeds with resolved, where links are separate.
+    fn claim(&self, if_index: u32) -> AppResult<()> {
+        if !matches!(self.backend, Backend::ResolvConf) {
+            return Ok(());
+        }
+        let mut owner = self
+            .resolv_conf_owner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        match *owner {
+            Some(other) if other != if_index => Err(AppError::invalid(format!(
+                "Without systemd-resolved, DNS is set in {} for every interface at once, and interface {} already has it. Deactivate that one first.",
+                resolv_conf::PATH, other
+            ))),
+            _ => {
+                *owner = Some(if_index);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether restoring `if_index` may touch the snapshot: with `resolv.conf`, only the
+    /// owner's may, or anyone's when there is no owner, as after a crash.
+    fn owns(&self, if_index: u32) -> bool {
+        match self.backend {
+            Backend::Resolved(_) => true,
+            Backend::ResolvConf => self
+                .resolv_conf_owner
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_none_or(|owner| owner == if_index),
+        }
+    }
+
+    fn release(&self) {
+        *self
+            .resolv_conf_owner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn snapshot_key(&self, if_index: u32) -> AppResult<String> {
+        match self.backend {
+            Backend::Resolved(_) => link_name(if_index),
+            Backend::ResolvConf => Ok(RESOLV_CONF_KEY.to_string()),
+        }
+    }
+
+    fn read_snapshot(&self, if_index: u32) -> AppResult<Snapshot> {
+        Ok(match &self.backend {
+            Backend::Resolved(resolved) => {
+                let servers = resolved.link_dns(if_index)?;
+                // `~.` is ours only when it came with a proxy. A link that routes every
+                // name to its own servers — a VPN, typically — keeps it.
+                let ours = !servers.is_empty() && servers.iter().all(is_proxy_addr);
+                Snapshot::Resolved {
+                    servers: servers
+                        .into_iter()
+                        .filter(|ip| !is_proxy_addr(ip))
+                        .collect(),
+                    domains: resolved
+                        .link_domains(if_index)?
+                        .into_iter()
+                        .filter(|(domain, _)| !(ours && domain == ROUTE_ALL))
+                        .collect(),
+                    llmnr: resolved.link_llmnr(if_index)?,
+                }
+            }
+            // A previous run's proxy lines are not worth restoring.
+            Backend::ResolvConf => Snapshot::ResolvConf {
+                text: resolv_conf::without_proxies(&resolv_conf::read()?),
+            },
+        })
+    }
+
+    fn apply_snapshot(&self, if_index: u32, snapshot: Option<&Snapshot>) -> AppResult<()> {
+        match (&self.backend, snapshot) {
+            (
+                Backend::Resolved(resolved),
+                Some(Snapshot::Resolved {
+                    servers,
+                    domains,
+                    llmnr,
+                }),
+            ) if !servers.is_empty() || !domains.is_empty() => {
+                resolved.set_link_dns(if_index, servers)?;
+                resolved.set_link_domains(if_index, domains)?;
+                resolved.set_link_llmnr(if_index, llmnr)
+            }
+            (Backend::Resolved(resolved), _) => resolved.revert_link(if_index),
+            (Backend::ResolvConf, Some(Snapshot::ResolvConf { text })) => resolv_conf::write(text),
+            // Nothing saved: drop the proxy lines and keep the rest.
+            (Backend::ResolvConf, _) => {
+                resolv_conf::write(&resolv_conf::without_proxies(&resolv_conf::read()?))
+            }
+        }
+    }
+}
+
+impl DnsConfigurator for LinuxConfigurator {
+    fn list_interfaces(&self) -> AppResult<Vec<NetworkInterface>> {
+        let addresses = read_addresses();
+        let gateways = read_gateways();
+        let dns: Vec<(u32, IpAddr)> = match &self.backend {
+            Backend::Resolved(resolved) => resolved.dns()?,
+            Backend::ResolvConf => Vec::new(),
+        };
+        let global_dns = match self.backend {
+            Backend::Resolved(_) => Vec::new(),
+            Backend::ResolvConf => resolv_conf::nameservers(&resolv_conf::read()?),
+        };
+
+        let mut interfaces = Vec::new();
+        for link in read_links()? {
+            let dns_servers: Vec<String> = match self.backend {
+                Backend::Resolved(_) => dns
+                    .iter()
+                    .filter(|(index, _)| *index == link.index)
+                    .map(|(_, ip)| ip.to_string())
+                    .collect(),
+                Backend::ResolvConf => global_dns.iter().map(IpAddr::to_string).collect(),
+            };
+            let lease = match link.if_type {
+                IF_TYPE_SOFTWARE_LOOPBACK => None,
+                _ => dhcp_lease(&link),
+            };
+            // Servers DHCP did not hand out were set by someone. Without a lease, any
+            // server at all was.
+            let dns_is_static = dns_servers.iter().any(|server| {
+                lease
+                    .as_ref()
+                    .is_none_or(|lease| !lease.dns.iter().any(|ip| ip.to_string() == *server))
+            });
+            let description = link.driver.clone().unwrap_or_else(|| link.name.clone());
+            interfaces.push(NetworkInterface {
+                interface_index: link.index,
+                ipv6_interface_index: link.index,
+                kind: AdapterKind::classify(link.if_type, link.hardware, &link.name, &description),
+                description,
+                mac_address: link.mac,
+                if_type: link.if_type,
+                is_up: link.is_up,
+                is_admin_disabled: !link.admin_up,
+                ip_addresses: addresses.get(&link.name).cloned().unwrap_or_default(),
+                gateways: gateways.get(&link.name).cloned().unwrap_or_default(),
+                dns_servers,
+                dhcp_enabled: lease.is_some(),
+                dhcp_server: lease
+                    .and_then(|lease| lease.server)
+                    .map(|ip| ip.to_string()),
+                dns_is_static,
+                mtu: link.mtu,
+                link_speed: link.speed,
+                stats: link.stats,
+                name: link.name,
+            });
+        }
+        Ok(interfaces)
+    }
+
+    /// The interface of the IPv4 default route with the lowest metric, or the IPv6 one
+    /// if there is no IPv4 default route — the same preference as on Windows.
+    fn best_interface_index(&self) -> AppResult<u32> {
+        let links = read_links()?;
+        let usable = |name: &str| {
+            links.iter().find(|l| {
+                l.name == name
+                    && l.is_up
+                    && AdapterKind::classify(l.if_type, l.hardware, &l.name, "")
+                        .is_auto_selectable()
+            })
+        };
+
+        for routes in [
+            std::fs::read_to_string("/proc/net/route")
+                .map(|text| default_routes_v4(&text))
+                .unwrap_or_default(),
+            std::fs::read_to_string("/proc/net/ipv6_route")
+                .map(|text| default_routes_v6(&text))
+                .unwrap_or_default(),
+        ] {
+            if let Some(link) = routes
+                .iter()
+                .filter_map(|route| Some((route.metric, usable(&route.interface)?)))
+                .min_by_key(|(metric, _)| *metric)
+                .map(|(_, link)| link)
+            {
+                return Ok(link.index);
+            }
+        }
+        Err(AppError::NoActiveInterface)
+    }
+
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        match &self.backend {
+            Backend::Resolved(resolved) => resolved.link_dns(if_index),
+            Backend::ResolvConf => Ok(resolv_conf::nameservers(&resolv_conf::read()?)),
+        }
+    }
+
+    fn supports_ipv6_dns(&self) -> bool {
+        true
+    }
+
+    fn snapshot_dns(&self, if_index: u32) -> AppResult<()> {
+        let key = self.snapshot_key(if_index)?;
+        let _guard = SNAPSHOT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = snapshot_path();
+        let mut snapshots = load_all(&path);
+        if snapshots.contains_key(&key) {
+            return self.claim(if_index);
+        }
+
+        let snapshot = self.read_snapshot(if_index)?;
+        debug!(
+            "Saved the original DNS of interface {}: {:?}",
+            if_index, snapshot
+        );
+        snapshots.insert(key, snapshot);
+        save_all(&path, &snapshots)?;
+        self.claim(if_index)
+    }
+
+    /// With resolved, servers being set also get the `~.` routing domain — see
+    /// `resolved.rs`. With `resolv.conf`, this sets DNS for every interface at once.
+    fn set_dns(&self, if_index: u32, family: Family, servers: &[IpAddr]) -> AppResult<()> {
+        let key = self.snapshot_key(if_index)?;
+        match &self.backend {
+            Backend::Resolved(resolved) => {
+                let mut updated: Vec<IpAddr> = servers.to_vec();
+                updated.extend(
+                    resolved
+                        .link_dns(if_index)?
+                        .into_iter()
+                        .filter(|ip| Family::of(ip) != family),
+                );
+                if updated.is_empty() {
+                    return resolved.revert_link(if_index);
+                }
+                journal::record(&key, &updated)?;
+                resolved.set_link_dns(if_index, &updated)?;
+
+                let mut domains = resolved.link_domains(if_index)?;
+                if !domains.iter().any(|(domain, _)| domain == ROUTE_ALL) {
+                    domains.push((ROUTE_ALL.to_string(), true));
+                    resolved.set_link_domains(if_index, &domains)?;
+                }
+                Ok(())
+            }
+            Backend::ResolvConf => {
+                let text = resolv_conf::with_nameservers(&resolv_conf::read()?, family, servers);
+                journal::record(&key, &resolv_conf::nameservers(&text))?;
+                resolv_conf::write(&text)
+            }
+        }
+    }
+
+    fn dns_is_global(&self) -> bool {
+        matches!(self.backend, Backend::ResolvConf)
+    }
+
+    /// With `resolv.conf`, an interface other than the one that owns the file has
+    /// nothing of its own to put back, so it is left alone and reported as restored.
+    fn restore_dns(&self, if_index: u32) -> bool {
+        if !self.owns(if_index) {
+            debug!(
+                "Interface {} does not own {}; leaving it as it is",
+                if_index,
+                resolv_conf::PATH
+            );
+            return true;
+        }
+        let key = match self.snapshot_key(if_index) {
+            Ok(key) => key,
+            Err(e) => {
+                error!("Cannot restore interface {}: {}", if_index, e);
+                return false;
+            }
+        };
+        let _guard = SNAPSHOT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = snapshot_path();
+        let mut snapshots = load_all(&path);
+
+        if let Err(e) = self.apply_snapshot(if_index, snapshots.get(&key)) {
+            error!("Could not restore DNS on interface {}: {}", if_index, e);
+            return false;
+        }
+        journal::complete(&key);
+        if snapshots.remove(&key).is_some() {
+            if let Err(e) = save_all(&path, &snapshots) {
+                warn!(
+                    "Could not drop the DNS snapshot of interface {}: {}",
+                    if_index, e
+                );
+            }
+        }
+        self.release();
+        !self.interface_uses_proxy_dns(if_index)
+    }
+
+    fn restore_all(&self) {
+        // Everything goes back, whichever interface the activation was made on — it may
+        // not even be present any more.
+        self.release();
+        let interfaces = match self.list_interfaces() {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                error!("restore_all: failed to list interfaces: {}", e);
+                return;
+            }
+        };
+
+        for iface in interfaces {
+            // Checked again for each interface: with `resolv.conf`, restoring the first
+            // one restores them all.
+            if !self.interface_uses_proxy_dns(iface.interface_index) {
+                continue;
+            }
+            info!(
+                "Clearing stale proxy DNS on interface {} ({})",
+                iface.interface_index, iface.name
+            );
+            if !self.restore_dns(iface.interface_index) {
+                error!(
+                    "Could not clear proxy DNS from interface {} ({}) — name resolution on it is likely broken",
+                    iface.interface_index, iface.name
+                );
+            }
+        }
+    }
+
+    /// Restores what a previous run wrote and never put back; see [`journal`].
+    fn recover(&self) {
+        for (key, written) in journal::pending() {
+            let if_index = match self.backend {
+                Backend::Resolved(_) => link_index(&key),
+                // Any index will do: the file is global.
+                Backend::ResolvConf => (key == RESOLV_CONF_KEY).then_some(0),
+            };
+            let Some(if_index) = if_index else {
+                info!(
+                    "{} is gone since DNS was last written to it; nothing to restore",
+                    key
+                );
+                journal::complete(&key);
+                continue;
+            };
+            match self.interface_dns_servers(if_index) {
+                Ok(current) if current == written => {
+                    info!("Restoring the DNS a previous run left on {}", key);
+                    if !self.restore_dns(if_index) {
+                        error!("Could not restore the DNS a previous run left on {}", key);
+                    }
+                }
+                Ok(_) => {
+                    info!(
+                        "DNS on {} has changed since a previous run wrote it; leaving it as it is",
+                        key
+                    );
+                    journal::complete(&key);
+                }
+                Err(e) => error!("Cannot check the DNS on {}: {}", key, e),
+            }
+        }
+    }
+
+    /// Plain DNS is meant to stay. Anything still pointing at a proxy stays pending:
+    /// the sweep failed to restore it, and the next launch must.
+    fn settle_on_exit(&self) {
+        for (key, written) in journal::pending() {
+            if !written.iter().any(is_proxy_addr) {
+                journal::complete(&key);
+            }
+        }
+    }
+
+    /// Linux resolvers have no notion of a connection-specific suffix, so the first
+    /// search domain stands in for it. Nothing registers addresses in DNS either, and
+    /// without resolved there is no LLMNR.
+    fn dns_options(&self, if_index: u32) -> AppResult<DnsOptions> {
+        let (mut domains, llmnr) = match &self.backend {
+            Backend::Resolved(resolved) => (
+                resolved
+                    .link_domains(if_index)?
+                    .into_iter()
+                    .filter(|(_, route_only)| !route_only)
+                    .map(|(domain, _)| domain)
+                    .collect::<Vec<_>>(),
+                resolved.link_llmnr(if_index)? != "no",
+            ),
+            Backend::ResolvConf => (resolv_conf::search(&resolv_conf::read()?), false),
+        };
+        let suffix = if domains.is_empty() {
+            String::new()
+        } else {
+            domains.remove(0)
+        };
+        Ok(DnsOptions {
+            suffix,
+            search_list: domains,
+            llmnr,
+            ..DnsOptions::default()
+        })
+    }
+
+    /// Routing-only domains, the proxy's `~.` among them, are left as they are. With
+    /// `resolv.conf`, the search line is global like the name servers.
+    fn set_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()> {
+        if options.register_addresses || options.register_with_suffix {
+            return Err(AppError::invalid(
+                "Dynamic DNS registration is not available on Linux; addresses are \
+                 registered by the DHCP server, if at all.",
+            ));
+        }
+        let mut search: Vec<String> = Vec::new();
+        for domain in std::iter::once(&options.suffix).chain(&options.search_list) {
+            if !domain.is_empty() && !search.contains(domain) {
+                search.push(domain.clone());
+            }
+        }
+
+        match &self.backend {
+            Backend::Resolved(resolved) => {
+                let mut domains: Vec<(String, bool)> =
+                    search.into_iter().map(|domain| (domain, false)).collect();
+                domains.extend(
+                    resolved
+                        .link_domains(if_index)?
+                        .into_iter()
+                        .filter(|(_, route_only)| *route_only),
+                );
+                resolved.set_link_domains(if_index, &domains)?;
+                resolved.set_link_llmnr(if_index, if options.llmnr { "yes" } else { "no" })
+            }
+            Backend::ResolvConf => {
+                if options.llmnr {
+                    return Err(AppError::invalid(
+                        "LLMNR needs systemd-resolved, which is not running.",
+                    ));
+                }
+                let text = resolv_conf::read()?;
+                resolv_conf::write(&resolv_conf::with_search(&text, &search))
+            }
+        }
+    }
+
+    /// The gateway MAC comes from the ARP table, so there is none for an IPv6-only
+    /// gateway. The suffix is the link's first search domain with resolved, and the
+    /// global `search` line without it.
+    fn network_fingerprint(&self, if_index: u32) -> AppResult<NetworkFingerprint> {
+        let name = link_name(if_index)?;
+        let gateways: Vec<IpAddr> = read_gateways()
+            .remove(&name)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|gateway| gateway.parse().ok())
+            .collect();
+        let gateway_ip = gateways
+            .iter()
+            .find(|ip| ip.is_ipv4())
+            .or(gateways.first())
+            .copied();
+        let gateway_mac = gateway_ip.and_then(|ip| {
+            let arp = std::fs::read_to_string("/proc/net/arp").ok()?;
+            arp_mac(&arp, &ip, &name)
+        });
+
+        let dns_suffix = match &self.backend {
+            Backend::Resolved(resolved) => resolved
+                .link_domains(if_index)?
+                .into_iter()
+                .find(|(_, route_only)| !route_only)
+                .map(|(domain, _)| domain),
+            Backend::ResolvConf => resolv_conf::search(&resolv_conf::read()?)
+                .into_iter()
+                .next(),
+        };
+
+        Ok(NetworkFingerprint {
+            gateway_ip,
+            gateway_mac,
+            dns_suffix,
+            ssid: network_manager::ssid(&name),
+        })
+    }
+
+    /// `SIOCSIFFLAGS`, as `ip link set up|down` does.
+    fn set_adapter_enabled(&self, if_index: u32, enable: bool) -> AppResult<()> {
+        let name = link_name(if_index)?;
+        let socket_error = |operation: &str| {
+            AppError::io(
+                format!("{} failed on {}", operation, name),
+                std::io::Error::last_os_error(),
+            )
+        };
+
+        unsafe {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if fd < 0 {
+                return Err(socket_error("socket"));
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+
+            let mut request: libc::ifreq = std::mem::zeroed();
+            for (dst, src) in request
+                .ifr_name
+                .iter_mut()
+                .zip(name.bytes().take(libc::IFNAMSIZ - 1))
+            {
+                *dst = src as libc::c_char;
+            }
+            if libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFFLAGS, &mut request) < 0 {
+                return Err(socket_error("SIOCGIFFLAGS"));
+            }
+            let flags = request.ifr_ifru.ifru_flags;
+            let up = libc::IFF_UP as libc::c_short;
+            request.ifr_ifru.ifru_flags = if enable { flags | up } else { flags & !up };
+            if libc::ioctl(fd.as_raw_fd(), libc::SIOCSIFFLAGS, &request) < 0 {
+                return Err(socket_error("SIOCSIFFLAGS"));
+            }
+        }
+        Ok(())
+    }
+
+    /// glibc's stub resolver keeps no cache, so there is only something to flush when
+    /// resolved is in use.
+    fn flush_cache(&self) -> AppResult<()> {
+        match &self.backend {
+            Backend::Resolved(resolved) => resolved.flush_caches(),
+            Backend::ResolvConf => Ok(()),
+        }
+    }
+
+    /// Link, address and route changes from rtnetlink, DNS changes from writes to
+    /// `resolv.conf`; see [`watch`].
+    fn watch_changes(&self, on_change: ChangeCallback) {
+        watch::start(on_change);
+    }
+
+    /// The threads notice within a second, when their reads time out.
+    fn unwatch_changes(&self) {
+        watch::stop();
+    }
+}
+
+/// What sysfs says about one interface.
+struct Link {
+    index: u32,
+    name: String,
+    driver: Option<String>,
+    mac: Option<String>,
+    if_type: u32,
+    is_up: bool,
+    admin_up: bool,
+    /// Whether sysfs links the interface to a device. Bridges, veths, tunnels and the
+    /// like have none.
+    hardware: bool,
+    mtu: Option<u32>,
+    /// In bits per second.
+    speed: Option<u64>,
+    stats: InterfaceStats,
+}
+
+fn read_links() -> AppResult<Vec<Link>> {
+    let root = Path::new("/sys/class/net");
+    let entries = std::fs::read_dir(root)
+        .map_err(|e| AppError::io(format!("Could not list {}", root.display()), e))?;
+
+    let mut links = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let dir = entry.path();
+        let read = |file: &str| {
+            std::fs::read_to_string(dir.join(file))
+                .map(|s| s.trim().to_string())
+                .ok()
+        };
+
+        let Some(index) = read("ifindex").and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+        let flags = read("flags")
+            .and_then(|s| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok())
+            .unwrap_or(0);
+        let arp_type = read("type").and_then(|s| s.parse().ok()).unwrap_or(0);
+        let wireless = dir.join("wireless").exists() || dir.join("phy80211").exists();
+        let operstate = read("operstate").unwrap_or_default();
+        let counter = |name: &str| {
+            read(&format!("statistics/{}", name))
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0)
+        };
+
+        links.push(Link {
+            index,
+            driver: std::fs::read_link(dir.join("device/driver"))
+                .ok()
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned())),
+            mac: read("address")
+                .filter(|mac| arp_type == ARPHRD_ETHER && mac != "00:00:00:00:00:00")
+                .map(|mac| mac.to_uppercase()),
+            if_type: match arp_type {
+                ARPHRD_LOOPBACK => IF_TYPE_SOFTWARE_LOOPBACK,
+                ARPHRD_PPP => IF_TYPE_PPP,
+                ARPHRD_ETHER if wireless => IF_TYPE_IEEE80211,
+                ARPHRD_ETHER => IF_TYPE_ETHERNET,
+                ARPHRD_NONE => IF_TYPE_TUNNEL,
+                _ => IF_TYPE_OTHER,
+            },
+            // Loopback and most tunnels report "unknown" forever; running is the best
+            // there is for them.
+            is_up: operstate == "up"
+                || (operstate == "unknown" && flags & libc::IFF_RUNNING as u32 != 0),
+            admin_up: flags & libc::IFF_UP as u32 != 0,
+            hardware: dir.join("device").exists(),
+            mtu: read("mtu").and_then(|s| s.parse().ok()),
+            // Megabits per second; -1, or a read error, while the link is down or when
+            // the driver does not know.
+            speed: read("speed")
+                .and_then(|s| s.parse::<u64>().ok())
+                .filter(|speed| *speed > 0)
+                .map(|speed| speed * 1_000_000),
+            stats: InterfaceStats {
+                bytes_received: counter("rx_bytes"),
+                bytes_sent: counter("tx_bytes"),
+                packets_received: counter("rx_packets"),
+                packets_sent: counter("tx_packets"),
+            },
+            name,
+        });
+    }
+    links.sort_by_key(|l| l.index);
+    Ok(links)
+}
+
+fn link_name(if_index: u32) -> AppResult<String> {
+    read_links()?
+        .into_iter()
+        .find(|l| l.index == if_index)
+        .map(|l| l.name)
+        .ok_or(AppError::InterfaceNotFound(if_index))
+}
+
+fn link_index(name: &str) -> Option<u32> {
+    read_links()
+        .ok()?
+        .into_iter()
+        .find(|l| l.name == name)
+        .map(|l| l.index)
+}
+
+/// What DHCP handed a link.
+#[derive(Debug, PartialEq)]
+struct DhcpLease {
+    server: Option<IpAddr>,
+    dns: Vec<IpAddr>,
+}
+
+/// The link's DHCPv4 lease, from systemd-networkd or NetworkManager, whichever manages
+/// it. `None` if neither has one, which for a link that has an address means it is
+/// configured statically.
+fn dhcp_lease(link: &Link) -> Option<DhcpLease> {
+    let networkd = Path::new("/run/systemd/netif/leases").join(link.index.to_string());
+    if let Ok(text) = std::fs::read_to_string(networkd) {
+        return Some(parse_networkd_lease(&text));
+    }
+    let options = network_manager::dhcp4_options(&link.name)?;
+    let addresses = |key: &str| -> Vec<IpAddr> {
+        options
+            .get(key)
+            .map(|value| {
+                value
+                    .split_whitespace()
+                    .filter_map(|ip| ip.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    Some(DhcpLease {
+        server: addresses("dhcp_server_identifier").first().copied(),
+        dns: addresses("domain_name_servers"),
+    })
+}
+
+/// networkd's lease files are `KEY=value` lines; `DNS` is space-separated.
+fn parse_networkd_lease(text: &str) -> DhcpLease {
+    let mut lease = DhcpLease {
+        server: None,
+        dns: Vec::new(),
+    };
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        match key.trim() {
+            "SERVER_ADDRESS" => lease.server = value.trim().parse().ok(),
+            "DNS" => {
+                lease.dns = value
+                    .split_whitespace()
+                    .filter_map(|ip| ip.parse().ok())
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+    lease
+}
+
+/// Every interface's addresses, by interface name.
+fn read_addresses() -> HashMap<String, Vec<String>> {
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    unsafe {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        if libc::getifaddrs(&mut head) != 0 {
+            error!("getifaddrs failed: {}", std::io::Error::last_os_error());
+            return map;
+        }
+
+        let mut current = head;
+        while !current.is_null() {
+            let ifa = &*current;
+            current = ifa.ifa_next;
+            if ifa.ifa_addr.is_null() {
+                continue;
+            }
+            let ip = match (*ifa.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            let name = CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+            map.entry(name).or_default().push(ip.to_string());
+        }
+
+        libc::freeifaddrs(head);
+    }
+    map
+}
+
+/// Default-route gateways, by interface name.
+fn read_gateways() -> HashMap<String, Vec<String>> {
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    let v4 = std::fs::read_to_string("/proc/net/route")
+        .map(|text| default_routes_v4(&text))
+        .unwrap_or_default();
+    let v6 = std::fs::read_to_string("/proc/net/ipv6_route")
+        .map(|text| default_routes_v6(&text))
+        .unwrap_or_default();
+    for route in v4.into_iter().chain(v6) {
+        if let Some(gateway) = route.gateway {
+            map.entry(route.interface)
+                .or_default()
+                .push(gateway.to_string());
+        }
+    }
+    map
+}
+
+/// The MAC of `ip` on `interface` from `/proc/net/arp`, if the entry is complete.
+fn arp_mac(text: &str, ip: &IpAddr, interface: &str) -> Option<String> {
+    // ATF_COM: the entry has a hardware address, as opposed to a lookup in progress.
+    const ATF_COM: u32 = 0x02;
+    text.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [address, _hw_type, flags, mac, _mask, device] = fields[..] else {
+            return None;
+        };
+        let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok()?;
+        (address.parse::<IpAddr>().ok()? == *ip && device == interface && flags & ATF_COM != 0)
+            .then(|| mac.to_uppercase())
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DefaultRoute {
+    interface: String,
+    gateway: Option<IpAddr>,
+    metric: u32,
+}
+
+/// Default routes from `/proc/net/route`: destination and mask both zero. Addresses are
+/// the raw `in_addr` printed as a host-order hex number.
+fn default_routes_v4(text: &str) -> Vec<DefaultRoute> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [interface, destination, gateway, _flags, _refcnt, _use, metric, mask, ..] =
+                fields[..]
+            else {
+                return None;
+            };
+            if destination != "00000000" || mask != "00000000" {
+                return None;
+            }
+            let gateway = u32::from_str_radix(gateway, 16).ok()?;
+            Some(DefaultRoute {
+                interface: interface.to_string(),
+                gateway: (gateway != 0).then(|| IpAddr::V4(Ipv4Addr::from(gateway.to_ne_bytes()))),
+                metric: metric.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Default routes from `/proc/net/ipv6_route`: `::/0`. Addresses are 32 hex digits in
+/// network order; the metric is hex too.
+fn default_routes_v6(text: &str) -> Vec<DefaultRoute> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [destination, prefix, _src, _src_prefix, next_hop, metric, _refcnt, _use, _flags, interface] =
+                fields[..]
+            else {
+                return None;
+            };
+            if prefix != "00" || destination.chars().any(|c| c != '0') || interface == "lo" {
+                return None;
+            }
+            let next_hop = u128::from_str_radix(next_hop, 16).ok()?;
+            Some(DefaultRoute {
+                interface: interface.to_string(),
+                gateway: (next_hop != 0).then(|| IpAddr::V6(Ipv6Addr::from(next_hop))),
+                metric: u32::from_str_radix(metric, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+fn snapshot_path() -> PathBuf {
+    state_dir().join(SNAPSHOT_FILE)
+}
+
+/// A missing or unreadable file is an empty map, as on Windows.
+fn load_all(path: &Path) -> BTreeMap<String, Snapshot> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring unreadable DNS snapshots in {}: {}",
+                path.display(),
+                e
+            );
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+fn save_all<T: Serialize>(path: &Path, entries: &BTreeMap<String, T>) -> AppResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| AppError::io(format!("Could not create {}", dir.display()), e))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)
+        .map_err(|e| AppError::io(format!("Could not write {}", tmp.display()), e))?;
+    std::fs::rename(&tmp, path)
+        .map_err(|e| AppError::io(format!("Could not replace {}", path.display()), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_networkd_lease() {
+        let lease = "# This is private data. Do not parse.\nADDRESS=192.168.1.23\nSERVER_ADDRESS=192.168.1.1\nDNS=192.168.1.1 fd00::1\nDOMAINNAME=lan\n";
+        assert_eq!(
+            parse_networkd_lease(lease),
+            DhcpLease {
+                server: Some("192.168.1.1".parse().unwrap()),
+                dns: vec!["192.168.1.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            }
+        );
+    }
+
+    #[test]
+    fn finds_the_gateway_in_the_arp_table() {
+        let arp =
+            "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   192.168.1.1      0x1         0x2         a4:2b:b0:12:34:56     *        wlan0\n\
+                   192.168.1.7      0x1         0x0         00:00:00:00:00:00     *        wlan0\n\
+                   10.0.0.1         0x1         0x2         00:11:22:33:44:55     *        eth0\n";
+        let gateway: IpAddr = "192.168.1.1".parse().unwrap();
+
+        assert_eq!(
+            arp_mac(arp, &gateway, "wlan0").as_deref(),
+            Some("A4:2B:B0:12:34:56")
+        );
+        assert_eq!(arp_mac(arp, &gateway, "eth0"), None);
+        assert_eq!(arp_mac(arp, &"192.168.1.7".parse().unwrap(), "wlan0"), None);
+    }
+
+    #[test]
+    fn finds_default_routes_in_procfs() {
+        let v4 =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                  wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+                  wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0\n";
+        assert_eq!(
+            default_routes_v4(v4),
+            vec![DefaultRoute {
+                interface: "wlan0".to_string(),
+                gateway: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+                metric: 600,
+            }]
+        );
+
+        let v6 = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wlan0\n\
+                  fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 wlan0\n";
+        assert_eq!(
+            default_routes_v6(v6),
+            vec![DefaultRoute {
+                interface: "wlan0".to_string(),
+                gateway: Some("fe80::1".parse().unwrap()),
+                metric: 0x400,
+            }]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! NetworkManager, over D-Bus, for what nothing else on Linux reports: which SSID a
+//! wireless link is connected to, and the DHCP lease of a link it manages. Without
+//! NetworkManager — iwd on its own, wpa_supplicant configured by hand — there is no
+//! SSID, and network profiles match on the rest of the fingerprint.
+
+use std::collections::HashMap;
+
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+const DESTINATION: &str = "org.freedesktop.NetworkManager";
+const PATH: &str = "/org/freedesktop/NetworkManager";
+const DEVICE: &str = "org.freedesktop.NetworkManager.Device";
+const DHCP4_CONFIG: &str = "org.freedesktop.NetworkManager.DHCP4Config";
+const WIRELESS: &str = "org.freedesktop.NetworkManager.Device.Wireless";
+const ACCESS_POINT: &str = "org.freedesktop.NetworkManager.AccessPoint";
+
+/// The SSID `link` is associated with. `None` for a wired link, a disconnected one, or
+/// when NetworkManager is not running or does not manage the link.
+pub fn ssid(link: &str) -> Option<String> {
+    let conn = Connection::system().ok()?;
+    let device = device(&conn, link)?;
+    let access_point: OwnedObjectPath =
+        property(&conn, device.as_str(), WIRELESS, "ActiveAccessPoint")?;
+    // "/" is how NetworkManager says "not associated".
+    if access_point.as_str() == "/" {
+        return None;
+    }
+    let ssid: Vec<u8> = property(&conn, access_point.as_str(), ACCESS_POINT, "Ssid")?;
+    (!ssid.is_empty()).then(|| String::from_utf8_lossy(&ssid).into_owned())
+}
+
+/// The options of the link's DHCPv4 lease, as the DHCP client reported them:
+/// `dhcp_server_identifier`, `domain_name_servers` and so on. `None` if the link is not
+/// configured by DHCP or NetworkManager does not manage it.
+pub fn dhcp4_options(link: &str) -> Option<HashMap<String, String>> {
+    let conn = Connection::system().ok()?;
+    let device = device(&conn, link)?;
+    let config: OwnedObjectPath = property(&conn, device.as_str(), DEVICE, "Dhcp4Config")?;
+    if config.as_str() == "/" {
+        return None;
+    }
+    let options: HashMap<String, OwnedValue> =
+        property(&conn, config.as_str(), DHCP4_CONFIG, "Options")?;
+    Some(
+        options
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, String::try_from(value).ok()?)))
+            .collect(),
+    )
+}
+
+fn device(conn: &Connection, link: &str) -> Option<OwnedObjectPath> {
+    conn.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(DESTINATION),
+        "GetDeviceByIpIface",
+        &(link,),
+    )
+    .ok()?
+    .body()
+    .deserialize()
+    .ok()
+}
+
+fn property<T>(conn: &Connection, path: &str, interface: &str, name: &str) -> Option<T>
+where
+    T: TryFrom<OwnedValue>,
+{
+    let value: OwnedValue = conn
+        .call_method(
+            Some(DESTINATION),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(interface, name),
+        )
+        .ok()?
+        .body()
+        .deserialize()
+        .ok()?;
+    T::try_from(value).ok()
+}
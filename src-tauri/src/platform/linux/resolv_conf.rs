@@ -0,0 +1,149 @@
+//! Plain `/etc/resolv.conf`, for systems without systemd-resolved.
+//!
+//! The file is global: every interface resolves through the same `nameserver` lines, so
+//! setting DNS on one interface sets it for all of them. Only the `nameserver` lines of
+//! the family being changed are touched; `search`, `options` and comments stay as they
+//! are. The file is rewritten in place rather than replaced, because it is often a
+//! symlink that some other tool owns.
+
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::error::{AppError, AppResult};
+use crate::platform::{is_proxy_addr, Family};
+
+pub const PATH: &str = "/etc/resolv.conf";
+
+pub fn read() -> AppResult<String> {
+    std::fs::read_to_string(PATH).map_err(|e| AppError::io(format!("Could not read {}", PATH), e))
+}
+
+pub fn write(text: &str) -> AppResult<()> {
+    std::fs::write(Path::new(PATH), text)
+        .map_err(|e| AppError::io(format!("Could not write {}", PATH), e))
+}
+
+/// The `nameserver` entries, in order.
+pub fn nameservers(text: &str) -> Vec<IpAddr> {
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(addr)) => addr.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The domains on the `search` line. resolv(5) says the last `search` line wins.
+pub fn search(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some("search")).then(|| words.map(str::to_string).collect())
+        })
+        .next_back()
+        .unwrap_or_default()
+}
+
+/// `text` with its `search` line replaced by `domains`, or dropped if there are none.
+pub fn with_search(text: &str, domains: &[String]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut insert_at = None;
+    for line in text.lines() {
+        if line.split_whitespace().next() == Some("search") {
+            insert_at.get_or_insert(lines.len());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    if !domains.is_empty() {
+        let at = insert_at.unwrap_or(lines.len());
+        lines.insert(at, format!("search {}", domains.join(" ")));
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// `text` with the family's `nameserver` lines replaced by `servers`. The new lines go
+/// where the first removed one was, or at the end if the family had none.
+pub fn with_nameservers(text: &str, family: Family, servers: &[IpAddr]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut insert_at = None;
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let is_family = match (words.next(), words.next()) {
+            (Some("nameserver"), Some(addr)) => addr
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| Family::of(&ip) == family),
+            _ => false,
+        };
+        if is_family {
+            insert_at.get_or_insert(lines.len());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    let at = insert_at.unwrap_or(lines.len());
+    let new = servers.iter().map(|ip| format!("nameserver {}", ip));
+    lines.splice(at..at, new);
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// `text` without any `nameserver` line pointing at a proxy.
+pub fn without_proxies(text: &str) -> String {
+    [Family::V4, Family::V6]
+        .into_iter()
+        .fold(text.to_string(), |text, family| {
+            let kept: Vec<IpAddr> = nameservers(&text)
+                .into_iter()
+                .filter(|ip| Family::of(ip) == family && !is_proxy_addr(ip))
+                .collect();
+            with_nameservers(&text, family, &kept)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_only_the_family_being_set() {
+        let text = "# generated\nsearch lan\nnameserver 192.168.1.1\nnameserver fd00::1\nnameserver 1.1.1.1\noptions edns0\n";
+        let updated = with_nameservers(text, Family::V4, &["127.0.0.2".parse().unwrap()]);
+
+        assert_eq!(
+            updated,
+            "# generated\nsearch lan\nnameserver 127.0.0.2\nnameserver fd00::1\noptions edns0\n"
+        );
+        assert_eq!(
+            nameservers(&updated),
+            vec![
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+                "fd00::1".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_the_search_line_in_place() {
+        let text = "search lan\nnameserver 192.168.1.1\n";
+        let domains = ["corp.example.com".to_string(), "example.com".to_string()];
+
+        let updated = with_search(text, &domains);
+        assert_eq!(
+            updated,
+            "search corp.example.com example.com\nnameserver 192.168.1.1\n"
+        );
+        assert_eq!(search(&updated), domains);
+        assert_eq!(with_search(&updated, &[]), "nameserver 192.168.1.1\n");
+    }
+}
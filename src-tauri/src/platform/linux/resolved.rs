@@ -0,0 +1,189 @@
+//! systemd-resolved, over its `org.freedesktop.resolve1.Manager` D-Bus interface.
+//!
+//! resolved keeps DNS per link, which maps onto the app's per-interface model directly:
+//! `SetLinkDNS` replaces a link's servers and `RevertLink` drops everything set at
+//! runtime. Setting servers alone is not enough, though — resolved sends a query to
+//! every link that can route it, so a second link with DNS would still see queries the
+//! proxy should have answered. Applying a proxy therefore also gives the link the `~.`
+//! routing domain, which makes it the only default route for DNS.
+//!
+//! The per-link LLMNR mode is a property of the link's own object rather than the
+//! manager's, so reading it takes a `GetLink` first.
+//!
+//! Needs `CAP_NET_ADMIN` or a polkit rule allowing the calls; failures come back as
+//! D-Bus access-denied errors.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+use crate::error::{AppError, AppResult};
+
+const DESTINATION: &str = "org.freedesktop.resolve1";
+const PATH: &str = "/org/freedesktop/resolve1";
+const MANAGER: &str = "org.freedesktop.resolve1.Manager";
+const LINK: &str = "org.freedesktop.resolve1.Link";
+
+// Address families as resolved spells them (`AF_INET`, `AF_INET6`).
+const AF_INET: i32 = 2;
+const AF_INET6: i32 = 10;
+
+/// The routing domain that sends every query to a link.
+pub const ROUTE_ALL: &str = "~.";
+
+pub struct Resolved {
+    conn: Connection,
+}
+
+impl Resolved {
+    /// Connects to the system bus. `None` if resolved is not running.
+    pub fn connect() -> Option<Self> {
+        let conn = Connection::system().ok()?;
+        let running: bool = conn
+            .call_method(
+                Some("org.freedesktop.DBus"),
+                "/org/freedesktop/DBus",
+                Some("org.freedesktop.DBus"),
+                "NameHasOwner",
+                &(DESTINATION,),
+            )
+            .ok()?
+            .body()
+            .deserialize()
+            .ok()?;
+        running.then_some(Self { conn })
+    }
+
+    /// Every link's servers as `(link, server)`, in order. Link 0 holds the global
+    /// servers from `resolved.conf`.
+    pub fn dns(&self) -> AppResult<Vec<(u32, IpAddr)>> {
+        let entries: Vec<(i32, i32, Vec<u8>)> = self.property("DNS")?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|(link, family, bytes)| Some((link as u32, to_ip(family, &bytes)?)))
+            .collect())
+    }
+
+    /// The servers set on the link, in order.
+    pub fn link_dns(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        Ok(self
+            .dns()?
+            .into_iter()
+            .filter(|(link, _)| *link == if_index)
+            .map(|(_, ip)| ip)
+            .collect())
+    }
+
+    /// The link's search and routing domains, as `(domain, route_only)`.
+    pub fn link_domains(&self, if_index: u32) -> AppResult<Vec<(String, bool)>> {
+        let entries: Vec<(i32, String, bool)> = self.property("Domains")?;
+        Ok(entries
+            .into_iter()
+            .filter(|(link, _, _)| *link == if_index as i32)
+            .map(|(_, domain, route_only)| (domain, route_only))
+            .collect())
+    }
+
+    pub fn set_link_dns(&self, if_index: u32, servers: &[IpAddr]) -> AppResult<()> {
+        let addresses: Vec<(i32, Vec<u8>)> = servers.iter().map(from_ip).collect();
+        self.call("SetLinkDNS", &(if_index as i32, addresses))
+    }
+
+    pub fn set_link_domains(&self, if_index: u32, domains: &[(String, bool)]) -> AppResult<()> {
+        self.call("SetLinkDomains", &(if_index as i32, domains.to_vec()))
+    }
+
+    /// The link's LLMNR mode: `yes`, `no` or `resolve` (query, but do not answer).
+    pub fn link_llmnr(&self, if_index: u32) -> AppResult<String> {
+        let path: OwnedObjectPath = self
+            .conn
+            .call_method(
+                Some(DESTINATION),
+                PATH,
+                Some(MANAGER),
+                "GetLink",
+                &(if_index as i32,),
+            )
+            .and_then(|reply| reply.body().deserialize())
+            .map_err(|e| AppError::DBus(format!("GetLink failed: {}", e)))?;
+        self.property_of(path.as_str(), LINK, "LLMNR")
+    }
+
+    /// An empty `mode` drops the runtime setting.
+    pub fn set_link_llmnr(&self, if_index: u32, mode: &str) -> AppResult<()> {
+        self.call("SetLinkLLMNR", &(if_index as i32, mode))
+    }
+
+    /// Drops everything set on the link at runtime; it goes back to what networkd or
+    /// NetworkManager last handed resolved.
+    pub fn revert_link(&self, if_index: u32) -> AppResult<()> {
+        self.call("RevertLink", &(if_index as i32,))
+    }
+
+    pub fn flush_caches(&self) -> AppResult<()> {
+        self.call("FlushCaches", &())
+    }
+
+    fn call<B>(&self, method: &'static str, body: &B) -> AppResult<()>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.conn
+            .call_method(Some(DESTINATION), PATH, Some(MANAGER), method, body)
+            .map(|_| ())
+            .map_err(|e| AppError::DBus(format!("{} failed: {}", method, e)))
+    }
+
+    /// Read with `Properties.Get` every time: resolved's own state is the only copy
+    /// worth trusting, and a cached proxy would go stale between calls.
+    fn property<T>(&self, name: &'static str) -> AppResult<T>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: std::fmt::Display,
+    {
+        self.property_of(PATH, MANAGER, name)
+    }
+
+    fn property_of<T>(&self, path: &str, interface: &str, name: &'static str) -> AppResult<T>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: std::fmt::Display,
+    {
+        let reply = self
+            .conn
+            .call_method(
+                Some(DESTINATION),
+                path,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(interface, name),
+            )
+            .map_err(|e| AppError::DBus(format!("reading {} failed: {}", name, e)))?;
+        let value: OwnedValue = reply
+            .body()
+            .deserialize()
+            .map_err(|e| AppError::DBus(format!("reading {} failed: {}", name, e)))?;
+        T::try_from(value)
+            .map_err(|e| AppError::DBus(format!("{} has an unexpected type: {}", name, e)))
+    }
+}
+
+fn to_ip(family: i32, bytes: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET => <[u8; 4]>::try_from(bytes)
+            .ok()
+            .map(|b| IpAddr::V4(Ipv4Addr::from(b))),
+        AF_INET6 => <[u8; 16]>::try_from(bytes)
+            .ok()
+            .map(|b| IpAddr::V6(Ipv6Addr::from(b))),
+        _ => None,
+    }
+}
+
+fn from_ip(ip: &IpAddr) -> (i32, Vec<u8>) {
+    match ip {
+        IpAddr::V4(v4) => (AF_INET, v4.octets().to_vec()),
+        IpAddr::V6(v6) => (AF_INET6, v6.octets().to_vec()),
+    }
+}
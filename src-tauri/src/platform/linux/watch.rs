@@ -0,0 +1,458 @@
+//! Change notifications: an rtnetlink socket for links, addresses and routes, and
+//! inotify on the directories `resolv.conf` lives in for DNS.
+//!
+//! Each netlink message names the interface and family it is about, so those are
+//! reported as they come. DNS has nothing comparable — resolved rewrites its copies of
+//! `resolv.conf` whenever a link's servers change, and anything else managing DNS
+//! without it writes `/etc/resolv.conf` — so a write to either only says that
+//! something may have changed, and [`DnsDiff`] works out what.
+
+use std::ffi::CStr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{debug, info, warn};
+
+use crate::platform::{
+    self, ChangeCallback, ChangeKind, ChangeSource, DnsDiff, Family, NetworkChange,
+};
+
+/// Set while the watch threads run; cleared to stop them.
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Where `resolv.conf` is written: by hand or a DHCP client, and by resolved.
+const RESOLV_CONF_DIRS: [&CStr; 2] = [c"/etc", c"/run/systemd/resolve"];
+
+// `ifi_change` on an `RTM_NEWLINK` for a link that did not exist before.
+const IFI_CHANGE_NEW_LINK: u32 = u32::MAX;
+
+/// Receive buffer asked for on the netlink socket. The default, around 200 KiB, fills
+/// up when a VPN or container runtime adds hundreds of routes at once.
+const NETLINK_RECEIVE_BUFFER: libc::c_int = 4 << 20;
+
+/// Reported when the kernel had to drop notifications: something changed, somewhere.
+const OVERFLOW: NetworkChange = NetworkChange {
+    interface_index: 0,
+    family: None,
+    kind: ChangeKind::Parameter,
+    source: ChangeSource::Interface,
+};
+
+/// Starts both threads. Idempotent.
+pub fn start(on_change: ChangeCallback) {
+    if WATCHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let fd = match open_netlink() {
+        Ok(fd) => fd,
+        Err(e) => {
+            WATCHING.store(false, Ordering::SeqCst);
+            warn!(
+                "Could not subscribe to network change notifications ({}); the UI will fall back to polling",
+                e
+            );
+            return;
+        }
+    };
+
+    let on_netlink_change = on_change.clone();
+    let spawned = std::thread::Builder::new()
+        .name("network-watch".to_string())
+        .spawn(move || {
+            let mut buffer = [0u8; 8192];
+            while WATCHING.load(Ordering::SeqCst) {
+                let read = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                    )
+                };
+                if read > 0 {
+                    platform::guard_callback("network", || {
+                        for change in parse_netlink(&buffer[..read as usize]) {
+                            on_netlink_change(change);
+                        }
+                    });
+                    continue;
+                }
+                let e = std::io::Error::last_os_error();
+                if e.raw_os_error() == Some(libc::ENOBUFS) {
+                    // The socket overflowed and the kernel dropped messages. It stays
+                    // usable; what was lost is only which interfaces they were about.
+                    debug!("Network change notifications overflowed; reporting a general change");
+                    platform::guard_callback("network", || on_netlink_change(OVERFLOW));
+                    continue;
+                }
+                if !matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::Interrupted
+                ) {
+                    warn!("Network change notifications stopped: {}", e);
+                    WATCHING.store(false, Ordering::SeqCst);
+                }
+            }
+            debug!("Unsubscribed from network change notifications");
+        });
+    match spawned {
+        Ok(_) => info!("Subscribed to network change notifications"),
+        Err(e) => {
+            WATCHING.store(false, Ordering::SeqCst);
+            warn!("Could not start the network change thread: {}", e);
+            return;
+        }
+    }
+
+    // DNS changes are extra: without them the interface notifications still work.
+    if let Err(e) = std::thread::Builder::new()
+        .name("dns-watch".to_string())
+        .spawn(move || watch_resolv_conf(on_change))
+    {
+        warn!("Could not start the DNS change thread: {}", e);
+    }
+}
+
+/// The threads notice within a second, when their reads time out.
+pub fn stop() {
+    WATCHING.store(false, Ordering::SeqCst);
+}
+
+/// An rtnetlink socket subscribed to link, address and route changes, with a larger
+/// receive buffer than usual and a one-second read timeout so the reader can notice it
+/// has been asked to stop.
+fn open_netlink() -> std::io::Result<OwnedFd> {
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_LINK
+            | libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+        if libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // Best effort: FORCE goes past `net.core.rmem_max` but needs CAP_NET_ADMIN, and
+        // an overflow is survivable anyway.
+        for option in [libc::SO_RCVBUFFORCE, libc::SO_RCVBUF] {
+            if libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                &NETLINK_RECEIVE_BUFFER as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            ) == 0
+            {
+                break;
+            }
+        }
+
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        if libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+}
+
+/// One change per link, address and route message in what a single `recv` returned.
+///
+/// The kernel sends `RTM_NEWADDR` and `RTM_NEWROUTE` for updates as well as additions
+/// and does not say which, so both are reported as added. `RTM_NEWLINK` does say, by
+/// marking every flag as changed on a link it has just registered.
+fn parse_netlink(buffer: &[u8]) -> Vec<NetworkChange> {
+    const HEADER_LEN: usize = 16;
+
+    let mut changes = Vec::new();
+    let mut offset = 0;
+    while offset + HEADER_LEN <= buffer.len() {
+        let len = read_u32(buffer, offset) as usize;
+        if len < HEADER_LEN || offset + len > buffer.len() {
+            break;
+        }
+        let message_type = read_u16(buffer, offset + 4);
+        let body = &buffer[offset + HEADER_LEN..offset + len];
+        if let Some(change) = parse_message(message_type, body) {
+            changes.push(change);
+        }
+        offset += align(len);
+    }
+    changes
+}
+
+fn parse_message(message_type: u16, body: &[u8]) -> Option<NetworkChange> {
+    let (kind, source) = match message_type {
+        libc::RTM_NEWLINK => (ChangeKind::Parameter, ChangeSource::Interface),
+        libc::RTM_DELLINK => (ChangeKind::Deleted, ChangeSource::Interface),
+        libc::RTM_NEWADDR => (ChangeKind::Added, ChangeSource::Address),
+        libc::RTM_DELADDR => (ChangeKind::Deleted, ChangeSource::Address),
+        libc::RTM_NEWROUTE => (ChangeKind::Added, ChangeSource::Route),
+        libc::RTM_DELROUTE => (ChangeKind::Deleted, ChangeSource::Route),
+        _ => return None,
+    };
+    let family = match *body.first()? as i32 {
+        libc::AF_INET => Some(Family::V4),
+        libc::AF_INET6 => Some(Family::V6),
+        _ => None,
+    };
+
+    let (interface_index, kind) = match source {
+        // struct ifinfomsg: family, pad, type, index, flags, change.
+        ChangeSource::Interface => {
+            if body.len() < 16 {
+                return None;
+            }
+            let kind = if kind == ChangeKind::Parameter && read_u32(body, 12) == IFI_CHANGE_NEW_LINK
+            {
+                ChangeKind::Added
+            } else {
+                kind
+            };
+            (read_u32(body, 4), kind)
+        }
+        // struct ifaddrmsg: family, prefix length, flags, scope, index.
+        ChangeSource::Address => {
+            if body.len() < 8 {
+                return None;
+            }
+            (read_u32(body, 4), kind)
+        }
+        // struct rtmsg is 12 bytes, followed by attributes; the interface is RTA_OIF.
+        // A multipath route has none and is reported against no interface.
+        _ => (route_interface(body.get(12..)?), kind),
+    };
+
+    Some(NetworkChange {
+        interface_index,
+        // A link has no family of its own; its messages carry AF_UNSPEC.
+        family: if source == ChangeSource::Interface {
+            None
+        } else {
+            family
+        },
+        kind,
+        source,
+    })
+}
+
+fn route_interface(mut attributes: &[u8]) -> u32 {
+    while attributes.len() >= 4 {
+        let len = read_u16(attributes, 0) as usize;
+        let attribute_type = read_u16(attributes, 2);
+        if len < 4 || len > attributes.len() {
+            break;
+        }
+        if attribute_type == libc::RTA_OIF && len >= 8 {
+            return read_u32(attributes, 4);
+        }
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+    0
+}
+
+/// Netlink messages and attributes are padded to four bytes.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reports a [`ChangeSource::Dns`] change for every interface whose servers differ
+/// after a write to a `resolv.conf`.
+fn watch_resolv_conf(on_change: ChangeCallback) {
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+    if fd < 0 {
+        warn!(
+            "Could not watch for DNS changes: {}",
+            std::io::Error::last_os_error()
+        );
+        return;
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut watched = 0;
+    for dir in RESOLV_CONF_DIRS {
+        // Replaced by a rename by most writers, rewritten in place by some.
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) } >= 0 {
+            watched += 1;
+        }
+    }
+    if watched == 0 {
+        warn!("Could not watch for DNS changes: no resolv.conf directory to watch");
+        return;
+    }
+
+    let net = platform::system();
+    let mut diff = DnsDiff::new(&net.list_interfaces().unwrap_or_default());
+    let mut buffer = [0u8; 4096];
+    while WATCHING.load(Ordering::SeqCst) {
+        let mut poll = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, 1000) } <= 0 {
+            continue;
+        }
+        let read = unsafe {
+            libc::read(
+                fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read <= 0 || !touches_resolv_conf(&buffer[..read as usize]) {
+            continue;
+        }
+        platform::guard_callback("DNS", || match net.list_interfaces() {
+            Ok(interfaces) => {
+                for change in diff.changes(&interfaces) {
+                    on_change(change);
+                }
+            }
+            Err(e) => debug!("Could not re-read DNS after a resolv.conf change: {}", e),
+        });
+    }
+    debug!("Stopped watching for DNS changes");
+}
+
+/// Whether any of the inotify events is about a file named like `resolv.conf`:
+/// resolved writes `resolv.conf` and `stub-resolv.conf`, and `/etc` sees every other
+/// file on the system too.
+fn touches_resolv_conf(buffer: &[u8]) -> bool {
+    // struct inotify_event: wd, mask, cookie, len, then `len` bytes of padded name.
+    const HEADER_LEN: usize = 16;
+
+    let mut offset = 0;
+    while offset + HEADER_LEN <= buffer.len() {
+        let name_len = read_u32(buffer, offset + 12) as usize;
+        let end = (offset + HEADER_LEN + name_len).min(buffer.len());
+        let name = &buffer[offset + HEADER_LEN..end];
+        let name = name.split(|&b| b == 0).next().unwrap_or_default();
+        if name.ends_with(b"resolv.conf") {
+            return true;
+        }
+        offset = end;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(message_type: u16, body: &[u8]) -> Vec<u8> {
+        let len = 16 + body.len();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(len as u32).to_ne_bytes());
+        bytes.extend_from_slice(&message_type.to_ne_bytes());
+        bytes.extend_from_slice(&[0; 10]);
+        bytes.extend_from_slice(body);
+        bytes.resize(align(len), 0);
+        bytes
+    }
+
+    fn ifinfomsg(index: u32, change: u32) -> Vec<u8> {
+        let mut body = vec![libc::AF_UNSPEC as u8, 0, 1, 0];
+        body.extend_from_slice(&index.to_ne_bytes());
+        body.extend_from_slice(&0u32.to_ne_bytes());
+        body.extend_from_slice(&change.to_ne_bytes());
+        body
+    }
+
+    #[test]
+    fn netlink_messages_name_the_interface_family_and_kind() {
+        let mut address = vec![libc::AF_INET6 as u8, 64, 0, 0];
+        address.extend_from_slice(&3u32.to_ne_bytes());
+
+        // rtmsg, then a destination attribute, then RTA_OIF.
+        let mut route = vec![libc::AF_INET as u8, 0, 0, 0, 254, 3, 0, 1, 0, 0, 0, 0];
+        route.extend_from_slice(&8u16.to_ne_bytes());
+        route.extend_from_slice(&libc::RTA_DST.to_ne_bytes());
+        route.extend_from_slice(&[10, 0, 0, 0]);
+        route.extend_from_slice(&8u16.to_ne_bytes());
+        route.extend_from_slice(&libc::RTA_OIF.to_ne_bytes());
+        route.extend_from_slice(&5u32.to_ne_bytes());
+
+        let mut buffer = message(libc::RTM_NEWLINK, &ifinfomsg(2, IFI_CHANGE_NEW_LINK));
+        buffer.extend(message(
+            libc::RTM_NEWLINK,
+            &ifinfomsg(2, libc::IFF_UP as u32),
+        ));
+        buffer.extend(message(libc::RTM_DELADDR, &address));
+        buffer.extend(message(libc::RTM_NEWROUTE, &route));
+        // Not a change: skipped.
+        buffer.extend(message(libc::NLMSG_DONE as u16, &[0; 4]));
+
+        let changes: Vec<_> = parse_netlink(&buffer)
+            .into_iter()
+            .map(|c| (c.interface_index, c.family, c.kind, c.source))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (2, None, ChangeKind::Added, ChangeSource::Interface),
+                (2, None, ChangeKind::Parameter, ChangeSource::Interface),
+                (
+                    3,
+                    Some(Family::V6),
+                    ChangeKind::Deleted,
+                    ChangeSource::Address
+                ),
+                (5, Some(Family::V4), ChangeKind::Added, ChangeSource::Route),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_resolv_conf_writes_count() {
+        let event = |name: &[u8]| {
+            let padded = align(name.len() + 1);
+            let mut bytes = vec![0u8; 12];
+            bytes.extend_from_slice(&(padded as u32).to_ne_bytes());
+            bytes.extend_from_slice(name);
+            bytes.resize(16 + padded, 0);
+            bytes
+        };
+        let mut buffer = event(b"passwd");
+        assert!(!touches_resolv_conf(&buffer));
+        buffer.extend(event(b"stub-resolv.conf"));
+        assert!(touches_resolv_conf(&buffer));
+        assert!(!touches_resolv_conf(&event(b"resolv.conf.bak")));
+    }
+}
@@ -0,0 +1,494 @@
+//! An in-memory network, for testing the DNS flows without touching a real adapter.
+//!
+//! [`MockNetwork`] keeps a table of interfaces, each with the servers DHCP hands it and
+//! whatever static servers have been written over them, and implements
+//! [`DnsConfigurator`], [`AdapterDns`] and [`SnapshotStore`] on top of it, so a restore
+//! runs the same `platform::restore` code as on Windows. It can be told to misbehave the
+//! ways real systems have: ignore a clear while reporting success, report the old
+//! servers for a while after a write, or refuse IPv6 name servers altogether. Every
+//! write is logged, so tests can assert which mechanism finally took.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use super::restore::{self, AdapterDns, Snapshot, SnapshotStore};
+use super::{
+    AdapterKind, ChangeCallback, DnsConfigurator, DnsOptions, Family, InterfaceStats,
+    NetworkFingerprint, NetworkInterface,
+};
+use crate::error::{AppError, AppResult};
+
+/// Windows' default IPv6 anycast servers, as an adapter without IPv6 DNS reports them.
+const ANYCAST_DEFAULTS: [&str; 3] = ["fec0:0:0:ffff::1", "fec0:0:0:ffff::2", "fec0:0:0:ffff::3"];
+
+#[derive(Debug, Clone, Default)]
+struct Interface {
+    dhcp: Vec<IpAddr>,
+    static_v4: Vec<IpAddr>,
+    static_v6: Vec<IpAddr>,
+    anycast_defaults: bool,
+    enabled: bool,
+    network: NetworkFingerprint,
+    options: DnsOptions,
+    kind: AdapterKind,
+}
+
+impl Interface {
+    /// What the adapter reports: the static servers of a family if it has any, the
+    /// DHCP ones otherwise.
+    fn servers(&self) -> Vec<IpAddr> {
+        let dhcp = |family| {
+            self.dhcp
+                .iter()
+                .filter(move |ip| Family::of(ip) == family)
+                .copied()
+        };
+        let mut servers: Vec<IpAddr> = if self.static_v4.is_empty() {
+            dhcp(Family::V4).collect()
+        } else {
+            self.static_v4.clone()
+        };
+        if !self.static_v6.is_empty() {
+            servers.extend(&self.static_v6);
+        } else if dhcp(Family::V6).next().is_some() {
+            servers.extend(dhcp(Family::V6));
+        } else if self.anycast_defaults {
+            servers.extend(
+                ANYCAST_DEFAULTS
+                    .iter()
+                    .map(|s| s.parse::<IpAddr>().unwrap()),
+            );
+        }
+        servers
+    }
+
+    fn static_servers(&mut self, family: Family) -> &mut Vec<IpAddr> {
+        match family {
+            Family::V4 => &mut self.static_v4,
+            Family::V6 => &mut self.static_v6,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    interfaces: BTreeMap<u32, Interface>,
+    /// Per interface: how many more reads return `.1` instead of the real servers.
+    stale: HashMap<u32, (usize, Vec<IpAddr>)>,
+    /// What each interface had at the first change.
+    snapshots: HashMap<u32, Snapshot>,
+    calls: Vec<String>,
+    on_change: Option<ChangeCallback>,
+}
+
+/// The fake interface table. Build it with the chained setters, then [`leak`](Self::leak)
+/// it where a `&'static dyn DnsConfigurator` is needed.
+#[derive(Default)]
+pub struct MockNetwork {
+    state: Mutex<State>,
+    best: AtomicU32,
+    no_ipv6: bool,
+    ignore_null_clear: bool,
+    ignore_empty_string_clear: bool,
+    ignore_legacy_clear: bool,
+    stale_reads: usize,
+}
+
+impl MockNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an interface whose DHCP server hands out `dhcp`. The first one added is the
+    /// best interface unless [`best`](Self::best) says otherwise.
+    pub fn interface(mut self, if_index: u32, dhcp: &[&str]) -> Self {
+        if *self.best.get_mut() == 0 {
+            *self.best.get_mut() = if_index;
+        }
+        self.state.lock().unwrap().interfaces.insert(
+            if_index,
+            Interface {
+                dhcp: dhcp.iter().map(|s| s.parse().unwrap()).collect(),
+                enabled: true,
+                ..Interface::default()
+            },
+        );
+        self
+    }
+
+    /// Gives the interface static servers of their family, as if the user had set them.
+    pub fn with_static(self, if_index: u32, servers: &[&str]) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            let interface = state.interfaces.get_mut(&if_index).unwrap();
+            for ip in servers.iter().map(|s| s.parse::<IpAddr>().unwrap()) {
+                interface.static_servers(Family::of(&ip)).push(ip);
+            }
+        }
+        self
+    }
+
+    /// Makes the interface report the default IPv6 anycast servers when it has no IPv6
+    /// DNS of its own.
+    pub fn with_anycast_defaults(self, if_index: u32) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get_mut(&if_index)
+            .unwrap()
+            .anycast_defaults = true;
+        self
+    }
+
+    /// Makes the interface a VPN, a Hyper-V switch or whatever else; adapters are
+    /// physical otherwise.
+    pub fn kind(self, if_index: u32, kind: AdapterKind) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get_mut(&if_index)
+            .unwrap()
+            .kind = kind;
+        self
+    }
+
+    pub fn best(self, if_index: u32) -> Self {
+        self.move_default_route(if_index);
+        self
+    }
+
+    /// Moves the default route, as unplugging one adapter and joining another would.
+    pub fn move_default_route(&self, if_index: u32) {
+        self.best.store(if_index, Ordering::Relaxed);
+    }
+
+    /// Connects the interface to a network that looks like `network`, as joining
+    /// another Wi-Fi would.
+    pub fn join_network(&self, if_index: u32, network: NetworkFingerprint) {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get_mut(&if_index)
+            .expect("no such mock interface")
+            .network = network;
+    }
+
+    /// An OS without `SetInterfaceDnsSettings`: IPv6 writes fail.
+    pub fn without_ipv6_support(self) -> Self {
+        Self {
+            no_ipv6: true,
+            ..self
+        }
+    }
+
+    /// The null clear reports success and changes nothing.
+    pub fn ignore_null_clear(self) -> Self {
+        Self {
+            ignore_null_clear: true,
+            ..self
+        }
+    }
+
+    /// The empty-string clear reports success and changes nothing.
+    pub fn ignore_empty_string_clear(self) -> Self {
+        Self {
+            ignore_empty_string_clear: true,
+            ..self
+        }
+    }
+
+    /// The legacy clear reports success and changes nothing.
+    pub fn ignore_legacy_clear(self) -> Self {
+        Self {
+            ignore_legacy_clear: true,
+            ..self
+        }
+    }
+
+    /// After each write, the next `reads` reads still return the servers from before it.
+    pub fn stale_reads(self, reads: usize) -> Self {
+        Self {
+            stale_reads: reads,
+            ..self
+        }
+    }
+
+    pub fn leak(self) -> &'static Self {
+        Box::leak(Box::new(self))
+    }
+
+    /// Every write so far, e.g. `"set V4 [127.0.0.2]"`, `"null V6"`, `"empty V4"`,
+    /// `"legacy"`, `"options DnsOptions { .. }"`.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// What the interface really has configured, ignoring stale reads.
+    pub fn servers(&self, if_index: u32) -> Vec<IpAddr> {
+        self.state.lock().unwrap().interfaces[&if_index].servers()
+    }
+
+    /// Logs the call and, unless `ignored`, replaces the family's static servers.
+    fn write(
+        &self,
+        if_index: u32,
+        family: Family,
+        servers: &[IpAddr],
+        call: String,
+        ignored: bool,
+    ) -> AppResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(call);
+        let interface = state
+            .interfaces
+            .get_mut(&if_index)
+            .ok_or(AppError::InterfaceNotFound(if_index))?;
+        if ignored {
+            return Ok(());
+        }
+        let before = interface.servers();
+        *interface.static_servers(family) = servers.to_vec();
+        if self.stale_reads > 0 {
+            state.stale.insert(if_index, (self.stale_reads, before));
+        }
+        Ok(())
+    }
+
+    fn check_family(&self, family: Family) -> AppResult<()> {
+        if family == Family::V6 && self.no_ipv6 {
+            // ERROR_PROC_NOT_FOUND, as when the export is missing.
+            return Err(AppError::win32("SetInterfaceDnsSettings", 127));
+        }
+        Ok(())
+    }
+}
+
+impl AdapterDns for MockNetwork {
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some((reads, before)) = state.stale.get_mut(&if_index) {
+            if *reads > 0 {
+                *reads -= 1;
+                return Ok(before.clone());
+            }
+        }
+        state
+            .interfaces
+            .get(&if_index)
+            .map(Interface::servers)
+            .ok_or(AppError::InterfaceNotFound(if_index))
+    }
+
+    fn set_interface_dns(
+        &self,
+        if_index: u32,
+        family: Family,
+        servers: &[IpAddr],
+    ) -> AppResult<()> {
+        self.check_family(family)?;
+        if servers.is_empty() {
+            self.write(
+                if_index,
+                family,
+                servers,
+                format!("null {:?}", family),
+                self.ignore_null_clear,
+            )
+        } else {
+            self.write(
+                if_index,
+                family,
+                servers,
+                format!("set {:?} {:?}", family, servers),
+                false,
+            )
+        }
+    }
+
+    fn clear_interface_dns_empty_string(&self, if_index: u32, family: Family) -> AppResult<()> {
+        self.check_family(family)?;
+        self.write(
+            if_index,
+            family,
+            &[],
+            format!("empty {:?}", family),
+            self.ignore_empty_string_clear,
+        )
+    }
+
+    fn clear_interface_dns_legacy(&self, if_index: u32) -> AppResult<()> {
+        self.write(
+            if_index,
+            Family::V4,
+            &[],
+            "legacy".to_string(),
+            self.ignore_legacy_clear,
+        )
+    }
+
+    fn supports_ipv6_dns(&self) -> bool {
+        !self.no_ipv6
+    }
+
+    fn interface_dns_options(&self, if_index: u32) -> AppResult<DnsOptions> {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get(&if_index)
+            .map(|interface| interface.options.clone())
+            .ok_or(AppError::InterfaceNotFound(if_index))
+    }
+
+    fn set_interface_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("options {:?}", options));
+        state
+            .interfaces
+            .get_mut(&if_index)
+            .ok_or(AppError::InterfaceNotFound(if_index))?
+            .options = options.clone();
+        Ok(())
+    }
+}
+
+impl SnapshotStore for MockNetwork {
+    fn load(&self, if_index: u32) -> Option<Snapshot> {
+        self.state.lock().unwrap().snapshots.get(&if_index).cloned()
+    }
+
+    fn forget(&self, if_index: u32) {
+        self.state.lock().unwrap().snapshots.remove(&if_index);
+    }
+}
+
+impl DnsConfigurator for MockNetwork {
+    fn list_interfaces(&self) -> AppResult<Vec<NetworkInterface>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .interfaces
+            .iter()
+            .map(|(&if_index, interface)| NetworkInterface {
+                interface_index: if_index,
+                ipv6_interface_index: if_index,
+                name: format!("mock{}", if_index),
+                description: format!("Mock adapter {}", if_index),
+                mac_address: None,
+                if_type: 6, // ethernetCsmacd
+                kind: interface.kind,
+                is_up: interface.enabled,
+                is_admin_disabled: !interface.enabled,
+                ip_addresses: Vec::new(),
+                gateways: Vec::new(),
+                dns_servers: interface.servers().iter().map(IpAddr::to_string).collect(),
+                dhcp_enabled: true,
+                dhcp_server: None,
+                dns_is_static: !interface.static_v4.is_empty() || !interface.static_v6.is_empty(),
+                mtu: Some(1500),
+                link_speed: None,
+                stats: InterfaceStats::default(),
+            })
+            .collect())
+    }
+
+    fn best_interface_index(&self) -> AppResult<u32> {
+        match self.best.load(Ordering::Relaxed) {
+            0 => Err(AppError::NoActiveInterface),
+            best => Ok(best),
+        }
+    }
+
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        AdapterDns::interface_dns_servers(self, if_index)
+    }
+
+    fn supports_ipv6_dns(&self) -> bool {
+        AdapterDns::supports_ipv6_dns(self)
+    }
+
+    fn snapshot_dns(&self, if_index: u32) -> AppResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let interface = state
+            .interfaces
+            .get(&if_index)
+            .ok_or(AppError::InterfaceNotFound(if_index))?;
+        let original = Snapshot {
+            v4: interface.static_v4.clone(),
+            v6: interface.static_v6.clone(),
+            options: Some(interface.options.clone()),
+        };
+        state.snapshots.entry(if_index).or_insert(original);
+        Ok(())
+    }
+
+    fn set_dns(&self, if_index: u32, family: Family, servers: &[IpAddr]) -> AppResult<()> {
+        self.set_interface_dns(if_index, family, servers)
+    }
+
+    fn restore_dns(&self, if_index: u32) -> bool {
+        restore::restore_snapshot(self, self, if_index)
+    }
+
+    fn restore_all(&self) {
+        let indexes: Vec<u32> = self
+            .state
+            .lock()
+            .unwrap()
+            .interfaces
+            .keys()
+            .copied()
+            .collect();
+        for if_index in indexes {
+            if self.interface_uses_proxy_dns(if_index) {
+                self.restore_dns(if_index);
+            }
+        }
+    }
+
+    fn dns_options(&self, if_index: u32) -> AppResult<DnsOptions> {
+        self.interface_dns_options(if_index)
+    }
+
+    fn set_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()> {
+        self.set_interface_dns_options(if_index, options)
+    }
+
+    fn network_fingerprint(&self, if_index: u32) -> AppResult<NetworkFingerprint> {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get(&if_index)
+            .map(|interface| interface.network.clone())
+            .ok_or(AppError::InterfaceNotFound(if_index))
+    }
+
+    fn set_adapter_enabled(&self, if_index: u32, enable: bool) -> AppResult<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .get_mut(&if_index)
+            .ok_or(AppError::InterfaceNotFound(if_index))?
+            .enabled = enable;
+        Ok(())
+    }
+
+    fn flush_cache(&self) -> AppResult<()> {
+        self.state.lock().unwrap().calls.push("flush".to_string());
+        Ok(())
+    }
+
+    fn watch_changes(&self, on_change: ChangeCallback) {
+        self.state.lock().unwrap().on_change = Some(on_change);
+    }
+
+    fn unwatch_changes(&self) {
+        self.state.lock().unwrap().on_change = None;
+    }
+}
@@ -0,0 +1,687 @@
+//! The operating-system side of DNS configuration, behind one trait.
+//!
+//! Commands and the lifecycle hooks in `lib.rs` talk to [`DnsConfigurator`] only, via
+//! [`system`]. Each supported OS has one implementation:
+//!
+//! - **Windows** (`windows.rs`): a thin wrapper over `crate::win` — IP Helper, the
+//!   registry snapshots and the change journal.
+//! - **Linux** (`linux/`): systemd-resolved over D-Bus when it is running, otherwise
+//!   `/etc/resolv.conf` directly. Enough to run the real proxy flow end to end on a
+//!   developer machine.
+//!
+//! What is not OS-specific about DNS configuration lives here too: the proxy's loopback
+//! addresses, address families and the interface model the UI is sent.
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(test)]
+pub mod mock;
+#[cfg(any(windows, test))]
+pub mod restore;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+compile_error!("DNS configuration is only implemented for Windows and Linux");
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppResult;
+
+/// Loopback addresses the local DoH/DoT/DoQ/DoH3 proxy binds to.
+pub const PROXY_V4: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+pub const PROXY_V6: Ipv6Addr = Ipv6Addr::LOCALHOST; // ::1
+
+/// How many proxies can run at once, one per interface. Each gets its own IPv4
+/// loopback address starting at `PROXY_V4`, because the Windows DNS client only ever
+/// talks to port 53 — the address is the only thing that can tell them apart. IPv6 has
+/// a single loopback address, so only one of them can serve `::1`.
+pub const PROXY_SLOTS: u8 = 16;
+
+/// The IPv4 loopback address of proxy slot `slot` (`127.0.0.2`, `127.0.0.3`, …).
+pub fn proxy_v4(slot: u8) -> Ipv4Addr {
+    let [a, b, c, d] = PROXY_V4.octets();
+    Ipv4Addr::new(a, b, c, d + slot)
+}
+
+/// True if this address is one of the proxies' own loopback addresses.
+pub fn is_proxy_addr(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => (0..PROXY_SLOTS).any(|slot| *v4 == proxy_v4(slot)),
+        IpAddr::V6(v6) => *v6 == PROXY_V6,
+    }
+}
+
+/// Windows' default site-local IPv6 DNS anycast addresses.
+///
+/// These are present on most systems even when the user has never configured IPv6 DNS,
+/// so they must not be counted as "real" configured servers — otherwise every
+/// activation would think IPv6 DNS needs redirecting, and the UI would list them as
+/// though the user had set them.
+pub fn is_default_ipv6_anycast(ip: &IpAddr) -> bool {
+    const DEFAULTS: [Ipv6Addr; 3] = [
+        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 1),
+        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 2),
+        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 3),
+    ];
+    matches!(ip, IpAddr::V6(v6) if DEFAULTS.contains(v6))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn of(ip: &IpAddr) -> Self {
+        if ip.is_ipv4() {
+            Family::V4
+        } else {
+            Family::V6
+        }
+    }
+}
+
+/// One network interface as the UI sees it. `if_type` is the IANA ifType, which is
+/// what Windows reports natively; other platforms map onto it.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInterface {
+    pub interface_index: u32,
+    pub ipv6_interface_index: u32,
+    pub name: String,
+    pub description: String,
+    pub mac_address: Option<String>,
+    pub if_type: u32,
+    pub kind: AdapterKind,
+    pub is_up: bool,
+    pub is_admin_disabled: bool,
+    pub ip_addresses: Vec<String>,
+    pub gateways: Vec<String>,
+    pub dns_servers: Vec<String>,
+    /// Whether the interface gets its IPv4 address from DHCP.
+    pub dhcp_enabled: bool,
+    pub dhcp_server: Option<String>,
+    /// Whether the DNS servers were set on the interface rather than handed out by
+    /// DHCP. True if either family was.
+    pub dns_is_static: bool,
+    pub mtu: Option<u32>,
+    /// In bits per second. `None` when the driver does not say, as virtual adapters
+    /// often do not.
+    pub link_speed: Option<u64>,
+    pub stats: InterfaceStats,
+}
+
+/// Traffic through an interface since it came up. Packets count unicast and
+/// non-unicast alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct InterfaceStats {
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub packets_sent: u64,
+}
+
+// IANA ifType values the classification looks at.
+const IF_TYPE_PPP: u32 = 23;
+const IF_TYPE_SOFTWARE_LOOPBACK: u32 = 24;
+const IF_TYPE_PROP_VIRTUAL: u32 = 53;
+const IF_TYPE_IEEE80211: u32 = 71;
+const IF_TYPE_TUNNEL: u32 = 131;
+const IF_TYPE_WWANPP: u32 = 243;
+const IF_TYPE_WWANPP2: u32 = 244;
+
+/// Words in an adapter's description that give a VPN client away. Most of them install
+/// an Ethernet-looking virtual adapter, so the interface type alone cannot tell.
+const VPN_DESCRIPTIONS: [&str; 13] = [
+    "vpn",
+    "wireguard",
+    "wintun",
+    "tap-windows",
+    "anyconnect",
+    "globalprotect",
+    "pangp",
+    "fortinet",
+    "juniper",
+    "pulse secure",
+    "tailscale",
+    "zerotier",
+    "nordlynx",
+];
+
+/// How Linux VPN links are usually named. `tun` and `tap` are OpenVPN's, and those of
+/// most other clients that run in user space.
+const VPN_NAME_PREFIXES: [&str; 6] = ["wg", "tailscale", "zt", "nordlynx", "tun", "tap"];
+
+/// What sort of adapter an interface is, for the UI's icons and for choosing which
+/// interfaces "Auto" and batch activations may touch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdapterKind {
+    /// A wired network card.
+    #[default]
+    Physical,
+    /// Wi-Fi or mobile broadband.
+    Wireless,
+    Vpn,
+    /// Software with no hardware behind it: Hyper-V, VMware and VirtualBox switches,
+    /// container bridges.
+    Virtual,
+    /// Address-family tunnels such as 6to4, Teredo and IP-HTTPS.
+    Tunnel,
+    Loopback,
+}
+
+impl AdapterKind {
+    /// `hardware` is whether there is a device behind the interface: NDIS's
+    /// `HardwareInterface` flag on Windows, a `device` link in sysfs on Linux.
+    pub fn classify(if_type: u32, hardware: bool, name: &str, description: &str) -> Self {
+        let description = description.to_lowercase();
+        let name = name.to_lowercase();
+        if if_type == IF_TYPE_SOFTWARE_LOOPBACK {
+            AdapterKind::Loopback
+        } else if if_type == IF_TYPE_PPP
+            || VPN_DESCRIPTIONS
+                .iter()
+                .any(|word| description.contains(word))
+            || (VPN_NAME_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+                // The kernel's IP-in-IP device, not a VPN.
+                && !name.starts_with("tunl"))
+        {
+            AdapterKind::Vpn
+        } else if if_type == IF_TYPE_TUNNEL {
+            AdapterKind::Tunnel
+        } else if !hardware || if_type == IF_TYPE_PROP_VIRTUAL || description.contains("virtual") {
+            AdapterKind::Virtual
+        } else if matches!(
+            if_type,
+            IF_TYPE_IEEE80211 | IF_TYPE_WWANPP | IF_TYPE_WWANPP2
+        ) {
+            AdapterKind::Wireless
+        } else {
+            AdapterKind::Physical
+        }
+    }
+
+    /// Whether "Auto" may settle on it. Tunnels answer routing probes for IPv6 on
+    /// machines without native IPv6, and queries never really leave through them. A VPN
+    /// that carries the default route is where queries go, so it qualifies.
+    pub fn is_auto_selectable(self) -> bool {
+        !matches!(self, AdapterKind::Tunnel | AdapterKind::Loopback)
+    }
+}
+
+/// What tells one network from another, as seen from an interface connected to it.
+/// Any part can be missing: a point-to-point link has no gateway, Ethernet has no
+/// SSID, and plenty of networks hand out no DNS suffix.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkFingerprint {
+    /// The default gateway, IPv4 if there is one.
+    pub gateway_ip: Option<IpAddr>,
+    /// The gateway's MAC address, upper-case and colon-separated like
+    /// [`NetworkInterface::mac_address`]. It is what tells two networks that both use
+    /// `192.168.1.1` apart.
+    pub gateway_mac: Option<String>,
+    /// The connection-specific DNS suffix, usually from DHCP.
+    pub dns_suffix: Option<String>,
+    pub ssid: Option<String>,
+}
+
+/// An interface's DNS settings other than its name servers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsOptions {
+    /// The connection-specific suffix set on the interface itself; empty when there is
+    /// none and whatever DHCP hands out applies.
+    pub suffix: String,
+    /// Domains tried in order when resolving a single-label name.
+    pub search_list: Vec<String>,
+    /// Dynamic DNS: register the interface's addresses under the computer's name.
+    pub register_addresses: bool,
+    /// Register them under the connection-specific suffix as well.
+    pub register_with_suffix: bool,
+    /// Answer and send LLMNR queries on the interface.
+    pub llmnr: bool,
+}
+
+/// Event name the frontend listens on. Carries a [`NetworkChanges`]: everything reported
+/// within a short window, in one event.
+pub const NETWORK_CHANGED_EVENT: &str = "network-changed";
+
+/// Which table a [`NetworkChange`] was reported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeSource {
+    /// The interface itself: link up or down, adapter added or removed, MTU.
+    Interface,
+    /// A unicast address on the interface.
+    Address,
+    /// A route through the interface, the default route included.
+    Route,
+    /// The DNS servers in effect on the interface.
+    Dns,
+}
+
+/// What happened to the row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    /// The row was there before and still is, with something about it changed.
+    Parameter,
+}
+
+/// One change as the OS reported it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct NetworkChange {
+    /// 0 when the change is not tied to one interface.
+    pub interface_index: u32,
+    /// `None` when the change covers both families.
+    pub family: Option<Family>,
+    pub kind: ChangeKind,
+    pub source: ChangeSource,
+}
+
+/// Changes gathered over a short window. A Wi-Fi switch reports dozens of rows within a
+/// few milliseconds — the link, each address, each route, twice over for IPv6 — and the
+/// frontend should refetch once for all of them, not once per row.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NetworkChanges {
+    /// In the order first reported, each distinct change once.
+    pub changes: Vec<NetworkChange>,
+}
+
+impl NetworkChanges {
+    /// Adds the change unless an identical one is already pending. Returns whether the
+    /// batch was empty before, i.e. whether this change opens a new window.
+    pub fn push(&mut self, change: NetworkChange) -> bool {
+        let opened = self.changes.is_empty();
+        if !self.changes.contains(&change) {
+            self.changes.push(change);
+        }
+        opened
+    }
+}
+
+/// Finds which interfaces' DNS servers changed, by comparing what is in effect now
+/// with what was last seen. The OS only says *that* something DNS-related changed —
+/// a registry key or a file — not on which interface, nor whether the servers moved at
+/// all; a DHCP renewal rewrites the same keys with the same values.
+#[derive(Debug, Default)]
+pub struct DnsDiff {
+    last: HashMap<u32, Vec<IpAddr>>,
+}
+
+impl DnsDiff {
+    /// Starts from the servers in effect now, so the first comparison reports only what
+    /// changes after this call.
+    pub fn new(interfaces: &[NetworkInterface]) -> Self {
+        Self {
+            last: servers_by_interface(interfaces),
+        }
+    }
+
+    /// One change per interface and family whose servers differ from last time. An
+    /// interface that appeared or went away is left to the interface notifications.
+    pub fn changes(&mut self, interfaces: &[NetworkInterface]) -> Vec<NetworkChange> {
+        let current = servers_by_interface(interfaces);
+        let mut changes = Vec::new();
+        for (&index, servers) in &current {
+            let Some(before) = self.last.get(&index) else {
+                continue;
+            };
+            for family in [Family::V4, Family::V6] {
+                let of = |list: &[IpAddr]| -> Vec<IpAddr> {
+                    list.iter()
+                        .filter(|ip| Family::of(ip) == family)
+                        .copied()
+                        .collect()
+                };
+                let (old, new) = (of(before), of(servers));
+                if old == new {
+                    continue;
+                }
+                changes.push(NetworkChange {
+                    interface_index: index,
+                    family: Some(family),
+                    kind: if old.is_empty() {
+                        ChangeKind::Added
+                    } else if new.is_empty() {
+                        ChangeKind::Deleted
+                    } else {
+                        ChangeKind::Parameter
+                    },
+                    source: ChangeSource::Dns,
+                });
+            }
+        }
+        changes.sort_by_key(|change| (change.interface_index, change.family == Some(Family::V6)));
+        self.last = current;
+        changes
+    }
+}
+
+fn servers_by_interface(interfaces: &[NetworkInterface]) -> HashMap<u32, Vec<IpAddr>> {
+    interfaces
+        .iter()
+        .map(|interface| {
+            let servers = interface
+                .dns_servers
+                .iter()
+                .filter_map(|server| server.parse().ok())
+                .collect();
+            (interface.interface_index, servers)
+        })
+        .collect()
+}
+
+/// Called from whatever thread the OS reports network changes on, once per change.
+/// Must return quickly.
+pub type ChangeCallback = Arc<dyn Fn(NetworkChange) + Send + Sync>;
+
+/// Runs the body of an OS notification callback or watch loop iteration, containing a
+/// panic in it. Unwinding out of an `extern "system"` callback aborts the process, and
+/// the panic hook only restores DNS for the main thread, so the adapters would be left
+/// on a dead proxy; on a watch thread, the panic would end notifications for good. One
+/// dropped notification is the lesser loss, and the hook still logs the panic.
+pub fn guard_callback(what: &str, body: impl FnOnce()) {
+    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)).is_err() {
+        error!(
+            "Handling a {} change panicked; the notification was dropped",
+            what
+        );
+    }
+}
+
+/// Reading and changing the DNS configuration of network interfaces.
+///
+/// Every method blocks. Async callers go through `spawn_blocking` for anything slow;
+/// the exit handler and the panic hook have no runtime at all and call straight in.
+pub trait DnsConfigurator: Send + Sync {
+    /// All interfaces, with their addresses, gateways and DNS servers in effect.
+    fn list_interfaces(&self) -> AppResult<Vec<NetworkInterface>>;
+
+    /// The interface internet traffic is routed over.
+    fn best_interface_index(&self) -> AppResult<u32>;
+
+    /// The DNS servers in effect on the interface, both families.
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>>;
+
+    /// Whether IPv6 name servers can be changed at all.
+    fn supports_ipv6_dns(&self) -> bool;
+
+    /// Records how the interface's DNS is configured before the first change, so
+    /// [`restore_dns`](Self::restore_dns) can put it back. Keeps an existing record.
+    fn snapshot_dns(&self, if_index: u32) -> AppResult<()>;
+
+    /// Sets one family's servers, in order. An empty list reverts the family to what
+    /// the network provides.
+    fn set_dns(&self, if_index: u32, family: Family, servers: &[IpAddr]) -> AppResult<()>;
+
+    /// Whether DNS is one setting for every interface rather than per interface, as
+    /// with a bare `/etc/resolv.conf`. Only one interface can then be activated.
+    fn dns_is_global(&self) -> bool {
+        false
+    }
+
+    /// Puts the interface's DNS back the way [`snapshot_dns`](Self::snapshot_dns)
+    /// found it. Returns whether it took.
+    fn restore_dns(&self, if_index: u32) -> bool;
+
+    /// Restores every interface still pointing at a proxy address. Runs at startup, on
+    /// exit, from the panic hook and from the watchdog.
+    fn restore_all(&self);
+
+    /// Undoes changes a previous run made and never reverted. Runs once at startup,
+    /// before [`restore_all`](Self::restore_all).
+    fn recover(&self) {}
+
+    /// Called on a clean exit, once every proxy address has been restored: the plain
+    /// DNS still applied is meant to stay.
+    fn settle_on_exit(&self) {}
+
+    /// The interface's suffix, search list, registration and LLMNR settings.
+    fn dns_options(&self, if_index: u32) -> AppResult<DnsOptions>;
+
+    /// Replaces them. Covered by [`snapshot_dns`](Self::snapshot_dns) and
+    /// [`restore_dns`](Self::restore_dns) like the name servers, so snapshot first.
+    fn set_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()>;
+
+    /// What the network the interface is connected to looks like. Parts the OS
+    /// cannot report are left out rather than failing the whole call.
+    fn network_fingerprint(&self, if_index: u32) -> AppResult<NetworkFingerprint>;
+
+    /// Brings the adapter up or takes it down.
+    fn set_adapter_enabled(&self, if_index: u32, enable: bool) -> AppResult<()>;
+
+    /// Empties the system resolver's cache.
+    fn flush_cache(&self) -> AppResult<()>;
+
+    /// Calls `on_change` for every change to an interface, its addresses, its routes or
+    /// its DNS servers, uncoalesced. Failure is logged and non-fatal: the frontend keeps
+    /// a slow poll as a safety net.
+    fn watch_changes(&self, on_change: ChangeCallback);
+
+    /// Stops calling the callback given to [`watch_changes`](Self::watch_changes).
+    fn unwatch_changes(&self);
+
+    /// Maps the frontend's `0` = "Auto" sentinel to the real best-interface index.
+    fn resolve_interface_index(&self, idx: u32) -> AppResult<u32> {
+        if idx == 0 {
+            self.best_interface_index()
+        } else {
+            Ok(idx)
+        }
+    }
+
+    /// The interface's real resolvers: what it is configured with, minus the proxy's
+    /// own loopback addresses and Windows' default IPv6 anycast servers.
+    ///
+    /// Read before activation, this is what the machine would be using without the app.
+    fn real_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        Ok(self
+            .interface_dns_servers(if_index)?
+            .into_iter()
+            .filter(|ip| !is_proxy_addr(ip) && !is_default_ipv6_anycast(ip))
+            .collect())
+    }
+
+    /// True if the interface has real (user- or DHCP-configured, non-anycast-default)
+    /// IPv6 DNS servers that would bypass an IPv4-only proxy.
+    fn has_real_ipv6_dns(&self, if_index: u32) -> bool {
+        match self.real_dns_servers(if_index) {
+            Ok(servers) => servers.iter().any(IpAddr::is_ipv6),
+            Err(e) => {
+                error!(
+                    "Failed to read DNS servers on interface {}: {}",
+                    if_index, e
+                );
+                false
+            }
+        }
+    }
+
+    /// True if the interface still points at the proxy loopback on either family.
+    fn interface_uses_proxy_dns(&self, if_index: u32) -> bool {
+        match self.interface_dns_servers(if_index) {
+            Ok(servers) => servers.iter().any(is_proxy_addr),
+            Err(e) => {
+                error!(
+                    "Failed to verify DNS state on interface {}: {}",
+                    if_index, e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// The configurator for the OS this build targets.
+pub fn system() -> &'static dyn DnsConfigurator {
+    #[cfg(windows)]
+    {
+        static SYSTEM: windows::WindowsConfigurator = windows::WindowsConfigurator;
+        &SYSTEM
+    }
+    #[cfg(target_os = "linux")]
+    {
+        static SYSTEM: std::sync::OnceLock<linux::LinuxConfigurator> = std::sync::OnceLock::new();
+        SYSTEM.get_or_init(linux::LinuxConfigurator::new)
+    }
+}
+
+/// Where the app keeps state that has to survive a crash: `%LOCALAPPDATA%\<identifier>`
+/// on Windows, the same folder Tauri uses for local app data, and the XDG state
+/// directory elsewhere.
+pub(crate) fn state_dir() -> PathBuf {
+    #[cfg(windows)]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+
+    base.unwrap_or_else(std::env::temp_dir)
+        .join("ir.betterdnsjumper.app")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapters_are_classified_by_what_they_are() {
+        let cases = [
+            (
+                6,
+                true,
+                "Ethernet",
+                "Intel(R) Ethernet Connection I219-V",
+                AdapterKind::Physical,
+            ),
+            (
+                71,
+                true,
+                "Wi-Fi",
+                "Intel(R) Wi-Fi 6 AX201 160MHz",
+                AdapterKind::Wireless,
+            ),
+            (
+                6,
+                false,
+                "vEthernet (Default Switch)",
+                "Hyper-V Virtual Ethernet Adapter",
+                AdapterKind::Virtual,
+            ),
+            (
+                6,
+                true,
+                "VMware Network Adapter VMnet8",
+                "VMware Virtual Ethernet Adapter for VMnet8",
+                AdapterKind::Virtual,
+            ),
+            (53, false, "home", "WireGuard Tunnel", AdapterKind::Vpn),
+            (
+                6,
+                false,
+                "Ethernet 3",
+                "Cisco AnyConnect Secure Mobility Client Virtual Miniport Adapter for Windows x64",
+                AdapterKind::Vpn,
+            ),
+            (131, false, "wg0", "wg0", AdapterKind::Vpn),
+            (131, false, "tun0", "tun0", AdapterKind::Vpn),
+            (6, false, "tap0", "tap0", AdapterKind::Vpn),
+            (131, false, "tunl0", "tunl0", AdapterKind::Tunnel),
+            (
+                131,
+                false,
+                "Teredo Tunneling Pseudo-Interface",
+                "Microsoft Teredo Tunneling Adapter",
+                AdapterKind::Tunnel,
+            ),
+            (24, false, "lo", "lo", AdapterKind::Loopback),
+            (6, false, "docker0", "docker0", AdapterKind::Virtual),
+        ];
+        for (if_type, hardware, name, description, kind) in cases {
+            assert_eq!(
+                AdapterKind::classify(if_type, hardware, name, description),
+                kind,
+                "{}",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_changes_within_a_window_are_sent_once() {
+        let link = NetworkChange {
+            interface_index: 4,
+            family: None,
+            kind: ChangeKind::Parameter,
+            source: ChangeSource::Interface,
+        };
+        let route = NetworkChange {
+            family: Some(Family::V4),
+            kind: ChangeKind::Added,
+            source: ChangeSource::Route,
+            ..link
+        };
+        let mut batch = NetworkChanges::default();
+        assert!(batch.push(link));
+        assert!(!batch.push(route));
+        assert!(!batch.push(link));
+        assert_eq!(batch.changes, vec![link, route]);
+    }
+
+    #[test]
+    fn dns_diff_reports_only_the_families_that_moved() {
+        use mock::MockNetwork;
+
+        let net = MockNetwork::default()
+            .interface(3, &["192.168.1.1"])
+            .interface(7, &["10.0.0.1"]);
+        let mut diff = DnsDiff::new(&net.list_interfaces().unwrap());
+        // A DHCP renewal that hands out the same servers.
+        assert!(diff.changes(&net.list_interfaces().unwrap()).is_empty());
+
+        net.set_dns(3, Family::V4, &["127.0.0.1".parse().unwrap()])
+            .unwrap();
+        net.set_dns(3, Family::V6, &["::1".parse().unwrap()])
+            .unwrap();
+        let changes = diff.changes(&net.list_interfaces().unwrap());
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.interface_index, change.family, change.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, Some(Family::V4), ChangeKind::Parameter),
+                (3, Some(Family::V6), ChangeKind::Added),
+            ]
+        );
+        assert!(changes
+            .iter()
+            .all(|change| change.source == ChangeSource::Dns));
+        // Reported once, not on every comparison after.
+        assert!(diff.changes(&net.list_interfaces().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn a_panicking_callback_is_contained() {
+        let mut after = false;
+        guard_callback("test", || panic!("boom"));
+        guard_callback("test", || after = true);
+        assert!(after);
+    }
+}
@@ -0,0 +1,429 @@
+//! Reverting an interface's DNS, escalating until it takes.
+//!
+//! Written against [`AdapterDns`] — the handful of primitive operations `crate::win`
+//! provides — and [`SnapshotStore`] rather than against IP Helper and the registry
+//! directly, so the choice of what to put back, the escalation and its verification
+//! all run the same way against the in-memory network in `platform::mock`.
+//! This logic has regressed more than once; it is the part that most needs tests.
+
+use std::net::IpAddr;
+
+use log::{debug, error, warn};
+
+use super::{is_default_ipv6_anycast, DnsOptions, Family};
+use crate::error::AppResult;
+
+/// The adapter-level operations the restore is built from.
+pub trait AdapterDns: Send + Sync {
+    /// The servers in effect on the interface, both families.
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>>;
+
+    /// Sets one family's servers. An empty list is the documented clear: a null
+    /// `NameServer` on Windows.
+    fn set_interface_dns(&self, if_index: u32, family: Family, servers: &[IpAddr])
+        -> AppResult<()>;
+
+    /// The same clear spelled as an empty string.
+    fn clear_interface_dns_empty_string(&self, if_index: u32, family: Family) -> AppResult<()>;
+
+    /// The last resort: IPv4 only, through whatever the OS had before (WMI on Windows).
+    fn clear_interface_dns_legacy(&self, if_index: u32) -> AppResult<()>;
+
+    /// Whether IPv6 name servers can be written at all.
+    fn supports_ipv6_dns(&self) -> bool;
+
+    /// The suffix, search list, registration and LLMNR settings.
+    fn interface_dns_options(&self, if_index: u32) -> AppResult<DnsOptions>;
+
+    fn set_interface_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()>;
+}
+
+/// What the interface had before the first change: each family's static servers, empty
+/// meaning DHCP, and its options when they were saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub v4: Vec<IpAddr>,
+    pub v6: Vec<IpAddr>,
+    pub options: Option<DnsOptions>,
+}
+
+/// Where the snapshots are kept: a file in the state directory on Windows, a map in the
+/// mock.
+pub trait SnapshotStore: Send + Sync {
+    fn load(&self, if_index: u32) -> Option<Snapshot>;
+
+    /// Called once the interface is back the way it was, so nothing about it needs
+    /// keeping any more.
+    fn forget(&self, if_index: u32);
+}
+
+/// Puts the interface's DNS back the way its snapshot says, DHCP when there is none,
+/// and its options with it. The snapshot is forgotten once everything took, and kept
+/// otherwise, so the next attempt still knows what to put back.
+pub fn restore_snapshot(dns: &dyn AdapterDns, store: &dyn SnapshotStore, if_index: u32) -> bool {
+    let snapshot = store.load(if_index);
+    let mut restored = match snapshot {
+        Some(ref snapshot) if !snapshot.v4.is_empty() || !snapshot.v6.is_empty() => {
+            restore_servers(dns, if_index, &snapshot.v4, &snapshot.v6)
+        }
+        _ => restore_dhcp(dns, if_index),
+    };
+    if let Some(options) = snapshot.as_ref().and_then(|s| s.options.as_ref()) {
+        restored &= restore_options(dns, if_index, options);
+    }
+    if restored {
+        store.forget(if_index);
+    }
+    restored
+}
+
+/// Writes the options back, unless they are already what the interface has.
+fn restore_options(dns: &dyn AdapterDns, if_index: u32, options: &DnsOptions) -> bool {
+    if dns.interface_dns_options(if_index).ok().as_ref() == Some(options) {
+        return true;
+    }
+    match dns.set_interface_dns_options(if_index, options) {
+        Ok(()) => true,
+        Err(e) => {
+            error!(
+                "Could not restore the DNS options of interface {}: {}",
+                if_index, e
+            );
+            false
+        }
+    }
+}
+
+/// Writes static servers back to the interface; an empty list puts that family back
+/// on DHCP, with the same empty-string retry as [`restore_dhcp`]. At least one of the
+/// two must be non-empty — all-DHCP is [`restore_dhcp`]'s job.
+pub fn restore_servers(dns: &dyn AdapterDns, if_index: u32, v4: &[IpAddr], v6: &[IpAddr]) -> bool {
+    let applied: Vec<IpAddr> = match dns.interface_dns_servers(if_index) {
+        Ok(servers) => servers
+            .into_iter()
+            .filter(|ip| !is_default_ipv6_anycast(ip))
+            .collect(),
+        Err(e) => {
+            error!("Cannot restore interface {}: {}", if_index, e);
+            return false;
+        }
+    };
+    debug!(
+        "Restoring interface {} to {:?} / {:?}, currently set to {:?}",
+        if_index, v4, v6, applied
+    );
+
+    // Without SetInterfaceDnsSettings the IPv6 servers cannot be written, so they were
+    // never changed either.
+    let targets: Vec<(Family, &[IpAddr])> = if dns.supports_ipv6_dns() {
+        vec![(Family::V4, v4), (Family::V6, v6)]
+    } else {
+        vec![(Family::V4, v4)]
+    };
+
+    for &(family, servers) in &targets {
+        if let Err(e) = dns.set_interface_dns(if_index, family, servers) {
+            error!(
+                "Could not restore {:?} DNS on interface {}: {}",
+                family, if_index, e
+            );
+        }
+    }
+    if servers_restored(dns, if_index, &targets, &applied) {
+        return true;
+    }
+
+    warn!(
+        "Interface {} does not match its snapshot yet — retrying the DHCP families with an empty string",
+        if_index
+    );
+    for &(family, servers) in &targets {
+        if servers.is_empty() {
+            if let Err(e) = dns.clear_interface_dns_empty_string(if_index, family) {
+                debug!(
+                    "Empty-string clear failed for {:?} on interface {}: {}",
+                    family, if_index, e
+                );
+            }
+        }
+    }
+    servers_restored(dns, if_index, &targets, &applied)
+}
+
+/// True once every static family lists exactly its servers, in order, and nothing
+/// else that was `applied` is left.
+fn servers_restored(
+    dns: &dyn AdapterDns,
+    if_index: u32,
+    targets: &[(Family, &[IpAddr])],
+    applied: &[IpAddr],
+) -> bool {
+    let expected: Vec<&(Family, &[IpAddr])> = targets
+        .iter()
+        .filter(|(_, servers)| !servers.is_empty())
+        .collect();
+    let wanted: Vec<&IpAddr> = expected.iter().flat_map(|(_, s)| s.iter()).collect();
+
+    poll_dns(dns, if_index, |current| {
+        let leftover = applied
+            .iter()
+            .any(|ip| !wanted.contains(&ip) && current.contains(ip));
+        !leftover
+            && expected.iter().all(|(family, servers)| {
+                let configured: Vec<IpAddr> = current
+                    .iter()
+                    .filter(|ip| Family::of(ip) == *family && !is_default_ipv6_anycast(ip))
+                    .copied()
+                    .collect();
+                configured == *servers
+            })
+    })
+}
+
+/// Reverts one interface to its DHCP-provided DNS servers, escalating until it takes.
+///
+/// Returns whether the previously configured servers are actually gone.
+///
+/// Blocking on purpose: the exit handler has no async runtime, and leaving `127.0.0.2`
+/// applied there is precisely the failure that strands a user with no working internet.
+/// The async command wraps this in `spawn_blocking` so both paths share one
+/// implementation — they diverged once before, and only the command got fixed.
+///
+/// Three mechanisms are tried in order, because they do not fail alike:
+///
+/// 1. `SetInterfaceDnsSettings` with a null `NameServer` — the documented spelling.
+/// 2. The same call with an empty string. A null pointer appears to be ignored on some
+///    systems even with `DNS_SETTING_NAMESERVER` set, so the call reports success while
+///    the old servers stay exactly where they were.
+/// 3. WMI's `SetDNSServerSearchOrder`, which this app used before the IP Helper
+///    migration. IPv4-only, which is the family that matters — `127.0.0.2` is what
+///    breaks name resolution when it is left behind.
+pub fn restore_dhcp(dns: &dyn AdapterDns, if_index: u32) -> bool {
+    // Whatever is configured right now is what has to disappear. Snapshotting it works
+    // for plain DNS too, where nothing points at the proxy and checking only for
+    // 127.0.0.2 would report success without having changed anything at all.
+    let applied: Vec<IpAddr> = match dns.interface_dns_servers(if_index) {
+        Ok(servers) => servers
+            .into_iter()
+            .filter(|ip| !is_default_ipv6_anycast(ip))
+            .collect(),
+        Err(e) => {
+            error!("Cannot restore interface {}: {}", if_index, e);
+            return false;
+        }
+    };
+
+    if applied.is_empty() {
+        debug!(
+            "Interface {} has no configured DNS servers; nothing to restore",
+            if_index
+        );
+        return true;
+    }
+    debug!(
+        "Restoring interface {}, currently set to {:?}",
+        if_index, applied
+    );
+
+    for family in [Family::V4, Family::V6] {
+        if let Err(e) = dns.set_interface_dns(if_index, family, &[]) {
+            debug!(
+                "Null-pointer clear failed for {:?} on interface {}: {}",
+                family, if_index, e
+            );
+        }
+    }
+    if dns_restored(dns, if_index, &applied) {
+        return true;
+    }
+
+    warn!(
+        "Interface {} unchanged after the null clear — retrying with an empty string",
+        if_index
+    );
+    for family in [Family::V4, Family::V6] {
+        if let Err(e) = dns.clear_interface_dns_empty_string(if_index, family) {
+            debug!(
+                "Empty-string clear failed for {:?} on interface {}: {}",
+                family, if_index, e
+            );
+        }
+    }
+    if dns_restored(dns, if_index, &applied) {
+        return true;
+    }
+
+    warn!(
+        "Interface {} unchanged after both IP Helper forms — falling back to WMI",
+        if_index
+    );
+    if let Err(e) = dns.clear_interface_dns_legacy(if_index) {
+        error!("WMI fallback failed on interface {}: {}", if_index, e);
+    }
+    dns_restored(dns, if_index, &applied)
+}
+
+/// True once none of `applied` are still configured on the interface.
+fn dns_restored(dns: &dyn AdapterDns, if_index: u32, applied: &[IpAddr]) -> bool {
+    poll_dns(dns, if_index, |current| {
+        !applied.iter().any(|ip| current.contains(ip))
+    })
+}
+
+/// Reads the interface's DNS servers until `done` accepts them.
+///
+/// Polls rather than reading once: `GetAdaptersAddresses` does not always reflect a
+/// `SetInterfaceDnsSettings` write immediately, so a single read taken straight
+/// afterwards can report the old servers and make a good restore look like a failure.
+/// Kept short — this runs during application exit.
+fn poll_dns(dns: &dyn AdapterDns, if_index: u32, done: impl Fn(&[IpAddr]) -> bool) -> bool {
+    const ATTEMPTS: usize = 4;
+    const DELAY: std::time::Duration = std::time::Duration::from_millis(150);
+
+    for attempt in 1..=ATTEMPTS {
+        match dns.interface_dns_servers(if_index) {
+            Ok(current) => {
+                if done(&current) {
+                    debug!(
+                        "Interface {} restored to {:?} after {} read(s)",
+                        if_index, current, attempt
+                    );
+                    return true;
+                }
+                debug!(
+                    "Interface {} still shows {:?} on read {}/{}",
+                    if_index, current, attempt, ATTEMPTS
+                );
+            }
+            Err(e) => debug!(
+                "Could not read DNS servers on interface {}: {}",
+                if_index, e
+            ),
+        }
+        if attempt < ATTEMPTS {
+            std::thread::sleep(DELAY);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock::MockNetwork;
+    use crate::platform::DnsConfigurator;
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn the_null_clear_is_enough_when_it_works() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2", "::1"]);
+
+        assert!(restore_dhcp(&net, 7));
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert_eq!(net.calls(), vec!["null V4", "null V6"]);
+    }
+
+    #[test]
+    fn an_ignored_null_clear_escalates_to_the_empty_string() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .ignore_null_clear();
+
+        assert!(restore_dhcp(&net, 7));
+        assert_eq!(net.servers(7), ips(&["192.168.1.1"]));
+        assert_eq!(
+            net.calls(),
+            vec!["null V4", "null V6", "empty V4", "empty V6"]
+        );
+    }
+
+    #[test]
+    fn the_legacy_clear_is_the_last_resort() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .ignore_null_clear()
+            .ignore_empty_string_clear();
+
+        assert!(restore_dhcp(&net, 7));
+        assert_eq!(net.calls().last().unwrap(), "legacy");
+    }
+
+    #[test]
+    fn slow_reads_do_not_escalate() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .stale_reads(2);
+
+        assert!(restore_dhcp(&net, 7));
+        assert_eq!(net.calls(), vec!["null V4", "null V6"]);
+    }
+
+    #[test]
+    fn reports_failure_when_nothing_takes() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .ignore_null_clear()
+            .ignore_empty_string_clear()
+            .ignore_legacy_clear();
+
+        assert!(!restore_dhcp(&net, 7));
+        assert_eq!(net.servers(7), ips(&["127.0.0.2"]));
+    }
+
+    #[test]
+    fn static_servers_are_written_back_in_order() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1", "fd00::1"])
+            .with_static(7, &["127.0.0.2", "::1"]);
+
+        assert!(restore_servers(
+            &net,
+            7,
+            &ips(&["10.0.0.53", "10.0.0.54"]),
+            &[]
+        ));
+        assert_eq!(net.servers(7), ips(&["10.0.0.53", "10.0.0.54", "fd00::1"]));
+    }
+
+    #[test]
+    fn ipv6_is_left_alone_without_support() {
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["127.0.0.2"])
+            .without_ipv6_support();
+
+        assert!(restore_servers(&net, 7, &ips(&["10.0.0.53"]), &[]));
+        assert_eq!(net.calls(), vec!["set V4 [10.0.0.53]"]);
+    }
+
+    #[test]
+    fn the_snapshot_is_kept_until_the_restore_takes() {
+        let stuck = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .ignore_null_clear()
+            .ignore_empty_string_clear()
+            .ignore_legacy_clear();
+        stuck.snapshot_dns(7).unwrap();
+        stuck.set_dns(7, Family::V4, &ips(&["127.0.0.2"])).unwrap();
+        assert!(!restore_snapshot(&stuck, &stuck, 7));
+        assert!(stuck.load(7).is_some());
+
+        let net = MockNetwork::new()
+            .interface(7, &["192.168.1.1"])
+            .with_static(7, &["10.0.0.53"]);
+        net.snapshot_dns(7).unwrap();
+        net.set_dns(7, Family::V4, &ips(&["127.0.0.2"])).unwrap();
+        assert!(restore_snapshot(&net, &net, 7));
+        assert_eq!(net.servers(7), ips(&["10.0.0.53"]));
+        assert!(net.load(7).is_none());
+    }
+}
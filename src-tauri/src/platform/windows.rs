@@ -0,0 +1,90 @@
+//! [`DnsConfigurator`] on Windows: everything goes through `crate::win`.
+
+use std::net::IpAddr;
+
+use super::{
+    ChangeCallback, DnsConfigurator, DnsOptions, Family, NetworkFingerprint, NetworkInterface,
+};
+use crate::error::AppResult;
+use crate::win;
+
+pub struct WindowsConfigurator;
+
+impl DnsConfigurator for WindowsConfigurator {
+    fn list_interfaces(&self) -> AppResult<Vec<NetworkInterface>> {
+        win::adapters::list_interfaces()
+    }
+
+    fn best_interface_index(&self) -> AppResult<u32> {
+        win::adapters::best_interface_index()
+    }
+
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        win::interface_dns_servers(if_index)
+    }
+
+    /// Without `SetInterfaceDnsSettings` (before Windows 10 1903) only the WMI path is
+    /// left, and it is IPv4-only.
+    fn supports_ipv6_dns(&self) -> bool {
+        win::dns_settings::supports_ipv6_dns()
+    }
+
+    fn snapshot_dns(&self, if_index: u32) -> AppResult<()> {
+        win::dns_snapshot::ensure(if_index).map(|_| ())
+    }
+
+    /// Journaled, so a crash before the change is undone can still be recovered from.
+    fn set_dns(&self, if_index: u32, family: Family, servers: &[IpAddr]) -> AppResult<()> {
+        win::dns_journal::set_interface_dns(if_index, family, servers)
+    }
+
+    fn restore_dns(&self, if_index: u32) -> bool {
+        win::restore_dns_blocking(if_index)
+    }
+
+    fn restore_all(&self) {
+        win::clear_stale_doh_dns();
+    }
+
+    fn recover(&self) {
+        win::dns_journal::replay();
+    }
+
+    fn settle_on_exit(&self) {
+        win::dns_journal::settle_on_exit();
+    }
+
+    fn dns_options(&self, if_index: u32) -> AppResult<DnsOptions> {
+        win::dns_options::read(if_index)
+    }
+
+    /// Not journaled: like plain DNS, these are meant to stay until the user clears
+    /// them, and the snapshot already knows what to put back.
+    fn set_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()> {
+        win::dns_options::write(if_index, options)
+    }
+
+    fn network_fingerprint(&self, if_index: u32) -> AppResult<NetworkFingerprint> {
+        win::network_id::network_fingerprint(if_index)
+    }
+
+    /// Through SetupAPI, the mechanism Device Manager uses; see `win::device`. Requires
+    /// administrator rights; without them SetupAPI reports ERROR_ACCESS_DENIED, which
+    /// surfaces as a Win32 error with the system's own description.
+    fn set_adapter_enabled(&self, if_index: u32, enable: bool) -> AppResult<()> {
+        let guid = win::dns_settings::interface_guid(if_index)?;
+        win::device::set_adapter_enabled(guid, enable)
+    }
+
+    fn flush_cache(&self) -> AppResult<()> {
+        win::flush_resolver_cache()
+    }
+
+    fn watch_changes(&self, on_change: ChangeCallback) {
+        win::notify::register(on_change);
+    }
+
+    fn unwatch_changes(&self) {
+        win::notify::unregister();
+    }
+}
@@ -5,4 +5,7 @@ pub struct ServerTestResult {
     pub success: bool,
     pub latency: usize,
     pub error: Option<String>,
+    /// Whether the entry's profile canary confirmed the profile is applied; `None` when
+    /// there is no canary. On `Some(false)`, `error` says what the canary returned.
+    pub profile_confirmed: Option<bool>,
 }
@@ -0,0 +1,150 @@
+//! A second process that restores DNS if the app is killed.
+//!
+//! Task Manager's "End task" (or `kill -9`), a crash that takes the process down without
+//! unwinding, or Windows force-terminating the app during shutdown all skip every exit handler
+//! and the panic hook. The adapters then point at a proxy that no longer exists until
+//! the next launch. The only thing that can still act at that point is another process.
+//!
+//! The app starts the same binary as `watchdog --parent-pid <pid>`, with a pipe on its
+//! stdin. On a clean exit the app writes [`CLEAN_EXIT`] to the pipe before it goes.
+//! The watchdog waits for the parent process to end; if it ended without saying so, it
+//! runs the same restore as the exit handler. Everything it does is written to the app
+//! log through `logging::init_standalone`.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+
+use log::{debug, info, warn};
+
+use crate::platform;
+
+/// What the app writes to the watchdog's stdin on a clean exit.
+const CLEAN_EXIT: &str = "clean-exit";
+
+/// `CREATE_NO_WINDOW`: debug builds are console-subsystem executables, and without this
+/// every launch would open a console window for the watchdog.
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// The pipe to the running watchdog, kept open for as long as the app runs.
+static WATCHDOG_STDIN: Mutex<Option<ChildStdin>> = Mutex::new(None);
+
+/// Starts the watchdog for this process. Failure is logged and otherwise ignored: the
+/// app works without it, it only loses the protection against being killed.
+pub fn spawn() {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            warn!("Not starting the DNS watchdog: {}", e);
+            return;
+        }
+    };
+    let mut command = Command::new(exe);
+    command
+        .args(["watchdog", "--parent-pid", &std::process::id().to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let child = command.spawn();
+
+    match child {
+        Ok(mut child) => {
+            debug!("DNS watchdog started as process {}", child.id());
+            *WATCHDOG_STDIN.lock().unwrap_or_else(|e| e.into_inner()) = child.stdin.take();
+        }
+        Err(e) => warn!("Could not start the DNS watchdog: {}", e),
+    }
+}
+
+/// Tells the watchdog this exit is deliberate and DNS has already been restored.
+pub fn signal_clean_exit() {
+    let mut stdin = WATCHDOG_STDIN.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(mut pipe) = stdin.take() {
+        if let Err(e) = writeln!(pipe, "{}", CLEAN_EXIT).and_then(|_| pipe.flush()) {
+            warn!(
+                "Could not tell the DNS watchdog about the clean exit: {}",
+                e
+            );
+        }
+    }
+}
+
+/// The watchdog process itself. Returns the exit code.
+pub fn run(parent_pid: u32) -> i32 {
+    crate::logging::init_standalone();
+    debug!("DNS watchdog watching process {}", parent_pid);
+
+    // Read on a thread: the pipe reaches EOF once the parent is gone, however it went,
+    // so joining it after the wait below also means every message has been seen.
+    let reader = std::thread::spawn(|| {
+        BufReader::new(std::io::stdin().lock())
+            .lines()
+            .map_while(Result::ok)
+            .any(|line| line.trim() == CLEAN_EXIT)
+    });
+
+    wait_for_exit(parent_pid);
+    let clean = reader.join().unwrap_or(false);
+
+    if clean {
+        debug!("Process {} exited cleanly; DNS watchdog done", parent_pid);
+        return 0;
+    }
+
+    warn!(
+        "Process {} ended without a clean exit — the DNS watchdog is restoring the adapters",
+        parent_pid
+    );
+    platform::system().restore_all();
+    info!(
+        "DNS watchdog finished restoring after process {}",
+        parent_pid
+    );
+    0
+}
+
+/// Blocks until the process ends. Returns at once if it cannot be opened, which
+/// almost always means it has already gone.
+#[cfg(windows)]
+fn wait_for_exit(pid: u32) {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{
+        OpenProcess, WaitForSingleObject, INFINITE, PROCESS_SYNCHRONIZE,
+    };
+
+    unsafe {
+        match OpenProcess(PROCESS_SYNCHRONIZE, false, pid) {
+            Ok(handle) => {
+                WaitForSingleObject(handle, INFINITE);
+                let _ = CloseHandle(handle);
+            }
+            Err(e) => log::error!("DNS watchdog could not open process {}: {}", pid, e),
+        }
+    }
+}
+
+/// Polls procfs, since only a parent can wait on a process. A zombie counts as gone:
+/// it has exited and is only waiting for its own parent to reap it.
+#[cfg(target_os = "linux")]
+fn wait_for_exit(pid: u32) {
+    let stat = format!("/proc/{}/stat", pid);
+    loop {
+        let running = std::fs::read_to_string(&stat).is_ok_and(|text| {
+            // The state follows the command name, which is in parentheses and may
+            // itself contain spaces or parentheses.
+            text.rsplit_once(')')
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .is_some_and(|state| state != "Z" && state != "X")
+        });
+        if !running {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
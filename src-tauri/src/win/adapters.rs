@@ -1,4 +1,6 @@
-//! Network interface enumeration via `GetAdaptersAddresses` + `GetIfTable2`.
+//! Network interface enumeration via `GetAdaptersAddresses` + `GetIfTable2`. The first
+//! has the addresses, DHCP state and link properties; the second the admin state, the
+//! traffic counters and whether there is hardware behind the interface.
 //!
 //! Replaces the WMI `Win32_NetworkAdapter[Configuration]` queries the app polls every
 //! ~5-10s from the frontend. Those were two unfiltered full-table WMI scans per call;
@@ -20,10 +22,10 @@ use std::ffi::c_void;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ptr;
 
-use log::{error, info, warn};
-use serde::Serialize;
-
+use super::dns_snapshot::read_static_servers;
 use crate::error::{AppError, AppResult};
+use crate::platform::{AdapterKind, Family, InterfaceStats, NetworkInterface};
+use log::{error, info, warn};
 use windows::Win32::NetworkManagement::IpHelper::{
     ConvertInterfaceLuidToIndex, FreeMibTable, GetAdaptersAddresses, GetBestInterfaceEx,
     GetIfTable2, GAA_FLAG_INCLUDE_ALL_INTERFACES, GAA_FLAG_INCLUDE_GATEWAYS, GAA_FLAG_SKIP_ANYCAST,
@@ -32,27 +34,13 @@ use windows::Win32::NetworkManagement::IpHelper::{
 use windows::Win32::NetworkManagement::Ndis::NET_LUID_LH;
 use windows::Win32::Networking::WinSock::{AF_INET, AF_INET6, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6};
 
-/// One network interface, flattened from `GetAdaptersAddresses` + `GetIfTable2`.
-/// Replaces the old WMI-derived `{ adapter, config }` shape.
-#[derive(Debug, Clone, Serialize)]
-pub struct NetworkInterface {
-    pub interface_index: u32,
-    pub ipv6_interface_index: u32,
-    pub name: String,
-    pub description: String,
-    pub mac_address: Option<String>,
-    pub if_type: u32,
-    pub is_up: bool,
-    pub is_admin_disabled: bool,
-    pub ip_addresses: Vec<String>,
-    pub gateways: Vec<String>,
-    pub dns_servers: Vec<String>,
-}
-
 // IF_OPER_STATUS / NET_IF_ADMIN_STATUS values (netioapi.h) — stable Win32 constants.
 const IF_OPER_STATUS_UP: i64 = 1;
 const NET_IF_ADMIN_STATUS_DOWN: i64 = 2;
 
+// IP_ADAPTER_ADDRESSES `Flags` bit (iptypes.h).
+const IP_ADAPTER_DHCP_ENABLED: u32 = 0x0004;
+
 // Plain Win32 error codes (WinError.h) — used instead of the typed `WIN32_ERROR`
 // constants because `GetAdaptersAddresses` returns a bare `u32`, not `WIN32_ERROR`.
 const ERROR_SUCCESS: u32 = 0;
@@ -60,14 +48,21 @@ const ERROR_BUFFER_OVERFLOW: u32 = 111;
 const ERROR_NO_DATA: u32 = 232;
 
 /// Lists all network interfaces with their current addresses, gateways, DNS servers,
-/// and admin-enabled state.
+/// admin-enabled state, counters and kind.
 pub fn list_interfaces() -> AppResult<Vec<NetworkInterface>> {
     let mut interfaces = read_adapters()?;
-    let admin_disabled = read_admin_disabled_map();
+    let rows = read_if_rows();
     for iface in &mut interfaces {
-        if let Some(disabled) = admin_disabled.get(&iface.interface_index) {
-            iface.is_admin_disabled = *disabled;
+        // Without the row, assume hardware: calling a real NIC virtual would keep it
+        // out of batch activations, which is worse than the reverse.
+        let mut hardware = true;
+        if let Some(row) = rows.get(&iface.interface_index) {
+            iface.is_admin_disabled = row.admin_disabled;
+            iface.stats = row.stats;
+            hardware = row.hardware;
         }
+        iface.kind =
+            AdapterKind::classify(iface.if_type, hardware, &iface.name, &iface.description);
     }
     Ok(interfaces)
 }
@@ -155,15 +150,8 @@ pub fn best_interface_index() -> AppResult<u32> {
     Err(AppError::NoActiveInterface)
 }
 
-// IANA ifType values (netioapi.h) for the adapter kinds that must never be auto-selected.
-const IF_TYPE_SOFTWARE_LOOPBACK: u32 = 24;
-const IF_TYPE_TUNNEL: u32 = 131;
-
 fn is_usable(iface: &NetworkInterface) -> bool {
-    iface.is_up
-        && !iface.is_admin_disabled
-        && iface.if_type != IF_TYPE_SOFTWARE_LOOPBACK
-        && iface.if_type != IF_TYPE_TUNNEL
+    iface.is_up && !iface.is_admin_disabled && iface.kind.is_auto_selectable()
 }
 
 fn is_usable_adapter(interfaces: &[NetworkInterface], index: u32) -> bool {
@@ -202,20 +190,24 @@ fn best_interface_for(dest: IpAddr) -> Option<u32> {
     }
 }
 
-/// Maps the frontend's `0` = "Auto" sentinel to the real best-interface index.
-pub fn resolve_interface_index(idx: u32) -> AppResult<u32> {
-    if idx == 0 {
-        best_interface_index()
-    } else {
-        Ok(idx)
-    }
+/// What `GetIfTable2` adds to an adapter.
+struct IfRow {
+    /// `config_manager_error_code == 22` (WMI) doesn't have a direct IP Helper
+    /// equivalent; `AdminStatus == Down` is the closest match — "the user turned this
+    /// adapter off", distinct from `OperStatus == Down` (cable unplugged, no driver,
+    /// etc).
+    admin_disabled: bool,
+    /// NDIS's `HardwareInterface` flag: false for Hyper-V switches, VPN miniports and
+    /// every other adapter that is only software.
+    hardware: bool,
+    stats: InterfaceStats,
 }
 
-/// `InterfaceIndex -> is administratively disabled` via `GetIfTable2`.
-/// `config_manager_error_code == 22` (WMI) doesn't have a direct IP Helper equivalent;
-/// `AdminStatus == Down` is the closest match — "the user turned this adapter off",
-/// distinct from `OperStatus == Down` (cable unplugged, no driver, etc).
-fn read_admin_disabled_map() -> HashMap<u32, bool> {
+// Bit 0 of `InterfaceAndOperStatusFlags` is `HardwareInterface` (netioapi.h).
+const HARDWARE_INTERFACE: u8 = 0x01;
+
+/// `InterfaceIndex -> IfRow` via `GetIfTable2`.
+fn read_if_rows() -> HashMap<u32, IfRow> {
     let mut map = HashMap::new();
     unsafe {
         let mut table_ptr: *mut MIB_IF_TABLE2 = ptr::null_mut();
@@ -231,8 +223,19 @@ fn read_admin_disabled_map() -> HashMap<u32, bool> {
         // member — the OS allocates `count` contiguous rows starting at that address.
         let rows = std::slice::from_raw_parts(table.Table.as_ptr(), count);
         for row in rows {
-            let is_down = row.AdminStatus.0 as i64 == NET_IF_ADMIN_STATUS_DOWN;
-            map.insert(row.InterfaceIndex, is_down);
+            map.insert(
+                row.InterfaceIndex,
+                IfRow {
+                    admin_disabled: row.AdminStatus.0 as i64 == NET_IF_ADMIN_STATUS_DOWN,
+                    hardware: row.InterfaceAndOperStatusFlags._bitfield & HARDWARE_INTERFACE != 0,
+                    stats: InterfaceStats {
+                        bytes_received: row.InOctets,
+                        bytes_sent: row.OutOctets,
+                        packets_received: row.InUcastPkts + row.InNUcastPkts,
+                        packets_sent: row.OutUcastPkts + row.OutNUcastPkts,
+                    },
+                },
+            );
         }
 
         FreeMibTable(table_ptr as *const c_void);
@@ -265,13 +268,7 @@ fn read_adapters() -> AppResult<Vec<NetworkInterface>> {
             let mac_address = if adapter.PhysicalAddressLength > 0 {
                 let len =
                     (adapter.PhysicalAddressLength as usize).min(adapter.PhysicalAddress.len());
-                Some(
-                    adapter.PhysicalAddress[..len]
-                        .iter()
-                        .map(|b| format!("{:02X}", b))
-                        .collect::<Vec<_>>()
-                        .join(":"),
-                )
+                Some(format_mac(&adapter.PhysicalAddress[..len]))
             } else {
                 None
             };
@@ -308,6 +305,22 @@ fn read_adapters() -> AppResult<Vec<NetworkInterface>> {
                 dns = node.Next;
             }
 
+            let dhcp_enabled = adapter.Anonymous2.Flags & IP_ADAPTER_DHCP_ENABLED != 0;
+            let dhcp_server = if dhcp_enabled {
+                sockaddr_to_ip(adapter.Dhcpv4Server.lpSockaddr)
+                    .filter(|ip| !ip.is_unspecified())
+                    .map(|ip| ip.to_string())
+            } else {
+                None
+            };
+
+            // `AdapterName` is the braced interface GUID, the registry key the static
+            // servers would be under; see `dns_snapshot`.
+            let guid = adapter.AdapterName.to_string().unwrap_or_default();
+            let dns_is_static = [Family::V4, Family::V6].into_iter().any(|family| {
+                read_static_servers(&guid, family).is_ok_and(|servers| !servers.is_empty())
+            });
+
             interfaces.push(NetworkInterface {
                 interface_index,
                 ipv6_interface_index: adapter.Ipv6IfIndex,
@@ -315,11 +328,21 @@ fn read_adapters() -> AppResult<Vec<NetworkInterface>> {
                 description,
                 mac_address,
                 if_type: adapter.IfType,
+                kind: AdapterKind::default(), // filled in by list_interfaces()
                 is_up,
                 is_admin_disabled: false, // filled in by list_interfaces()
                 ip_addresses,
                 gateways,
                 dns_servers,
+                dhcp_enabled,
+                dhcp_server,
+                dns_is_static,
+                // Loopback reports an MTU of all ones.
+                mtu: Some(adapter.Mtu).filter(|mtu| *mtu != 0 && *mtu != u32::MAX),
+                // All ones when unknown, zero while disconnected.
+                link_speed: Some(adapter.TransmitLinkSpeed)
+                    .filter(|speed| *speed != 0 && *speed != u64::MAX),
+                stats: InterfaceStats::default(), // filled in by list_interfaces()
             });
 
             current = adapter.Next;
@@ -329,6 +352,32 @@ fn read_adapters() -> AppResult<Vec<NetworkInterface>> {
     Ok(interfaces)
 }
 
+/// The interface's connection-specific DNS suffix — the one DHCP handed out, or the
+/// one set on the adapter — if it has one.
+pub fn dns_suffix(if_index: u32) -> AppResult<Option<String>> {
+    let buffer = fetch_adapters_buffer()?;
+    unsafe {
+        let mut current = buffer.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+        while !current.is_null() {
+            let adapter = &*current;
+            if luid_to_index(&adapter.Luid) == Some(if_index) {
+                return Ok(wide_ptr_to_string(adapter.DnsSuffix.0).filter(|s| !s.is_empty()));
+            }
+            current = adapter.Next;
+        }
+    }
+    Err(AppError::InterfaceNotFound(if_index))
+}
+
+/// `AA:BB:CC:DD:EE:FF`, the form every MAC address the UI sees is in.
+pub(crate) fn format_mac(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
 unsafe fn luid_to_index(luid: &NET_LUID_LH) -> Option<u32> {
     let mut index: u32 = 0;
     let status = ConvertInterfaceLuidToIndex(luid, &mut index);
@@ -152,7 +152,7 @@ unsafe fn read_net_cfg_instance_id(
 }
 
 /// Formats a GUID the way the registry stores `NetCfgInstanceId`: braced, hyphenated.
-fn guid_to_braced_string(guid: &GUID) -> String {
+pub(crate) fn guid_to_braced_string(guid: &GUID) -> String {
     let d4 = guid.data4;
     format!(
         "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
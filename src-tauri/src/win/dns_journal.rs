@@ -0,0 +1,461 @@
+//! An on-disk record of every DNS change this app makes to an adapter, so a crash can
+//! be undone on the next launch.
+//!
+//! `clear_stale_doh_dns` can only recognize the proxy's own loopback addresses. A
+//! plain-DNS activation, or an IPv6 redirect whose IPv4 half was already cleaned up,
+//! leaves nothing it could recognize. The journal covers those: each change is appended
+//! *before* it is applied, with the family's previous and new servers, and marked
+//! complete once it has been reverted. Whatever is still incomplete at startup was
+//! never reverted, and `replay` undoes it. Plain DNS is the exception on a clean exit:
+//! it is meant to stay, so `settle_on_exit` marks it complete.
+//!
+//! Replay only touches a family whose servers are still exactly what we wrote. If
+//! something else has changed them since — the user, a VPN client, group policy — that
+//! change is theirs, and the entry is abandoned rather than undone.
+//!
+//! The file is JSON lines, appended and synced one record at a time, so a crash can at
+//! worst tear the last line. Unparseable lines are skipped. The file is deleted once
+//! nothing in it is pending.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::device::guid_to_braced_string;
+use super::dns_settings::{self, interface_guid, Family};
+use super::dns_snapshot::{self, FamilyDns};
+use super::{adapters, is_proxy_addr};
+use crate::error::{AppError, AppResult};
+
+const JOURNAL_FILE: &str = "dns_journal.jsonl";
+
+/// Serializes access to the file within this process.
+static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+/// One change to one address family of one interface.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    /// Braced. Interface indexes are not stable across reboots; GUIDs are.
+    pub interface_guid: String,
+    /// At the time of the change, for the log only.
+    pub interface_index: u32,
+    pub family: Family,
+    pub previous: FamilyDns,
+    pub applied: FamilyDns,
+    /// RFC 3339.
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Change(JournalEntry),
+    Complete { id: u64, timestamp: String },
+}
+
+fn journal_path() -> PathBuf {
+    crate::platform::state_dir().join(JOURNAL_FILE)
+}
+
+/// Records the change, then applies it. A change that fails to apply is marked
+/// complete straight away, since there is nothing to undo.
+///
+/// Refuses to apply a change it could not record: an unjournaled change is exactly
+/// what a crash would leave behind with no way to find it.
+pub fn set_interface_dns(if_index: u32, family: Family, servers: &[IpAddr]) -> AppResult<()> {
+    let guid = guid_to_braced_string(&interface_guid(if_index)?);
+    let previous = match dns_snapshot::read_static_servers(&guid, family)? {
+        current if current.is_empty() => FamilyDns::Dhcp,
+        current => FamilyDns::Static { servers: current },
+    };
+    let applied = if servers.is_empty() {
+        FamilyDns::Dhcp
+    } else {
+        FamilyDns::Static {
+            servers: servers.to_vec(),
+        }
+    };
+
+    let path = journal_path();
+    let id = {
+        let _guard = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let id = read_records(&path)
+            .iter()
+            .filter_map(|record| match record {
+                Record::Change(entry) => Some(entry.id),
+                Record::Complete { .. } => None,
+            })
+            .max()
+            .unwrap_or(0)
+            + 1;
+        append(
+            &path,
+            &Record::Change(JournalEntry {
+                id,
+                interface_guid: guid,
+                interface_index: if_index,
+                family,
+                previous,
+                applied,
+                timestamp: chrono::Utc::now().to_rfc3339(),
+            }),
+        )?;
+        id
+    };
+
+    let result = dns_settings::set_interface_dns(if_index, family, servers);
+    if result.is_err() {
+        complete_ids(&path, &[id]);
+    }
+    result
+}
+
+/// Marks every pending change on the interface complete. Called once its DNS has been
+/// restored.
+pub fn complete(if_index: u32) {
+    let Ok(guid) = interface_guid(if_index) else {
+        return;
+    };
+    let guid = guid_to_braced_string(&guid);
+    let path = journal_path();
+    let ids: Vec<u64> = pending(&read_records(&path))
+        .into_iter()
+        .filter(|entry| entry.interface_guid == guid)
+        .map(|entry| entry.id)
+        .collect();
+    complete_ids(&path, &ids);
+}
+
+/// Called on a clean exit, after the proxy sweep. Plain DNS is meant to outlive the
+/// app, so those changes are settled here rather than undone by the next launch.
+/// Anything still pointing at a proxy stays pending: the sweep failed to revert it,
+/// and the next launch must.
+pub fn settle_on_exit() {
+    let path = journal_path();
+    let ids: Vec<u64> = pending(&read_records(&path))
+        .into_iter()
+        .filter(|entry| match &entry.applied {
+            FamilyDns::Static { servers } => !servers.iter().any(is_proxy_addr),
+            FamilyDns::Dhcp => true,
+        })
+        .map(|entry| entry.id)
+        .collect();
+    if !ids.is_empty() {
+        debug!("Settling {} plain DNS change(s) on exit", ids.len());
+    }
+    complete_ids(&path, &ids);
+}
+
+/// Undoes every change a previous run made and never reverted. Runs at startup,
+/// before anything else touches an adapter.
+pub fn replay() {
+    let path = journal_path();
+    let pending = pending(&read_records(&path));
+    if pending.is_empty() {
+        debug!("DNS change journal has nothing pending");
+        remove_if_settled(&path);
+        return;
+    }
+    info!(
+        "DNS change journal has {} change(s) a previous run did not revert",
+        pending.len()
+    );
+
+    let interfaces = match adapters::list_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            error!("Cannot replay the DNS change journal: {}", e);
+            return;
+        }
+    };
+    let indexes: Vec<(String, u32)> = interfaces
+        .iter()
+        .filter_map(|i| {
+            interface_guid(i.interface_index)
+                .ok()
+                .map(|guid| (guid_to_braced_string(&guid), i.interface_index))
+        })
+        .collect();
+
+    // The snapshot covers both families, so it stays until neither has anything left
+    // to undo: a failed IPv6 revert still needs it after IPv4 went through.
+    let mut settled = HashSet::new();
+    let mut unsettled = HashSet::new();
+    for undo in plan(&pending) {
+        let Some(&(_, if_index)) = indexes
+            .iter()
+            .find(|(guid, _)| *guid == undo.interface_guid)
+        else {
+            // Kept pending: the adapter may only be unplugged, and it still carries
+            // our change when it comes back.
+            warn!(
+                "Interface {} is not present; its {:?} DNS change stays pending",
+                undo.interface_guid, undo.family
+            );
+            continue;
+        };
+
+        match undo_one(if_index, &undo) {
+            Ok(true) => info!(
+                "Reverted the {:?} DNS change on interface {} to {:?}",
+                undo.family, if_index, undo.restore
+            ),
+            Ok(false) => info!(
+                "The {:?} DNS on interface {} was changed by something else since; leaving it",
+                undo.family, if_index
+            ),
+            Err(e) => {
+                error!(
+                    "Could not revert the {:?} DNS change on interface {}: {}",
+                    undo.family, if_index, e
+                );
+                unsettled.insert(if_index);
+                continue;
+            }
+        }
+        complete_ids(&path, &undo.ids);
+        settled.insert(if_index);
+    }
+    for if_index in settled.difference(&unsettled) {
+        dns_snapshot::remove(*if_index);
+    }
+}
+
+/// Writes `undo.restore` back, but only if the family still holds what we applied.
+fn undo_one(if_index: u32, undo: &Undo) -> AppResult<bool> {
+    let current = dns_snapshot::read_static_servers(&undo.interface_guid, undo.family)?;
+    let ours = match &undo.expected {
+        FamilyDns::Dhcp => current.is_empty(),
+        FamilyDns::Static { servers } => current == *servers,
+    };
+    if !ours {
+        return Ok(false);
+    }
+
+    let servers: &[IpAddr] = match &undo.restore {
+        // Never put a proxy address back, even if that is what the family had: nothing
+        // is listening on it any more.
+        FamilyDns::Static { servers } if !servers.iter().any(is_proxy_addr) => servers,
+        _ => &[],
+    };
+    dns_settings::set_interface_dns(if_index, undo.family, servers)?;
+    Ok(true)
+}
+
+/// What undoing one family of one interface takes.
+#[derive(Debug, PartialEq, Eq)]
+struct Undo {
+    interface_guid: String,
+    family: Family,
+    /// What the family had before the first pending change.
+    restore: FamilyDns,
+    /// What the last pending change left it with.
+    expected: FamilyDns,
+    ids: Vec<u64>,
+}
+
+/// Collapses pending changes per interface and family: a chain of changes is undone by
+/// restoring what came before the first one, if the family still shows the last.
+fn plan(pending: &[JournalEntry]) -> Vec<Undo> {
+    let mut plan: Vec<Undo> = Vec::new();
+    for entry in pending {
+        match plan
+            .iter_mut()
+            .find(|u| u.interface_guid == entry.interface_guid && u.family == entry.family)
+        {
+            Some(undo) => {
+                undo.expected = entry.applied.clone();
+                undo.ids.push(entry.id);
+            }
+            None => plan.push(Undo {
+                interface_guid: entry.interface_guid.clone(),
+                family: entry.family,
+                restore: entry.previous.clone(),
+                expected: entry.applied.clone(),
+                ids: vec![entry.id],
+            }),
+        }
+    }
+    plan
+}
+
+/// Changes with no completion record, oldest first.
+fn pending(records: &[Record]) -> Vec<JournalEntry> {
+    let completed: HashSet<u64> = records
+        .iter()
+        .filter_map(|record| match record {
+            Record::Complete { id, .. } => Some(*id),
+            Record::Change(_) => None,
+        })
+        .collect();
+    let mut pending: Vec<JournalEntry> = records
+        .iter()
+        .filter_map(|record| match record {
+            Record::Change(entry) if !completed.contains(&entry.id) => Some(entry.clone()),
+            _ => None,
+        })
+        .collect();
+    pending.sort_by_key(|entry| entry.id);
+    pending
+}
+
+fn complete_ids(path: &Path, ids: &[u64]) {
+    if ids.is_empty() {
+        return;
+    }
+    let _guard = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    for &id in ids {
+        if let Err(e) = append(
+            path,
+            &Record::Complete {
+                id,
+                timestamp: timestamp.clone(),
+            },
+        ) {
+            error!("Could not mark DNS change {} complete: {}", id, e);
+            return;
+        }
+    }
+    remove_if_settled_locked(path);
+}
+
+fn remove_if_settled(path: &Path) {
+    let _guard = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    remove_if_settled_locked(path);
+}
+
+/// Deletes the journal once nothing in it is pending, so it does not grow forever.
+fn remove_if_settled_locked(path: &Path) {
+    if path.exists() && pending(&read_records(path)).is_empty() {
+        if let Err(e) = std::fs::remove_file(path) {
+            debug!("Could not remove the settled DNS journal: {}", e);
+        }
+    }
+}
+
+fn read_records(path: &Path) -> Vec<Record> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                // Most likely the last line, torn by a crash mid-write.
+                warn!("Skipping an unreadable DNS journal line: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Appends one line and syncs it to disk before returning.
+fn append(path: &Path, record: &Record) -> AppResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| AppError::io(format!("Could not create {}", dir.display()), e))?;
+    }
+    // Start on a fresh line if the last write was torn, or this record would be lost
+    // along with it.
+    let torn = std::fs::read(path)
+        .map(|bytes| bytes.last().is_some_and(|&b| b != b'\n'))
+        .unwrap_or(false);
+    let mut line = if torn {
+        "\n".to_string()
+    } else {
+        String::new()
+    };
+    line.push_str(&serde_json::to_string(record)?);
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| AppError::io(format!("Could not open {}", path.display()), e))?;
+    file.write_all(line.as_bytes())
+        .and_then(|_| file.sync_data())
+        .map_err(|e| AppError::io(format!("Could not write {}", path.display()), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(id: u64, family: Family, previous: FamilyDns, applied: FamilyDns) -> Record {
+        Record::Change(JournalEntry {
+            id,
+            interface_guid: "{GUID}".to_string(),
+            interface_index: 7,
+            family,
+            previous,
+            applied,
+            timestamp: "2026-10-19T00:00:00Z".to_string(),
+        })
+    }
+
+    fn servers(list: &[&str]) -> FamilyDns {
+        FamilyDns::Static {
+            servers: list.iter().map(|s| s.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn a_chain_of_changes_is_undone_to_before_the_first() {
+        let records = vec![
+            change(
+                1,
+                Family::V4,
+                servers(&["10.0.0.53"]),
+                servers(&["127.0.0.2"]),
+            ),
+            change(
+                2,
+                Family::V4,
+                servers(&["127.0.0.2"]),
+                servers(&["1.1.1.1"]),
+            ),
+            change(3, Family::V6, FamilyDns::Dhcp, servers(&["::1"])),
+            Record::Complete {
+                id: 3,
+                timestamp: "2026-10-19T00:00:01Z".to_string(),
+            },
+        ];
+
+        assert_eq!(
+            plan(&pending(&records)),
+            vec![Undo {
+                interface_guid: "{GUID}".to_string(),
+                family: Family::V4,
+                restore: servers(&["10.0.0.53"]),
+                expected: servers(&["1.1.1.1"]),
+                ids: vec![1, 2],
+            }]
+        );
+    }
+
+    #[test]
+    fn torn_lines_are_skipped() {
+        let path =
+            std::env::temp_dir().join(format!("dns-journal-test-{}.jsonl", std::process::id()));
+        append(
+            &path,
+            &change(1, Family::V4, FamilyDns::Dhcp, servers(&["127.0.0.2"])),
+        )
+        .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"op\":\"complete\",\"id\"").unwrap();
+
+        assert_eq!(pending(&read_records(&path)).len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! The per-interface DNS settings that are not name servers: the connection-specific
+//! suffix, the search list, dynamic DNS registration and LLMNR.
+//!
+//! All of them are members of the same `DNS_INTERFACE_SETTINGS` structure the name
+//! servers are set through, so they share its availability: Windows 10 1903 and later,
+//! with the exports resolved at runtime (see `crate::win::dns_settings`). There is no
+//! WMI fallback — `SetDNSDomain` and friends cover only part of this and would leave
+//! the snapshot half-restorable, which is worse than refusing up front.
+
+use std::sync::OnceLock;
+
+use log::debug;
+use windows::core::{GUID, PWSTR};
+use windows::Win32::NetworkManagement::IpHelper::{
+    DNS_INTERFACE_SETTINGS, DNS_INTERFACE_SETTINGS_VERSION1, DNS_SETTINGS_ENABLE_LLMNR,
+    DNS_SETTING_DOMAIN, DNS_SETTING_REGISTER_ADAPTER_NAME, DNS_SETTING_REGISTRATION_ENABLED,
+    DNS_SETTING_SEARCHLIST,
+};
+
+use super::dns_settings::{interface_guid, iphlpapi_export, set_interface_dns_settings};
+use crate::error::{AppError, AppResult};
+use crate::platform::DnsOptions;
+
+/// Signatures of `GetInterfaceDnsSettings` and `FreeInterfaceDnsSettings` as documented
+/// in netioapi.h.
+type GetInterfaceDnsSettingsFn =
+    unsafe extern "system" fn(GUID, *mut DNS_INTERFACE_SETTINGS) -> u32;
+type FreeInterfaceDnsSettingsFn = unsafe extern "system" fn(*mut DNS_INTERFACE_SETTINGS);
+
+/// Resolved once per process. `None` means this Windows build predates 1903.
+static GET_INTERFACE_DNS_SETTINGS: OnceLock<
+    Option<(GetInterfaceDnsSettingsFn, FreeInterfaceDnsSettingsFn)>,
+> = OnceLock::new();
+
+fn get_interface_dns_settings() -> Option<(GetInterfaceDnsSettingsFn, FreeInterfaceDnsSettingsFn)> {
+    *GET_INTERFACE_DNS_SETTINGS.get_or_init(|| {
+        let get = iphlpapi_export(c"GetInterfaceDnsSettings")?;
+        let free = iphlpapi_export(c"FreeInterfaceDnsSettings")?;
+        // The standard GetProcAddress idiom, as in `dns_settings`.
+        unsafe {
+            Some((
+                std::mem::transmute::<
+                    unsafe extern "system" fn() -> isize,
+                    GetInterfaceDnsSettingsFn,
+                >(get),
+                std::mem::transmute::<
+                    unsafe extern "system" fn() -> isize,
+                    FreeInterfaceDnsSettingsFn,
+                >(free),
+            ))
+        }
+    })
+}
+
+fn unsupported() -> AppError {
+    AppError::invalid(
+        "This version of Windows cannot change the DNS suffix, search list or registration \
+         settings from here. Windows 10 version 1903 or later is required.",
+    )
+}
+
+/// Every member this module reads or writes.
+const OPTION_FLAGS: u32 = DNS_SETTING_DOMAIN
+    | DNS_SETTING_SEARCHLIST
+    | DNS_SETTING_REGISTRATION_ENABLED
+    | DNS_SETTING_REGISTER_ADAPTER_NAME
+    | DNS_SETTINGS_ENABLE_LLMNR;
+
+pub fn read(if_index: u32) -> AppResult<DnsOptions> {
+    let (get_fn, free_fn) = get_interface_dns_settings().ok_or_else(unsupported)?;
+    let guid = interface_guid(if_index)?;
+
+    unsafe {
+        let mut settings = DNS_INTERFACE_SETTINGS {
+            Version: DNS_INTERFACE_SETTINGS_VERSION1,
+            ..Default::default()
+        };
+        let status = get_fn(guid, &mut settings);
+        if status != 0 {
+            return Err(AppError::win32("GetInterfaceDnsSettings", status));
+        }
+
+        let options = DnsOptions {
+            suffix: wide_string(settings.Domain),
+            search_list: parse_search_list(&wide_string(settings.SearchList)),
+            register_addresses: settings.RegistrationEnabled != 0,
+            register_with_suffix: settings.RegisterAdapterName != 0,
+            llmnr: settings.EnableLLMNR != 0,
+        };
+        // The strings were allocated by the call and are ours to release.
+        free_fn(&mut settings);
+        Ok(options)
+    }
+}
+
+/// Writes all of the options at once. An empty suffix or search list clears it.
+pub fn write(if_index: u32, options: &DnsOptions) -> AppResult<()> {
+    let set_fn = set_interface_dns_settings().ok_or_else(unsupported)?;
+    let guid = interface_guid(if_index)?;
+
+    // An empty string rather than a null pointer to clear, for the reason given on
+    // `dns_settings::clear_interface_dns_empty_string`.
+    let mut domain = to_wide(&options.suffix);
+    let mut search_list = to_wide(&options.search_list.join(","));
+
+    let settings = DNS_INTERFACE_SETTINGS {
+        Version: DNS_INTERFACE_SETTINGS_VERSION1,
+        Flags: OPTION_FLAGS as u64,
+        Domain: PWSTR(domain.as_mut_ptr()),
+        NameServer: PWSTR::null(),
+        SearchList: PWSTR(search_list.as_mut_ptr()),
+        RegistrationEnabled: options.register_addresses as u32,
+        RegisterAdapterName: options.register_with_suffix as u32,
+        EnableLLMNR: options.llmnr as u32,
+        QueryAdapterName: 0,
+        ProfileNameServer: PWSTR::null(),
+    };
+    let status = unsafe { set_fn(guid, &settings) };
+    if status != 0 {
+        return Err(AppError::win32("SetInterfaceDnsSettings", status));
+    }
+    debug!(
+        "Set the DNS options of interface {}: {:?}",
+        if_index, options
+    );
+
+    // `domain` and `search_list` back the pointers handed to the call above.
+    drop((domain, search_list));
+    Ok(())
+}
+
+fn to_wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+unsafe fn wide_string(text: PWSTR) -> String {
+    if text.is_null() {
+        String::new()
+    } else {
+        text.to_string().unwrap_or_default()
+    }
+}
+
+/// Comma- or space-separated, like `NameServer`.
+fn parse_search_list(value: &str) -> Vec<String> {
+    value
+        .split([',', ' '])
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_search_list_in_order() {
+        assert_eq!(
+            parse_search_list("corp.example.com, example.com lab.example"),
+            vec!["corp.example.com", "example.com", "lab.example"]
+        );
+        assert!(parse_search_list("").is_empty());
+    }
+}
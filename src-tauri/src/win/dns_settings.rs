@@ -29,7 +29,10 @@
 //! address family for reading. Reads go through `GetAdaptersAddresses` instead (see
 //! `crate::win::interface_dns_servers`), which returns both families in one call and
 //! works back to Windows XP.
+//! The members that have no family — suffix, search list, registration, LLMNR — are
+//! read with it, in `crate::win::dns_options`.
 
+use std::ffi::CStr;
 use std::mem::MaybeUninit;
 use std::net::IpAddr;
 use std::sync::OnceLock;
@@ -45,11 +48,7 @@ use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
 
 use crate::error::{AppError, AppResult};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Family {
-    V4,
-    V6,
-}
+pub use crate::platform::Family;
 
 impl Family {
     /// `DNS_SETTING_NAMESERVER` (0x0002) selects the *NameServer* member; adding
@@ -65,30 +64,31 @@ impl Family {
 }
 
 /// Signature of `SetInterfaceDnsSettings` as documented in netioapi.h.
-type SetInterfaceDnsSettingsFn =
+pub(super) type SetInterfaceDnsSettingsFn =
     unsafe extern "system" fn(GUID, *const DNS_INTERFACE_SETTINGS) -> u32;
 
 /// Resolved once per process. `None` means this Windows build predates 1903.
 static SET_INTERFACE_DNS_SETTINGS: OnceLock<Option<SetInterfaceDnsSettingsFn>> = OnceLock::new();
 
-fn resolve_set_interface_dns_settings() -> Option<SetInterfaceDnsSettingsFn> {
+/// An `iphlpapi.dll` export, or `None` if this Windows build does not have it.
+pub(super) fn iphlpapi_export(name: &CStr) -> Option<unsafe extern "system" fn() -> isize> {
     unsafe {
-        let name: Vec<u16> = "iphlpapi.dll\0".encode_utf16().collect();
-        let module = LoadLibraryW(PCWSTR(name.as_ptr())).ok()?;
-        let proc = GetProcAddress(
-            module,
-            PCSTR(c"SetInterfaceDnsSettings".as_ptr() as *const u8),
-        )?;
-        // Transmuting the returned FARPROC to the documented signature is the standard
-        // GetProcAddress idiom; the Win32 model offers no safe alternative.
-        Some(std::mem::transmute::<
-            unsafe extern "system" fn() -> isize,
-            SetInterfaceDnsSettingsFn,
-        >(proc))
+        let dll: Vec<u16> = "iphlpapi.dll\0".encode_utf16().collect();
+        let module = LoadLibraryW(PCWSTR(dll.as_ptr())).ok()?;
+        GetProcAddress(module, PCSTR(name.as_ptr() as *const u8))
     }
 }
 
-fn set_interface_dns_settings() -> Option<SetInterfaceDnsSettingsFn> {
+fn resolve_set_interface_dns_settings() -> Option<SetInterfaceDnsSettingsFn> {
+    let proc = iphlpapi_export(c"SetInterfaceDnsSettings")?;
+    // Transmuting the returned FARPROC to the documented signature is the standard
+    // GetProcAddress idiom; the Win32 model offers no safe alternative.
+    Some(unsafe {
+        std::mem::transmute::<unsafe extern "system" fn() -> isize, SetInterfaceDnsSettingsFn>(proc)
+    })
+}
+
+pub(super) fn set_interface_dns_settings() -> Option<SetInterfaceDnsSettingsFn> {
     *SET_INTERFACE_DNS_SETTINGS.get_or_init(|| {
         let resolved = resolve_set_interface_dns_settings();
         match resolved {
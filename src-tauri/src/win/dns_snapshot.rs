@@ -0,0 +1,276 @@
+//! The DNS an interface had before this app changed it, so deactivating puts back
+//! exactly that instead of always reverting to DHCP.
+//!
+//! `GetAdaptersAddresses` reports the servers in effect but not where they came from:
+//! a static `8.8.8.8` and a DHCP-assigned `8.8.8.8` look the same. The registry does
+//! tell them apart. Static servers live in the `NameServer` value under the interface's
+//! `Tcpip` (IPv4) or `Tcpip6` (IPv6) key; DHCP leaves it empty and writes
+//! `DhcpNameServer` instead. That is the same value `SetInterfaceDnsSettings` writes.
+//!
+//! Snapshots are kept in a JSON file keyed by interface GUID — indexes can be reused
+//! across reboots, GUIDs cannot — so the exit handler and the startup sweep can still
+//! restore after a crash. The file is read and written synchronously: both of those
+//! run without an async runtime.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+
+use super::device::guid_to_braced_string;
+use super::dns_options;
+use super::dns_settings::{interface_guid, Family};
+use super::is_proxy_addr;
+use crate::error::{AppError, AppResult};
+use crate::platform::{state_dir, DnsOptions};
+
+const SNAPSHOT_FILE: &str = "dns_snapshots.json";
+
+/// Where one address family's servers came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FamilyDns {
+    Dhcp,
+    /// In the order they were configured.
+    Static {
+        servers: Vec<IpAddr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsSnapshot {
+    /// Braced, as the registry spells it.
+    pub interface_guid: String,
+    pub v4: FamilyDns,
+    pub v6: FamilyDns,
+    /// The suffix, search list, registration and LLMNR settings. `None` when they could
+    /// not be read — before Windows 10 1903 — and in snapshots saved by older versions.
+    #[serde(default)]
+    pub options: Option<DnsOptions>,
+    /// RFC 3339.
+    pub taken_at: String,
+}
+
+impl DnsSnapshot {
+    pub fn family(&self, family: Family) -> &FamilyDns {
+        match family {
+            Family::V4 => &self.v4,
+            Family::V6 => &self.v6,
+        }
+    }
+}
+
+fn snapshot_path() -> PathBuf {
+    state_dir().join(SNAPSHOT_FILE)
+}
+
+/// Reads how the interface's DNS is configured right now.
+///
+/// Our own loopback addresses are dropped: if they are in the registry, it is a
+/// previous run's change that was never undone, not something worth restoring.
+pub fn read(if_index: u32) -> AppResult<DnsSnapshot> {
+    let guid = guid_to_braced_string(&interface_guid(if_index)?);
+    Ok(DnsSnapshot {
+        v4: read_family(&guid, Family::V4)?,
+        v6: read_family(&guid, Family::V6)?,
+        options: dns_options::read(if_index)
+            .map_err(|e| {
+                debug!(
+                    "Not saving the DNS options of interface {}: {}",
+                    if_index, e
+                )
+            })
+            .ok(),
+        interface_guid: guid,
+        taken_at: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+fn read_family(guid: &str, family: Family) -> AppResult<FamilyDns> {
+    let servers: Vec<IpAddr> = read_static_servers(guid, family)?
+        .into_iter()
+        .filter(|ip| !is_proxy_addr(ip))
+        .collect();
+
+    Ok(if servers.is_empty() {
+        FamilyDns::Dhcp
+    } else {
+        FamilyDns::Static { servers }
+    })
+}
+
+/// The family's static servers exactly as the registry has them, proxy addresses
+/// included. Empty means DHCP.
+pub(crate) fn read_static_servers(guid: &str, family: Family) -> AppResult<Vec<IpAddr>> {
+    let service = match family {
+        Family::V4 => "Tcpip",
+        Family::V6 => "Tcpip6",
+    };
+    let key = format!(
+        "SYSTEM\\CurrentControlSet\\Services\\{}\\Parameters\\Interfaces\\{}",
+        service, guid
+    );
+    Ok(parse_name_server(&read_registry_string(
+        &key,
+        "NameServer",
+    )?))
+}
+
+/// `NameServer` is comma- or space-separated, in priority order.
+fn parse_name_server(value: &str) -> Vec<IpAddr> {
+    value
+        .split([',', ' '])
+        .filter_map(|s| s.trim().parse().ok())
+        .collect()
+}
+
+/// A missing key or value reads as empty: an interface that has never had static DNS
+/// on a family may not have either.
+fn read_registry_string(key: &str, value: &str) -> AppResult<String> {
+    let key: Vec<u16> = key.encode_utf16().chain(std::iter::once(0)).collect();
+    let value: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut buffer = [0u16; 1024];
+    let mut size = (buffer.len() * 2) as u32;
+
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(key.as_ptr()),
+            PCWSTR(value.as_ptr()),
+            RRF_RT_REG_SZ,
+            None,
+            Some(buffer.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+    };
+    if status == ERROR_FILE_NOT_FOUND {
+        return Ok(String::new());
+    }
+    if status.is_err() {
+        return Err(AppError::win32("RegGetValueW", status.0));
+    }
+
+    // `size` is in bytes and includes the terminating NUL.
+    let len = (size as usize / 2).saturating_sub(1).min(buffer.len());
+    Ok(String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// Takes and saves the interface's snapshot, unless one is already saved. Switching
+/// from one server to another must not overwrite the original with our own change.
+pub fn ensure(if_index: u32) -> AppResult<DnsSnapshot> {
+    let path = snapshot_path();
+    let guid = guid_to_braced_string(&interface_guid(if_index)?);
+    let mut snapshots = load_all(&path);
+    if let Some(existing) = snapshots.get(&guid) {
+        return Ok(existing.clone());
+    }
+
+    let snapshot = read(if_index)?;
+    debug!(
+        "Saved the original DNS of interface {}: {:?}",
+        if_index, snapshot
+    );
+    snapshots.insert(guid, snapshot.clone());
+    save_all(&path, &snapshots)?;
+    Ok(snapshot)
+}
+
+/// The saved snapshot for the interface, if there is one.
+pub fn load(if_index: u32) -> Option<DnsSnapshot> {
+    let guid = guid_to_braced_string(&interface_guid(if_index).ok()?);
+    load_all(&snapshot_path()).remove(&guid)
+}
+
+/// Forgets the interface's snapshot once it has been restored.
+pub fn remove(if_index: u32) {
+    let Ok(guid) = interface_guid(if_index) else {
+        return;
+    };
+    let path = snapshot_path();
+    let mut snapshots = load_all(&path);
+    if snapshots.remove(&guid_to_braced_string(&guid)).is_some() {
+        if let Err(e) = save_all(&path, &snapshots) {
+            warn!(
+                "Could not drop the DNS snapshot of interface {}: {}",
+                if_index, e
+            );
+        }
+    }
+}
+
+/// A missing or unreadable file is an empty map: there is nothing to restore, and a
+/// corrupt file must not block activation.
+fn load_all(path: &Path) -> BTreeMap<String, DnsSnapshot> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring unreadable DNS snapshots in {}: {}",
+                path.display(),
+                e
+            );
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Written to a temporary file and renamed over the old one, so a crash mid-write
+/// leaves the previous snapshots intact.
+fn save_all(path: &Path, snapshots: &BTreeMap<String, DnsSnapshot>) -> AppResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| AppError::io(format!("Could not create {}", dir.display()), e))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(snapshots)?)
+        .map_err(|e| AppError::io(format!("Could not write {}", tmp.display()), e))?;
+    std::fs::rename(&tmp, path)
+        .map_err(|e| AppError::io(format!("Could not replace {}", path.display()), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_server_in_order() {
+        assert_eq!(
+            parse_name_server("10.0.0.53,10.0.0.54 2001:db8::53"),
+            vec![
+                "10.0.0.53".parse::<IpAddr>().unwrap(),
+                "10.0.0.54".parse().unwrap(),
+                "2001:db8::53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_name_server("").is_empty());
+    }
+
+    #[test]
+    fn snapshots_survive_a_round_trip_through_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("dns-snapshot-test-{}.json", std::process::id()));
+        let snapshot = DnsSnapshot {
+            interface_guid: "{1234ABCD-5678-9ABC-DEF0-112233445566}".to_string(),
+            v4: FamilyDns::Static {
+                servers: vec!["10.0.0.53".parse().unwrap()],
+            },
+            v6: FamilyDns::Dhcp,
+            options: Some(DnsOptions {
+                suffix: "corp.example.com".to_string(),
+                llmnr: true,
+                ..DnsOptions::default()
+            }),
+            taken_at: "2026-10-19T00:00:00Z".to_string(),
+        };
+        let snapshots = BTreeMap::from([(snapshot.interface_guid.clone(), snapshot)]);
+
+        save_all(&path, &snapshots).unwrap();
+        assert_eq!(load_all(&path), snapshots);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
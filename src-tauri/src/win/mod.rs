@@ -3,38 +3,28 @@
 
 pub mod adapters;
 pub mod device;
+pub mod dns_journal;
 pub mod dns_legacy;
+pub mod dns_options;
 pub mod dns_settings;
+pub mod dns_snapshot;
+pub mod network_id;
 pub mod notify;
 
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::IpAddr;
 
-use log::{debug, error, info, warn};
+use log::{debug, error, info};
 
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+use crate::platform::restore::{self, AdapterDns, Snapshot, SnapshotStore};
+use crate::platform::{is_proxy_addr, DnsOptions, Family, NetworkInterface};
 
-/// Loopback addresses the local DoH/DoT/DoQ/DoH3 proxy binds to.
-pub const PROXY_V4: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
-pub const PROXY_V6: Ipv6Addr = Ipv6Addr::LOCALHOST; // ::1
-
-/// True if this address is one of the proxy's own loopback addresses.
-pub fn is_proxy_addr(ip: &IpAddr) -> bool {
-    *ip == IpAddr::V4(PROXY_V4) || *ip == IpAddr::V6(PROXY_V6)
-}
-
-/// Windows' default site-local IPv6 DNS anycast addresses.
-///
-/// These are present on most systems even when the user has never configured IPv6 DNS,
-/// so they must not be counted as "real" configured servers — otherwise every
-/// activation would think IPv6 DNS needs redirecting, and the UI would list them as
-/// though the user had set them.
-pub fn is_default_ipv6_anycast(ip: &IpAddr) -> bool {
-    const DEFAULTS: [Ipv6Addr; 3] = [
-        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 1),
-        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 2),
-        Ipv6Addr::new(0xfec0, 0, 0, 0xffff, 0, 0, 0, 3),
-    ];
-    matches!(ip, IpAddr::V6(v6) if DEFAULTS.contains(v6))
+// `DnsFlushResolverCache` is an undocumented dnsapi.dll export — it isn't part of the
+// official Win32 metadata windows-rs generates from. This is the sole remaining
+// hand-written `extern` block in the codebase.
+#[link(name = "dnsapi")]
+extern "system" {
+    fn DnsFlushResolverCache() -> i32;
 }
 
 /// Reads the DNS servers currently configured on one interface, both address families.
@@ -46,7 +36,7 @@ pub fn interface_dns_servers(if_index: u32) -> AppResult<Vec<IpAddr>> {
     let iface = adapters::list_interfaces()?
         .into_iter()
         .find(|i| i.interface_index == if_index)
-        .ok_or(crate::error::AppError::InterfaceNotFound(if_index))?;
+        .ok_or(AppError::InterfaceNotFound(if_index))?;
 
     Ok(iface
         .dns_servers
@@ -55,157 +45,81 @@ pub fn interface_dns_servers(if_index: u32) -> AppResult<Vec<IpAddr>> {
         .collect())
 }
 
-/// True if the interface has real (user- or DHCP-configured, non-anycast-default) IPv6
-/// DNS servers that would bypass an IPv4-only proxy.
-pub fn has_real_ipv6_dns(if_index: u32) -> bool {
-    match interface_dns_servers(if_index) {
-        Ok(servers) => servers
-            .iter()
-            .any(|ip| ip.is_ipv6() && !is_default_ipv6_anycast(ip) && !is_proxy_addr(ip)),
-        Err(e) => {
-            error!(
-                "Failed to read DNS servers on interface {}: {}",
-                if_index, e
-            );
-            false
-        }
-    }
+/// Puts the interface's DNS back the way it was before this app changed it: the
+/// snapshot `set_dns` saved when there is one (see `dns_snapshot`), DHCP otherwise.
+/// The snapshot is forgotten once it has been restored, and kept if it could not be,
+/// so the next attempt still knows what to put back.
+///
+/// Blocking, for the same reason as [`restore_dhcp_dns_blocking`].
+pub fn restore_dns_blocking(if_index: u32) -> bool {
+    restore::restore_snapshot(&IpHelper, &Snapshots, if_index)
 }
 
-/// True if the interface still points at the proxy loopback on either family.
-pub fn interface_uses_proxy_dns(if_index: u32) -> bool {
-    match interface_dns_servers(if_index) {
-        Ok(servers) => servers.iter().any(is_proxy_addr),
-        Err(e) => {
-            error!(
-                "Failed to verify DNS state on interface {}: {}",
-                if_index, e
-            );
-            false
-        }
-    }
+/// Reverts one interface to its DHCP-provided DNS servers, escalating from IP Helper
+/// to WMI until it takes. See `platform::restore::restore_dhcp`.
+pub fn restore_dhcp_dns_blocking(if_index: u32) -> bool {
+    restore::restore_dhcp(&IpHelper, if_index)
 }
 
-/// Reverts one interface to its DHCP-provided DNS servers, escalating until it takes.
-///
-/// Returns whether the previously configured servers are actually gone.
-///
-/// Blocking on purpose: the exit handler has no async runtime, and leaving `127.0.0.2`
-/// applied there is precisely the failure that strands a user with no working internet.
-/// The async command wraps this in `spawn_blocking` so both paths share one
-/// implementation — they diverged once before, and only the command got fixed.
-///
-/// Three mechanisms are tried in order, because they do not fail alike:
-///
-/// 1. `SetInterfaceDnsSettings` with a null `NameServer` — the documented spelling.
-/// 2. The same call with an empty string. A null pointer appears to be ignored on some
-///    systems even with `DNS_SETTING_NAMESERVER` set, so the call reports success while
-///    the old servers stay exactly where they were.
-/// 3. WMI's `SetDNSServerSearchOrder`, which this app used before the IP Helper
-///    migration. IPv4-only, which is the family that matters — `127.0.0.2` is what
-///    breaks name resolution when it is left behind.
-pub fn restore_dhcp_dns_blocking(if_index: u32) -> bool {
-    // Whatever is configured right now is what has to disappear. Snapshotting it works
-    // for plain DNS too, where nothing points at the proxy and checking only for
-    // 127.0.0.2 would report success without having changed anything at all.
-    let applied: Vec<IpAddr> = match interface_dns_servers(if_index) {
-        Ok(servers) => servers
-            .into_iter()
-            .filter(|ip| !is_default_ipv6_anycast(ip))
-            .collect(),
-        Err(e) => {
-            error!("Cannot restore interface {}: {}", if_index, e);
-            return false;
-        }
-    };
+/// The calls the shared restore escalation is built from.
+struct IpHelper;
 
-    if applied.is_empty() {
-        debug!(
-            "Interface {} has no configured DNS servers; nothing to restore",
-            if_index
-        );
-        return true;
+impl AdapterDns for IpHelper {
+    fn interface_dns_servers(&self, if_index: u32) -> AppResult<Vec<IpAddr>> {
+        interface_dns_servers(if_index)
     }
-    debug!(
-        "Restoring interface {}, currently set to {:?}",
-        if_index, applied
-    );
 
-    for family in [dns_settings::Family::V4, dns_settings::Family::V6] {
-        if let Err(e) = dns_settings::set_interface_dns(if_index, family, &[]) {
-            debug!(
-                "Null-pointer clear failed for {:?} on interface {}: {}",
-                family, if_index, e
-            );
-        }
+    fn set_interface_dns(
+        &self,
+        if_index: u32,
+        family: Family,
+        servers: &[IpAddr],
+    ) -> AppResult<()> {
+        dns_settings::set_interface_dns(if_index, family, servers)
     }
-    if dns_restored(if_index, &applied) {
-        return true;
+
+    fn clear_interface_dns_empty_string(&self, if_index: u32, family: Family) -> AppResult<()> {
+        dns_settings::clear_interface_dns_empty_string(if_index, family)
     }
 
-    warn!(
-        "Interface {} unchanged after the null clear — retrying with an empty string",
-        if_index
-    );
-    for family in [dns_settings::Family::V4, dns_settings::Family::V6] {
-        if let Err(e) = dns_settings::clear_interface_dns_empty_string(if_index, family) {
-            debug!(
-                "Empty-string clear failed for {:?} on interface {}: {}",
-                family, if_index, e
-            );
-        }
+    fn clear_interface_dns_legacy(&self, if_index: u32) -> AppResult<()> {
+        dns_legacy::set_interface_dns_wmi(if_index, Family::V4, &[])
+    }
+
+    fn supports_ipv6_dns(&self) -> bool {
+        dns_settings::supports_ipv6_dns()
     }
-    if dns_restored(if_index, &applied) {
-        return true;
+
+    fn interface_dns_options(&self, if_index: u32) -> AppResult<DnsOptions> {
+        dns_options::read(if_index)
     }
 
-    warn!(
-        "Interface {} unchanged after both IP Helper forms — falling back to WMI",
-        if_index
-    );
-    if let Err(e) = dns_legacy::set_interface_dns_wmi(if_index, dns_settings::Family::V4, &[]) {
-        error!("WMI fallback failed on interface {}: {}", if_index, e);
+    fn set_interface_dns_options(&self, if_index: u32, options: &DnsOptions) -> AppResult<()> {
+        dns_options::write(if_index, options)
     }
-    dns_restored(if_index, &applied)
 }
 
-/// True once none of `applied` are still configured on the interface.
-///
-/// Polls rather than reading once: `GetAdaptersAddresses` does not always reflect a
-/// `SetInterfaceDnsSettings` write immediately, so a single read taken straight
-/// afterwards can report the old servers and make a good restore look like a failure.
-/// Kept short — this runs during application exit.
-fn dns_restored(if_index: u32, applied: &[IpAddr]) -> bool {
-    const ATTEMPTS: usize = 4;
-    const DELAY: std::time::Duration = std::time::Duration::from_millis(150);
+/// The snapshots in `dns_snapshot`, for the shared restore.
+struct Snapshots;
+
+impl SnapshotStore for Snapshots {
+    fn load(&self, if_index: u32) -> Option<Snapshot> {
+        let servers = |family: &dns_snapshot::FamilyDns| match family {
+            dns_snapshot::FamilyDns::Static { servers } => servers.clone(),
+            dns_snapshot::FamilyDns::Dhcp => Vec::new(),
+        };
+        dns_snapshot::load(if_index).map(|snapshot| Snapshot {
+            v4: servers(&snapshot.v4),
+            v6: servers(&snapshot.v6),
+            options: snapshot.options,
+        })
+    }
 
-    for attempt in 1..=ATTEMPTS {
-        match interface_dns_servers(if_index) {
-            Ok(current) => {
-                let leftover: Vec<&IpAddr> =
-                    applied.iter().filter(|ip| current.contains(ip)).collect();
-                if leftover.is_empty() {
-                    debug!(
-                        "Interface {} restored to {:?} after {} read(s)",
-                        if_index, current, attempt
-                    );
-                    return true;
-                }
-                debug!(
-                    "Interface {} still shows {:?} on read {}/{}",
-                    if_index, leftover, attempt, ATTEMPTS
-                );
-            }
-            Err(e) => debug!(
-                "Could not read DNS servers on interface {}: {}",
-                if_index, e
-            ),
-        }
-        if attempt < ATTEMPTS {
-            std::thread::sleep(DELAY);
-        }
+    /// The interface's journal entries are settled along with its snapshot.
+    fn forget(&self, if_index: u32) {
+        dns_journal::complete(if_index);
+        dns_snapshot::remove(if_index);
     }
-    false
 }
 
 /// Scans every interface for a stale proxy DNS entry — left over from a previous run
@@ -222,7 +136,7 @@ pub fn clear_stale_doh_dns() {
         }
     };
 
-    let stale: Vec<&adapters::NetworkInterface> = interfaces
+    let stale: Vec<&NetworkInterface> = interfaces
         .iter()
         .filter(|i| {
             i.dns_servers
@@ -242,9 +156,9 @@ pub fn clear_stale_doh_dns() {
             "Clearing stale proxy DNS on interface {} ({})",
             iface.interface_index, iface.name
         );
-        if restore_dhcp_dns_blocking(iface.interface_index) {
+        if restore_dns_blocking(iface.interface_index) {
             info!(
-                "Interface {} ({}) restored to DHCP",
+                "Interface {} ({}) restored to its original DNS",
                 iface.interface_index, iface.name
             );
         } else {
@@ -255,3 +169,18 @@ pub fn clear_stale_doh_dns() {
         }
     }
 }
+
+/// Empties the DNS client's cache, as `ipconfig /flushdns` does.
+pub fn flush_resolver_cache() -> AppResult<()> {
+    // Undocumented export: returns non-zero on success, and does not set last-error in
+    // a documented way, so there is no code worth reporting back.
+    let result = unsafe { DnsFlushResolverCache() };
+    debug!("DnsFlushResolverCache returned {}", result);
+    match result {
+        1 => Ok(()),
+        _ => Err(AppError::Internal(
+            "Windows refused to flush the DNS resolver cache. Try running the app as administrator."
+                .to_string(),
+        )),
+    }
+}
@@ -0,0 +1,131 @@
+//! Recognising the network an adapter is connected to.
+//!
+//! The gateway and DNS suffix come from `GetAdaptersAddresses`, like everything else in
+//! `adapters`. The gateway's MAC address comes from the neighbor table
+//! (`GetIpNetEntry2`), and the SSID from the WLAN service (`WlanQueryInterface`), which
+//! only answers for wireless adapters and is not running at all on machines without
+//! one.
+
+use std::ffi::c_void;
+use std::net::IpAddr;
+use std::ptr;
+
+use log::debug;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::NetworkManagement::IpHelper::{
+    GetIpNetEntry2, ResolveIpNetEntry2, MIB_IPNET_ROW2,
+};
+use windows::Win32::NetworkManagement::WiFi::{
+    wlan_intf_opcode_current_connection, WlanCloseHandle, WlanFreeMemory, WlanOpenHandle,
+    WlanQueryInterface, WLAN_CONNECTION_ATTRIBUTES,
+};
+use windows::Win32::Networking::WinSock::{AF_INET, AF_INET6};
+
+use super::adapters::{self, format_mac};
+use super::dns_settings::interface_guid;
+use crate::error::{AppError, AppResult};
+use crate::platform::NetworkFingerprint;
+
+/// WLAN API version 2, the one every Windows since Vista speaks.
+const WLAN_API_VERSION_2: u32 = 2;
+
+pub fn network_fingerprint(if_index: u32) -> AppResult<NetworkFingerprint> {
+    let interface = adapters::list_interfaces()?
+        .into_iter()
+        .find(|i| i.interface_index == if_index)
+        .ok_or(AppError::InterfaceNotFound(if_index))?;
+    let gateways: Vec<IpAddr> = interface
+        .gateways
+        .iter()
+        .filter_map(|gateway| gateway.parse().ok())
+        .collect();
+    // IPv4 first: an IPv6 gateway is usually a link-local address like fe80::1, which
+    // half the routers in the world share.
+    let gateway_ip = gateways
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or(gateways.first())
+        .copied();
+
+    Ok(NetworkFingerprint {
+        gateway_ip,
+        gateway_mac: gateway_ip.and_then(|ip| neighbor_mac(if_index, ip)),
+        dns_suffix: adapters::dns_suffix(if_index)?,
+        ssid: ssid(if_index),
+    })
+}
+
+/// The link-layer address of `ip` on the interface, from the neighbor cache, or by
+/// asking the network when it is not cached. `None` while resolution is incomplete.
+fn neighbor_mac(if_index: u32, ip: IpAddr) -> Option<String> {
+    let mut row = MIB_IPNET_ROW2 {
+        InterfaceIndex: if_index,
+        ..Default::default()
+    };
+    unsafe {
+        match ip {
+            IpAddr::V4(v4) => {
+                row.Address.Ipv4.sin_family = AF_INET;
+                row.Address.Ipv4.sin_addr.S_un.S_addr = u32::from_ne_bytes(v4.octets());
+            }
+            IpAddr::V6(v6) => {
+                row.Address.Ipv6.sin6_family = AF_INET6;
+                row.Address.Ipv6.sin6_addr.u.Byte = v6.octets();
+            }
+        }
+
+        // The gateway carries all the interface's traffic, so it is nearly always
+        // cached. Resolving blocks for as long as the network takes to answer.
+        if GetIpNetEntry2(&mut row).0 != 0 {
+            let status = ResolveIpNetEntry2(&mut row, None);
+            if status.0 != 0 {
+                debug!(
+                    "Could not resolve the MAC address of gateway {}: {}",
+                    ip, status.0
+                );
+                return None;
+            }
+        }
+    }
+
+    let len = (row.PhysicalAddressLength as usize).min(row.PhysicalAddress.len());
+    let mac = &row.PhysicalAddress[..len];
+    mac.iter().any(|&b| b != 0).then(|| format_mac(mac))
+}
+
+/// The SSID the adapter is associated with. `None` for a wired adapter, a wireless one
+/// that is not connected, or when the WLAN service is not running.
+fn ssid(if_index: u32) -> Option<String> {
+    let guid = interface_guid(if_index).ok()?;
+    unsafe {
+        let mut negotiated = 0;
+        let mut handle = HANDLE::default();
+        if WlanOpenHandle(WLAN_API_VERSION_2, None, &mut negotiated, &mut handle) != 0 {
+            return None;
+        }
+
+        let mut size = 0;
+        let mut data: *mut c_void = ptr::null_mut();
+        let status = WlanQueryInterface(
+            handle,
+            &guid,
+            wlan_intf_opcode_current_connection,
+            None,
+            &mut size,
+            &mut data,
+            None,
+        );
+        let ssid = (status == 0 && !data.is_null()).then(|| {
+            let attributes = &*(data as *const WLAN_CONNECTION_ATTRIBUTES);
+            let ssid = &attributes.wlanAssociationAttributes.dot11Ssid;
+            let len = (ssid.uSSIDLength as usize).min(ssid.ucSSID.len());
+            String::from_utf8_lossy(&ssid.ucSSID[..len]).into_owned()
+        });
+
+        if !data.is_null() {
+            WlanFreeMemory(data);
+        }
+        WlanCloseHandle(handle, None);
+        ssid.filter(|ssid| !ssid.is_empty())
+    }
+}
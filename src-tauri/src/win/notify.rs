@@ -1,129 +1,329 @@
-//! Push notification of network changes, via `NotifyIpInterfaceChange`.
+//! Push notification of network changes, via `NotifyIpInterfaceChange`,
+//! `NotifyUnicastIpAddressChange` and `NotifyRouteChange2`, plus a registry watch for
+//! DNS.
 //!
 //! Replaces polling as the *trigger* for refreshing the UI. The frontend previously
 //! refetched interfaces and DNS state on a fixed 5-10s timer, so plugging in a cable or
 //! switching Wi-Fi took up to ten seconds to show up, and the app kept doing syscalls
 //! forever while sitting idle in the tray.
 //!
-//! Windows delivers these callbacks on its own worker thread, so the callback does the
-//! minimum possible: emit a Tauri event and return. All the real work happens in the
-//! frontend's event handler.
+//! Windows delivers these callbacks on its own worker thread, so the callbacks do the
+//! minimum possible: turn the row into a [`NetworkChange`], hand it to the registered
+//! [`ChangeCallback`] and return. Coalescing and the real work happen elsewhere.
+//!
+//! There is no notification API for DNS servers. They live under the Tcpip and Tcpip6
+//! `Parameters\Interfaces` keys, so a thread waits on `RegNotifyChangeKeyValue` for
+//! those and compares the servers in effect before and after; the keys also change on
+//! every DHCP renewal, which mostly changes nothing that matters here.
 //!
 //! Available since Windows Vista, so this needs no runtime capability check — unlike
 //! `SetInterfaceDnsSettings`, see `dns_settings.rs`.
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
 use log::{debug, info, warn};
-use tauri::{AppHandle, Emitter};
-use windows::Win32::Foundation::HANDLE;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0, WIN32_ERROR};
 use windows::Win32::NetworkManagement::IpHelper::{
-    CancelMibChangeNotify2, NotifyIpInterfaceChange, MIB_IPINTERFACE_ROW, MIB_NOTIFICATION_TYPE,
+    CancelMibChangeNotify2, MibAddInstance, MibDeleteInstance, NotifyIpInterfaceChange,
+    NotifyRouteChange2, NotifyUnicastIpAddressChange, MIB_IPFORWARD_ROW2, MIB_IPINTERFACE_ROW,
+    MIB_NOTIFICATION_TYPE, MIB_UNICASTIPADDRESS_ROW,
+};
+use windows::Win32::Networking::WinSock::{ADDRESS_FAMILY, AF_INET, AF_INET6, AF_UNSPEC};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegNotifyChangeKeyValue, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE, KEY_NOTIFY,
+    REG_NOTIFY_CHANGE_LAST_SET,
 };
-use windows::Win32::Networking::WinSock::AF_UNSPEC;
+use windows::Win32::System::Threading::{CreateEventW, WaitForMultipleObjects};
 
-/// Event name the frontend listens on. Any change to the interface table — link up or
-/// down, address change, adapter added or removed — produces one.
-pub const NETWORK_CHANGED_EVENT: &str = "network-changed";
+use crate::platform::{
+    guard_callback, ChangeCallback, ChangeKind, ChangeSource, DnsDiff, Family, NetworkChange,
+};
 
-/// Handle kept alive for the lifetime of the process so the registration is not dropped.
-/// `CancelMibChangeNotify2` must be called before the callback can safely be freed.
-static NOTIFY_HANDLE: Mutex<Option<isize>> = Mutex::new(None);
+/// Handles kept alive for the lifetime of the process so the registrations are not
+/// dropped. `CancelMibChangeNotify2` must be called on each before the callback can
+/// safely be freed.
+static NOTIFY_HANDLES: Mutex<Vec<isize>> = Mutex::new(Vec::new());
 
-/// The app handle the callback emits through.
+/// What the callbacks call.
 ///
-/// A raw `static` is unavoidable: `NotifyIpInterfaceChange` takes a bare C function
+/// A raw `static` is unavoidable: the `Notify*Change` functions take a bare C function
 /// pointer plus a `*mut c_void` caller context, and Windows will invoke it from a thread
-/// we do not own. Storing the handle here rather than leaking it through the context
+/// we do not own. Storing the callback here rather than leaking it through the context
 /// pointer keeps the unsafe surface to this one module.
-static APP_HANDLE: Mutex<Option<AppHandle>> = Mutex::new(None);
+static ON_CHANGE: Mutex<Option<ChangeCallback>> = Mutex::new(None);
+
+/// Set while the DNS thread runs; cleared to stop it.
+static WATCHING_DNS: AtomicBool = AtomicBool::new(false);
+
+/// Where each family's per-interface DNS settings are kept.
+const DNS_KEYS: [PCWSTR; 2] = [
+    w!("SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces"),
+    w!("SYSTEM\\CurrentControlSet\\Services\\Tcpip6\\Parameters\\Interfaces"),
+];
+
+/// Hands the change to the registered callback, if any.
+fn report(change: NetworkChange) {
+    debug!("Network change: {:?}", change);
+
+    // Called outside the lock, so a callback that panics cannot poison it and silence
+    // every notification after. `lock()` can then only fail if `register` panicked.
+    let on_change = ON_CHANGE.lock().ok().and_then(|guard| guard.clone());
+    if let Some(on_change) = on_change {
+        on_change(change);
+    }
+}
+
+fn change(
+    interface_index: u32,
+    family: ADDRESS_FAMILY,
+    notification_type: MIB_NOTIFICATION_TYPE,
+    source: ChangeSource,
+) -> NetworkChange {
+    NetworkChange {
+        interface_index,
+        family: match family {
+            AF_INET => Some(Family::V4),
+            AF_INET6 => Some(Family::V6),
+            _ => None,
+        },
+        kind: match notification_type {
+            MibAddInstance => ChangeKind::Added,
+            MibDeleteInstance => ChangeKind::Deleted,
+            // `MibInitialNotification` is never asked for.
+            _ => ChangeKind::Parameter,
+        },
+        source,
+    }
+}
+
+// The three callbacks below are called by Windows on its own worker thread. They must
+// stay cheap and must not block: the OS serializes these callbacks, and a slow one
+// delays every subsequent network notification process-wide. The row is only valid for
+// the duration of the call.
 
-/// Called by Windows on its own worker thread whenever the IP interface table changes.
-///
-/// Must stay cheap and must not block: the OS serializes these callbacks, and a slow one
-/// delays every subsequent network notification process-wide.
 unsafe extern "system" fn on_interface_change(
     _caller_context: *const std::ffi::c_void,
-    _row: *const MIB_IPINTERFACE_ROW,
+    row: *const MIB_IPINTERFACE_ROW,
     notification_type: MIB_NOTIFICATION_TYPE,
 ) {
-    debug!(
-        "Network interface change notification: type {}",
-        notification_type.0
-    );
-
-    // `lock()` can only fail if a previous holder panicked; there is nothing useful to
-    // do about that inside an OS callback, so the notification is simply dropped.
-    let Ok(guard) = APP_HANDLE.lock() else {
-        return;
-    };
-    if let Some(app) = guard.as_ref() {
-        if let Err(e) = app.emit(NETWORK_CHANGED_EVENT, ()) {
-            warn!("Could not emit {}: {}", NETWORK_CHANGED_EVENT, e);
-        }
-    }
+    guard_callback("interface", || {
+        let (index, family) = row
+            .as_ref()
+            .map_or((0, AF_UNSPEC), |row| (row.InterfaceIndex, row.Family));
+        report(change(
+            index,
+            family,
+            notification_type,
+            ChangeSource::Interface,
+        ));
+    });
+}
+
+unsafe extern "system" fn on_address_change(
+    _caller_context: *const std::ffi::c_void,
+    row: *const MIB_UNICASTIPADDRESS_ROW,
+    notification_type: MIB_NOTIFICATION_TYPE,
+) {
+    guard_callback("address", || {
+        let (index, family) = row.as_ref().map_or((0, AF_UNSPEC), |row| {
+            (row.InterfaceIndex, row.Address.si_family)
+        });
+        report(change(
+            index,
+            family,
+            notification_type,
+            ChangeSource::Address,
+        ));
+    });
 }
 
-/// Registers for interface-change notifications. Idempotent.
+unsafe extern "system" fn on_route_change(
+    _caller_context: *const std::ffi::c_void,
+    row: *const MIB_IPFORWARD_ROW2,
+    notification_type: MIB_NOTIFICATION_TYPE,
+) {
+    guard_callback("route", || {
+        let (index, family) = row.as_ref().map_or((0, AF_UNSPEC), |row| {
+            (row.InterfaceIndex, row.DestinationPrefix.Prefix.si_family)
+        });
+        report(change(
+            index,
+            family,
+            notification_type,
+            ChangeSource::Route,
+        ));
+    });
+}
+
+/// Registers for interface, address, route and DNS change notifications. Idempotent.
 ///
 /// Failure is not fatal: the frontend keeps a slow poll as a safety net, so the app
-/// degrades to its previous behavior rather than going blind to network changes.
-pub fn register(app: AppHandle) {
+/// degrades to its previous behavior rather than going blind to network changes. Each
+/// subscription stands on its own, so one failing leaves the others working.
+pub fn register(on_change: ChangeCallback) {
     {
-        let Ok(mut guard) = APP_HANDLE.lock() else {
+        let Ok(mut guard) = ON_CHANGE.lock() else {
             warn!("Network notification state is poisoned; not registering");
             return;
         };
-        *guard = Some(app);
+        *guard = Some(on_change);
     }
 
-    let Ok(mut handle_guard) = NOTIFY_HANDLE.lock() else {
+    let Ok(mut handles) = NOTIFY_HANDLES.lock() else {
         return;
     };
-    if handle_guard.is_some() {
+    if !handles.is_empty() {
         return;
     }
 
+    // `initial_notification = false` throughout: the frontend already loads current
+    // state on mount, and an immediate callback would just duplicate that work.
     let mut handle = HANDLE::default();
     let status = unsafe {
         NotifyIpInterfaceChange(
             AF_UNSPEC,
             Some(on_interface_change),
             None,
-            // `initial_notification = false`: the frontend already loads current state
-            // on mount, and an immediate callback would just duplicate that work.
             false,
             &mut handle,
         )
     };
+    keep(&mut handles, "interface", status, handle);
+
+    let mut handle = HANDLE::default();
+    let status = unsafe {
+        NotifyUnicastIpAddressChange(AF_UNSPEC, Some(on_address_change), None, false, &mut handle)
+    };
+    keep(&mut handles, "address", status, handle);
+
+    let mut handle = HANDLE::default();
+    let status = unsafe {
+        NotifyRouteChange2(
+            AF_UNSPEC,
+            Some(on_route_change),
+            std::ptr::null(),
+            false,
+            &mut handle,
+        )
+    };
+    keep(&mut handles, "route", status, handle);
+    drop(handles);
+
+    if !WATCHING_DNS.swap(true, Ordering::SeqCst) {
+        if let Err(e) = std::thread::Builder::new()
+            .name("dns-watch".to_string())
+            .spawn(watch_dns)
+        {
+            WATCHING_DNS.store(false, Ordering::SeqCst);
+            warn!("Could not start the DNS change thread: {}", e);
+        }
+    }
+}
 
+fn keep(handles: &mut Vec<isize>, what: &str, status: WIN32_ERROR, handle: HANDLE) {
     if status.is_ok() {
-        *handle_guard = Some(handle.0 as isize);
-        info!("Subscribed to network interface change notifications");
+        handles.push(handle.0 as isize);
+        info!("Subscribed to network {} change notifications", what);
     } else {
         warn!(
-            "Could not subscribe to interface change notifications ({}); the UI will \
-             fall back to polling",
-            status.0
+            "Could not subscribe to {} change notifications ({}); the UI will fall back \
+             to polling",
+            what, status.0
         );
     }
 }
 
-/// Cancels the subscription. Called before shutdown so Windows is not left holding a
+/// Waits on the Tcpip and Tcpip6 interface keys and reports a [`ChangeSource::Dns`]
+/// change for every interface whose servers differ afterwards.
+fn watch_dns() {
+    let mut keys = Vec::new();
+    let mut events = Vec::new();
+    for path in DNS_KEYS {
+        let mut key = HKEY::default();
+        let opened = unsafe { RegOpenKeyExW(HKEY_LOCAL_MACHINE, path, None, KEY_NOTIFY, &mut key) };
+        if opened.is_err() {
+            debug!("Could not open a DNS key to watch: {}", opened.0);
+            continue;
+        }
+        match unsafe { CreateEventW(None, false, false, PCWSTR::null()) } {
+            Ok(event) if arm(key, event) => {
+                keys.push(key);
+                events.push(event);
+            }
+            _ => unsafe {
+                let _ = RegCloseKey(key);
+            },
+        }
+    }
+
+    if events.is_empty() {
+        warn!("Could not watch for DNS changes");
+    } else {
+        let mut diff = DnsDiff::new(&super::adapters::list_interfaces().unwrap_or_default());
+        while WATCHING_DNS.load(Ordering::SeqCst) {
+            // A one-second timeout so the thread notices it has been asked to stop.
+            let signaled = unsafe { WaitForMultipleObjects(&events, false, 1000) };
+            // Past the end on a timeout.
+            let which = signaled.0.wrapping_sub(WAIT_OBJECT_0.0) as usize;
+            if which >= events.len() {
+                continue;
+            }
+            // A notification fires once; ask for the next before reading, so a change
+            // made while reading is not missed.
+            arm(keys[which], events[which]);
+            guard_callback("DNS", || match super::adapters::list_interfaces() {
+                Ok(interfaces) => {
+                    for change in diff.changes(&interfaces) {
+                        report(change);
+                    }
+                }
+                Err(e) => debug!("Could not re-read DNS after a registry change: {}", e),
+            });
+        }
+    }
+
+    unsafe {
+        for key in keys {
+            let _ = RegCloseKey(key);
+        }
+        for event in events {
+            let _ = CloseHandle(event);
+        }
+    }
+    WATCHING_DNS.store(false, Ordering::SeqCst);
+    debug!("Stopped watching for DNS changes");
+}
+
+/// Asks for `event` to be signaled on the next value written anywhere under `key`.
+fn arm(key: HKEY, event: HANDLE) -> bool {
+    let status = unsafe {
+        RegNotifyChangeKeyValue(key, true, REG_NOTIFY_CHANGE_LAST_SET, Some(event), true)
+    };
+    if status.is_err() {
+        debug!("RegNotifyChangeKeyValue failed: {}", status.0);
+    }
+    status.is_ok()
+}
+
+/// Cancels the subscriptions. Called before shutdown so Windows is not left holding a
 /// callback into a process that is tearing down.
 pub fn unregister() {
-    let Ok(mut handle_guard) = NOTIFY_HANDLE.lock() else {
+    WATCHING_DNS.store(false, Ordering::SeqCst);
+
+    let Ok(mut handles) = NOTIFY_HANDLES.lock() else {
         return;
     };
-    if let Some(raw) = handle_guard.take() {
+    for raw in handles.drain(..) {
         let status = unsafe { CancelMibChangeNotify2(HANDLE(raw as *mut std::ffi::c_void)) };
         if status.is_err() {
             warn!("CancelMibChangeNotify2 failed: {}", status.0);
         } else {
-            debug!("Unsubscribed from network interface change notifications");
+            debug!("Unsubscribed from network change notifications");
         }
     }
-    if let Ok(mut guard) = APP_HANDLE.lock() {
+    if let Ok(mut guard) = ON_CHANGE.lock() {
         *guard = None;
     }
 }
@@ -20,6 +20,16 @@ const ServerCard = ({ server, testResult, onEdit, onRemove }: ServerCardProps) =
                 <span className="text-xs text-yellow-400">Testing...</span>
             );
         }
+        if (testResult.success && testResult.profile_confirmed === false) {
+            return (
+                <span
+                    className="text-xs text-yellow-400"
+                    title={testResult.error ?? undefined}
+                >
+                    {testResult.latency}ms · profile not applied
+                </span>
+            );
+        }
         if (testResult.success) {
             return (
                 <span className="text-xs text-green-400">{testResult.latency}ms</span>
@@ -2,6 +2,7 @@ import { MutationOptions, useMutation, useQuery } from "@tanstack/react-query";
 import { invoke } from "@tauri-apps/api/core";
 import { loadTestDomain } from "../stores/tauriSettingStore";
 import { DEFAULT_SETTING } from "../data/defaultSetting";
+import type { ActiveProfile, ProxyStatus } from "../types";
 
 export type BootstrapResolverInfo = {
     server: string;
@@ -63,6 +64,27 @@ export const useGetInterfaceDnsInfo = (interface_idx: number | null) => {
     });
 };
 
+export const useProxyStatus = (interfaceIndex?: number | null) => {
+    return useQuery({
+        queryKey: ["proxy_status", interfaceIndex ?? null],
+        queryFn: () =>
+            invoke<ProxyStatus>("get_proxy_status", {
+                interface_index: interfaceIndex ?? null,
+            }),
+        // Start/stop/error arrive by push (see useProxyEvents); the poll keeps uptime
+        // and the counters moving.
+        refetchInterval: 5000,
+    });
+};
+
+export const useActiveProfiles = () => {
+    return useQuery({
+        queryKey: ["active_profiles"],
+        queryFn: () => invoke<ActiveProfile[]>("get_active_profiles"),
+        refetchInterval: 5000,
+    });
+};
+
 export const useClearDnsCache = (
     params?: MutationOptions<void, Error, void>
 ) => {
@@ -107,6 +129,7 @@ export type ServerTestResult = {
     success: boolean;
     latency: number;
     error: string | null;
+    profile_confirmed: boolean | null;
 };
 
 export type InterfaceDnsInfo = {
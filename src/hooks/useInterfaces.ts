@@ -38,9 +38,22 @@ type Interface = {
     description: string;
     mac_address: string | null;
     if_type: number;
+    kind: "physical" | "wireless" | "vpn" | "virtual" | "tunnel" | "loopback";
     is_up: boolean;
     is_admin_disabled: boolean;
     ip_addresses: string[];
     gateways: string[];
     dns_servers: string[];
+    dhcp_enabled: boolean;
+    dhcp_server: string | null;
+    dns_is_static: boolean;
+    mtu: number | null;
+    /** Bits per second. */
+    link_speed: number | null;
+    stats: {
+        bytes_received: number;
+        bytes_sent: number;
+        packets_received: number;
+        packets_sent: number;
+    };
 };
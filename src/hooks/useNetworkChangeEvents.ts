@@ -3,11 +3,25 @@ import { listen } from "@tauri-apps/api/event";
 import { useQueryClient } from "@tanstack/react-query";
 
 /**
- * Event emitted by the Rust side from `NotifyIpInterfaceChange` whenever the IP
- * interface table changes — link up/down, address change, adapter added or removed.
+ * Event emitted by the Rust side whenever an interface, one of its addresses or routes,
+ * or its DNS servers change. Changes reported within a quarter of a second arrive
+ * together, each distinct change once.
  */
 const NETWORK_CHANGED_EVENT = "network-changed";
 
+export interface NetworkChange {
+    /** 0 when the change is not tied to one interface. */
+    interface_index: number;
+    /** `null` when the change covers both families. */
+    family: "v4" | "v6" | null;
+    kind: "added" | "deleted" | "parameter";
+    source: "interface" | "address" | "route" | "dns";
+}
+
+export interface NetworkChanges {
+    changes: NetworkChange[];
+}
+
 /** Queries whose data becomes stale the moment the network changes. */
 const NETWORK_DEPENDENT_KEYS = [
     ["interfaces"],
@@ -15,13 +29,16 @@ const NETWORK_DEPENDENT_KEYS = [
     ["interface_info"],
 ];
 
+/** What a DNS-only change can make stale: the routes and adapters are as they were. */
+const DNS_DEPENDENT_KEYS = [["interfaces"], ["interface_info"]];
+
 /**
  * Refreshes network state as soon as Windows reports a change, instead of waiting for
  * the next poll tick.
  *
  * The queries keep a slow poll as a safety net — a missed or unregistered notification
- * would otherwise leave the UI stale indefinitely, and `NotifyIpInterfaceChange`
- * registration is explicitly allowed to fail on the Rust side.
+ * would otherwise leave the UI stale indefinitely, and registering for notifications
+ * is explicitly allowed to fail on the Rust side.
  *
  * Mount once, near the root.
  */
@@ -34,8 +51,10 @@ export const useNetworkChangeEvents = () => {
         let unlisten: (() => void) | undefined;
         let cancelled = false;
 
-        listen(NETWORK_CHANGED_EVENT, () => {
-            for (const queryKey of NETWORK_DEPENDENT_KEYS) {
+        listen<NetworkChanges>(NETWORK_CHANGED_EVENT, ({ payload }) => {
+            const dnsOnly = payload.changes.every((change) => change.source === "dns");
+            const stale = dnsOnly ? DNS_DEPENDENT_KEYS : NETWORK_DEPENDENT_KEYS;
+            for (const queryKey of stale) {
                 queryClient.invalidateQueries({ queryKey });
             }
         }).then((fn) => {
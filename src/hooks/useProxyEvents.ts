@@ -0,0 +1,66 @@
+import { useEffect } from "react";
+import { listen } from "@tauri-apps/api/event";
+import { useQueryClient } from "@tanstack/react-query";
+import { addToast } from "@heroui/toast";
+
+/** Proxy lifecycle events emitted from `src-tauri/src/dns/dns_events.rs`. */
+const PROXY_STARTED_EVENT = "proxy-started";
+const PROXY_STOPPED_EVENT = "proxy-stopped";
+const PROXY_ERROR_EVENT = "proxy-error";
+
+type ProxyErrorPayload = {
+    interface_index: number | null;
+    upstream: string | null;
+    error: string;
+    /** Set when the upstream's key matched none of its pins. */
+    pin_mismatch: { server: string; presented: string[] } | null;
+};
+
+/**
+ * Keeps `get_proxy_status` current by refetching whenever the backend reports that
+ * the proxy started, stopped or failed, and surfaces failures as a toast.
+ *
+ * Mount once, near the root.
+ */
+export const useProxyEvents = () => {
+    const queryClient = useQueryClient();
+
+    useEffect(() => {
+        // Same late-registration guard as useNetworkChangeEvents.
+        const unlisteners: (() => void)[] = [];
+        let cancelled = false;
+
+        const refresh = () => {
+            queryClient.invalidateQueries({ queryKey: ["proxy_status"] });
+            queryClient.invalidateQueries({ queryKey: ["active_profiles"] });
+        };
+
+        const register = (promise: Promise<() => void>) =>
+            promise.then((fn) => {
+                if (cancelled) {
+                    fn();
+                } else {
+                    unlisteners.push(fn);
+                }
+            });
+
+        register(listen(PROXY_STARTED_EVENT, refresh));
+        register(listen(PROXY_STOPPED_EVENT, refresh));
+        register(
+            listen<ProxyErrorPayload>(PROXY_ERROR_EVENT, (event) => {
+                refresh();
+                addToast({
+                    title: "DNS proxy error",
+                    description: event.payload.error,
+                    color: "danger",
+                    timeout: 8000,
+                });
+            }),
+        );
+
+        return () => {
+            cancelled = true;
+            unlisteners.forEach((fn) => fn());
+        };
+    }, [queryClient]);
+};
@@ -3,10 +3,13 @@ import Titlebar from "../components/Titlebar";
 import Navigation from "../components/Navigation";
 import Updater from "../components/Updater";
 import { useNetworkChangeEvents } from "../hooks/useNetworkChangeEvents";
+import { useProxyEvents } from "../hooks/useProxyEvents";
 
 const DefaultLayout = () => {
     // Refresh network state the moment Windows reports a change, rather than on a timer.
     useNetworkChangeEvents();
+    // Proxy start/stop/failure, so the toggle follows the backend.
+    useProxyEvents();
 
     return (
         <div className="flex flex-col h-full">
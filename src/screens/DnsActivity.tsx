@@ -146,6 +146,12 @@ const DnsActivity = () => {
                 return "danger";
             case "blocked":
                 return "warning";
+            case "rebind":
+                return "secondary";
+            case "local":
+                return "primary";
+            case "fallback":
+                return "danger";
             default:
                 return "default";
         }
@@ -239,6 +245,23 @@ const DnsActivity = () => {
                                                 >
                                                     {log.status}
                                                 </Chip>
+                                                {log.status !== "rebind" &&
+                                                    log.rebind_hits.length >
+                                                        0 && (
+                                                        <Chip
+                                                            size="sm"
+                                                            variant="flat"
+                                                            color={statusColor(
+                                                                "rebind",
+                                                            )}
+                                                            className="text-xs"
+                                                            title={log.rebind_hits.join(
+                                                                ", ",
+                                                            )}
+                                                        >
+                                                            rebind
+                                                        </Chip>
+                                                    )}
                                                 <Button
                                                     size="sm"
                                                     variant="flat"
@@ -64,6 +64,7 @@ const Servers = () => {
                         success: false,
                         latency: 0,
                         error: errorMessage(error, "Test failed"),
+                        profile_confirmed: null,
                     });
                     return newMap;
                 });
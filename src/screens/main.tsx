@@ -1,4 +1,4 @@
-import { useState, useEffect, useMemo } from "react";
+import { useState, useEffect, useMemo, useRef } from "react";
 import ToggleButton from "../components/ToggleButton";
 import { Select, SelectItem } from "@heroui/select";
 import { Tooltip } from "@heroui/tooltip";
@@ -10,6 +10,7 @@ import {
     useClearDns,
     useClearDnsCache,
     useTestServer,
+    useProxyStatus,
     type ServerTestResult,
 } from "../hooks/useDns";
 import { DNSServer } from "../components/icons/DNSServer";
@@ -20,7 +21,7 @@ import { Reset } from "../components/icons/Reset";
 import { Texture } from "../components/icons/Texture";
 import { Tab, Tabs } from "@heroui/tabs";
 import { Test } from "../components/icons/Test";
-import { PROTOCOLS, PROXY_V4, PROXY_V6, SERVER } from "../types";
+import { PROTOCOLS, SERVER, isProxyAddress } from "../types";
 import { errorMessage } from "../utils/errorMessage";
 import { useServerStore } from "../stores/useServersStore";
 import { useDnsState } from "../hooks/useDnsState";
@@ -78,15 +79,13 @@ const Main = () => {
 
     // Is the local proxy currently written onto the adapter?
     //
-    // Deliberately narrow: it asks only about 127.0.0.2 / ::1, which are unambiguously
+    // Deliberately narrow: it asks only about the proxy loopbacks, which are unambiguously
     // ours. It is NOT a general "are we active" check — plain DNS writes the server's
     // own addresses onto the adapter, so a broader check would have to guess, and
     // guessing wrong is what made the toggle fight the user.
     const isProxyApplied = useMemo(
         () =>
-            (interfaceDnsInfo?.dns_servers ?? []).some(
-                (server) => server === PROXY_V4 || server === PROXY_V6,
-            ),
+            (interfaceDnsInfo?.dns_servers ?? []).some(isProxyAddress),
         [interfaceDnsInfo],
     );
 
@@ -156,6 +155,23 @@ const Main = () => {
         isFetchingInterfaceDnsInfo,
     ]);
 
+    // The backend's own view of this interface's proxy. Unlike the adapter check above it can say
+    // "stopped" with certainty, but only for the proxy — so it turns the switch off
+    // only on the transition from running to stopped, never merely because the proxy
+    // is absent (plain DNS never starts it).
+    const { data: proxyStatus } = useProxyStatus(IfIdx);
+    const wasProxyRunning = useRef(false);
+    useEffect(() => {
+        if (!proxyStatus || isSettingDns || isClearingDns) return;
+        if (proxyStatus.running && !isActive) {
+            setIsActive(true);
+        } else if (!proxyStatus.running && wasProxyRunning.current) {
+            setIsActive(false);
+        }
+        wasProxyRunning.current = proxyStatus.running;
+        // eslint-disable-next-line react-hooks/exhaustive-deps
+    }, [proxyStatus?.running, isSettingDns, isClearingDns]);
+
     const { mutate: testServer, isPending } = useTestServer({
         onSuccess: (data, variables) => {
             // Find the server key from the server string
@@ -186,6 +202,7 @@ const Main = () => {
                         success: false,
                         latency: 0,
                         error: errorMessage(error, "Test failed"),
+                        profile_confirmed: null,
                     });
                     return newMap;
                 });
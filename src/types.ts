@@ -59,7 +59,9 @@ export type DnsQueryLog = {
     record_type: string;
     response_records: string[];
     latency_ms: number;
-    status: "success" | "error" | "blocked";
+    status: "success" | "error" | "blocked" | "rebind" | "local" | "fallback";
+    /** Addresses rebinding protection removed, whatever the status. */
+    rebind_hits: string[];
 };
 
 export type DnsRule = {
@@ -70,6 +72,38 @@ export type DnsRule = {
     record_type: string;
 };
 
+/** Mirrors the Rust `ProxyStatus` returned by `get_proxy_status`. */
+export type ProxyStatus = {
+    running: boolean;
+    interface_index: number | null;
+    listen_v4: string | null;
+    upstream: string | null;
+    upstream_addr: string | null;
+    sockets: {
+        udp_v4: boolean;
+        tcp_v4: boolean;
+        udp_v6: boolean;
+        tcp_v6: boolean;
+    };
+    ipv6_redirect: boolean;
+    ipv6_unprotected: boolean;
+    started_at: string | null;
+    uptime_secs: number;
+    counts: {
+        queries: number;
+        errors: number;
+        blocked: number;
+    };
+};
+
+/** Mirrors the Rust `ActiveProfileInfo` returned by `get_active_profiles`. */
+export type ActiveProfile = {
+    interface_index: number;
+    dns_type: string;
+    servers: string[];
+    proxy: ProxyStatus | null;
+};
+
 export type LogLevel = "ERROR" | "WARN" | "INFO" | "DEBUG" | "TRACE";
 
 export const LOG_LEVELS: LogLevel[] = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
@@ -84,9 +118,19 @@ export type AppLogEntry = {
 };
 
 /**
- * Loopback addresses the bundled DNS proxy binds to. Must stay in sync with
- * `PROXY_V4` / `PROXY_V6` in `src-tauri/src/win/mod.rs` — the UI decides whether the
- * proxy is engaged by looking for these in the adapter's DNS server list.
+ * Loopback addresses the bundled DNS proxies bind to. Must stay in sync with
+ * `PROXY_V4` / `PROXY_V6` / `PROXY_SLOTS` in `src-tauri/src/win/mod.rs` — the UI
+ * decides whether a proxy is engaged by looking for these in the adapter's DNS server
+ * list. Each interface's proxy gets its own IPv4 address, counting up from `PROXY_V4`.
  */
 export const PROXY_V4 = "127.0.0.2";
 export const PROXY_V6 = "::1";
+export const PROXY_SLOTS = 16;
+
+export const isProxyAddress = (server: string) => {
+    if (server === PROXY_V6) return true;
+    const match = /^127\.0\.0\.(\d+)$/.exec(server);
+    if (!match) return false;
+    const slot = Number(match[1]) - 2;
+    return slot >= 0 && slot < PROXY_SLOTS;
+};